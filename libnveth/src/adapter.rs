@@ -1,52 +1,103 @@
-use alloc::vec::Vec;
+use alloc::{sync::Arc, vec::Vec};
 
 use core::{
+    default::default,
     mem::{self, MaybeUninit},
     ops::{Deref, DerefMut},
-    ptr,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicBool, AtomicU32, Ordering::Relaxed},
+    time::Duration,
 };
 
 use libnveth_macros::*;
 
+use shared::{
+    forward::FloodPolicy,
+    ioctl::VEthPrefix,
+    mac_addr::{self, MAC_ADDR_SIZE},
+    mtu,
+    routing::Mode,
+    wire,
+};
+
 use crate::{
-    crypto::aes_gcm::AesGcm,
-    list::BufPool,
+    cookie::CookieChecker,
+    crypto::{
+        self,
+        aead::{self, CipherSuite},
+        hash::HashAlgorithm,
+    },
+    events::Events,
+    fdb::Fdb,
+    handshake::{LocalKey, KEY_SIZE},
     net::MacAddr,
-    peer::Peer,
+    os::{
+        registry::DeviceKey,
+        sync::{Mutex, RwLock},
+    },
+    peer::{Peer, PeerList, Peers},
+    proxy::Replies,
     recv::{self, VEthRxQueue},
+    routing::Routes,
     send::{self, VEthTxQueue},
     socket::{IoRequest, UdpSocket},
+    stats::AdapterStats,
     windows::{
-        km::ntifs::RtlRandomEx,
+        km::wdm::{MmBuildMdlForNonPagedPool, MmInitializeMdl, MmSizeOfMdl, MDL, PAGE_SIZE},
         prelude as win,
         shared::ifdef::{NET_IF_MEDIA_CONNECT_STATE, NET_IF_MEDIA_DUPLEX_STATE},
     },
 };
 
+// Under the device's PnP key
+const MAC_ADDR_VALUE: [u16; 11] = utf16!(b"MacAddress\0");
+const MTU_VALUE: [u16; 4] = utf16!(b"Mtu\0");
+
 pub struct VEthAdapter {
     init: bool, // zero-init
 
     pub adapter_handle: win::NETADAPTER, // pre-init
+    device: win::WDFDEVICE,
+
+    local_mac_addr: MacAddr,
+    mtu: u32,
+
+    connected: bool, // zero-init
 
-    pub local_mac_addr: MacAddr,
+    // The raw `FloodPolicy` and `Mode`
+    flood: AtomicU32,
+    mode: AtomicU32,
+    relay: AtomicBool,
 
-    peers: Vec<Peer>, // TODO
+    local_key: RwLock<Option<Arc<LocalKey>>>,
+
+    peers: Peers,
+    fdb: Fdb,
+    routes: Routes,
+    replies: Replies,
+
+    cookie_checker: CookieChecker,
+
+    stats: AdapterStats,
+
+    events: Events, // pre-init
 
     pub tx_request: IoRequest,
     pub rx_request: IoRequest,
 
-    pub socket: UdpSocket,
+    pub socket: UdpSocket, // zero-init, valid while `Datapath::bound`
     request: IoRequest,
 
-    pub rx_buf_pool: BufPool<VEthCipherFrame>,
+    datapath: Mutex<Datapath>,
 }
 
 impl VEthAdapter {
-    pub fn pre_init(adapter: win::NETADAPTER) -> *mut Self {
+    pub fn pre_init(adapter: win::NETADAPTER, events_queue: win::WDFQUEUE) -> *mut Self {
         unsafe {
             let uninit = Self::from_adapter_mut_ptr(adapter);
 
             ptr::raw_mut!((*uninit).adapter_handle).write(adapter);
+            Events::pre_init(ptr::raw_mut!((*uninit).events), events_queue);
 
             uninit
         }
@@ -56,45 +107,56 @@ impl VEthAdapter {
         unsafe {
             let uninit = Self::from_device_mut_ptr(device);
 
+            ptr::raw_mut!((*uninit).device).write(device);
+
             let tx_request = IoRequest::init(ptr::raw_mut!((*uninit).tx_request))?;
             let rx_request = IoRequest::init(ptr::raw_mut!((*uninit).rx_request))?;
             let request = IoRequest::init(ptr::raw_mut!((*uninit).request))?;
 
-            let mut socket_init = UdpSocket::new(&mut (*uninit).tx_request)?;
-            let (socket, context) = socket_init.get();
-            socket.set_option(context, win::IPV6_V6ONLY, win::IPPROTO::IPPROTO_IPV6, false)?;
-
-            let local_addr = win::SOCKADDR_IN6 {
-                family: win::AF_INET6,
-                port: 5001u16.to_be(),
-                addr: [0; 16],
-                ..core::default::default()
+            // Until nvnet sets them, which are then persisted
+            let key = DeviceKey::open(device)?;
+            let mut local_mac_addr = [0; MAC_ADDR_SIZE];
+            if key
+                .query_value(&MAC_ADDR_VALUE, win::REG_BINARY, &mut local_mac_addr)
+                .is_err()
+                || !mac_addr::is_assignable(&local_mac_addr)
+            {
+                crypto::gen_random(&mut local_mac_addr)?;
+                local_mac_addr = mac_addr::local_unicast(local_mac_addr);
+            }
+            ptr::raw_mut!((*uninit).local_mac_addr).write(local_mac_addr.into());
+
+            let mut mtu = [0; 4];
+            let mtu = match key.query_value(&MTU_VALUE, win::REG_DWORD, &mut mtu) {
+                Ok(()) if mtu::is_valid(u32::from_ne_bytes(mtu)) => u32::from_ne_bytes(mtu),
+                _ => mtu::DEFAULT_MTU,
             };
-            socket.bind(context, &local_addr)?; // TODO remove
+            ptr::raw_mut!((*uninit).mtu).write(mtu);
 
-            // const TX_POOL_TAG: u32 = u32::from_ne_bytes([b'N', b'V', b'E', b'T']);
-            const RX_POOL_TAG: u32 = u32::from_ne_bytes([b'N', b'V', b'E', b'R']);
+            ptr::raw_mut!((*uninit).flood).write(AtomicU32::new(FloodPolicy::default().as_raw()));
+            ptr::raw_mut!((*uninit).mode).write(AtomicU32::new(Mode::Layer2.as_raw()));
+            ptr::raw_mut!((*uninit).relay).write(AtomicBool::new(false));
 
-            let rx_buf_pool = BufPool::init(ptr::raw_mut!((*uninit).rx_buf_pool), RX_POOL_TAG)?;
+            ptr::raw_mut!((*uninit).local_key).write(RwLock::new(None));
 
-            let local_mac_addr = {
-                let mut seed = 42u32;
-                let random = RtlRandomEx(&mut seed);
-                let random = random.to_ne_bytes();
-                MacAddr::new(0x02, 0x00, random[0], random[1], random[2], random[3])
-            };
-            ptr::raw_mut!((*uninit).local_mac_addr).write(local_mac_addr);
+            ptr::raw_mut!((*uninit).peers).write(Peers::default());
+            Fdb::init(ptr::raw_mut!((*uninit).fdb))?;
+            Routes::init(ptr::raw_mut!((*uninit).routes))?;
+            Replies::init(ptr::raw_mut!((*uninit).replies));
+
+            CookieChecker::init(ptr::raw_mut!((*uninit).cookie_checker))?;
+
+            ptr::raw_mut!((*uninit).stats).write(core::default::default());
+
+            Events::init(ptr::raw_mut!((*uninit).events));
 
-            ptr::raw_mut!((*uninit).peers).write(Vec::new());
+            Mutex::init(ptr::raw_mut!((*uninit).datapath), Datapath::default());
 
             mem::forget(tx_request);
             mem::forget(rx_request);
 
-            ptr::raw_mut!((*uninit).socket).write(socket_init.take());
             mem::forget(request);
 
-            mem::forget(rx_buf_pool);
-
             let init = &mut *uninit;
             init.init = true;
             Ok(init)
@@ -117,7 +179,94 @@ impl VEthAdapter {
         VEthAdapterPtr::from_device_mut(device).0
     }
 
+    // Reports the capabilities and the addresses, which NetAdapterCx only accepts while the adapter
+    // is stopped, and starts it.
+    pub fn start(&mut self) -> Result<(), win::NTSTATUS> {
+        let adapter_handle = self.adapter_handle;
+        let link_layer_capabilities =
+            win::NET_ADAPTER_LINK_LAYER_CAPABILITIES_INIT(crate::LINK_SPEED, crate::LINK_SPEED);
+        unsafe {
+            win::NetAdapterSetLinkLayerCapabilities(adapter_handle, &link_layer_capabilities)
+        };
+        unsafe { win::NetAdapterSetLinkLayerMtuSize(adapter_handle, self.mtu) };
+        let packet_filter = win::NET_ADAPTER_PACKET_FILTER_CAPABILITIES_INIT(
+            win::NET_PACKET_FILTER_FLAGS::NetPacketFilterFlagDirected
+                | win::NET_PACKET_FILTER_FLAGS::NetPacketFilterFlagMulticast
+                | win::NET_PACKET_FILTER_FLAGS::NetPacketFilterFlagAllMulticast
+                | win::NET_PACKET_FILTER_FLAGS::NetPacketFilterFlagBroadcast
+                | win::NET_PACKET_FILTER_FLAGS::NetPacketFilterFlagPromiscuous,
+            Some(evt_set_packet_filter),
+        );
+        unsafe { win::NetAdapterSetPacketFilterCapabilities(adapter_handle, &packet_filter) };
+        let mut link_layer_address = win::NET_ADAPTER_LINK_LAYER_ADDRESS {
+            length: MAC_ADDR_SIZE as _,
+            ..default()
+        };
+        link_layer_address.address[..MAC_ADDR_SIZE].copy_from_slice(self.local_mac_addr.bytes());
+        unsafe { win::NetAdapterSetPermanentLinkLayerAddress(adapter_handle, &link_layer_address) };
+        unsafe { win::NetAdapterSetCurrentLinkLayerAddress(adapter_handle, &link_layer_address) };
+        let tx_capabilities = win::NET_ADAPTER_TX_CAPABILITIES_INIT(1);
+        // Datagrams are received in place: the frame follows the message header in the fragment.
+        let rx_capabilities =
+            win::NET_ADAPTER_RX_CAPABILITIES_INIT_SYSTEM_MANAGED(mtu::datagram_size(self.mtu), 1);
+        unsafe {
+            win::NetAdapterSetDataPathCapabilities(
+                adapter_handle,
+                &tx_capabilities,
+                &rx_capabilities,
+            )
+        };
+        self.set_connect_state(self.connected);
+        let status = unsafe { win::NetAdapterStart(adapter_handle) };
+        if !win::NT_SUCCESS(status) {
+            trace_exit_status!("NetAdapterStart", status);
+            return Err(status);
+        }
+        Ok(())
+    }
+
+    // The stack sees the interface go away and come back, and the queues are recreated. Only at
+    // PASSIVE_LEVEL, and never with `datapath` locked: the queue cancel callbacks take it.
+    fn restart(&mut self) -> Result<(), win::NTSTATUS> {
+        unsafe { win::NetAdapterStop(self.adapter_handle) };
+        self.start()
+    }
+
+    // Restarts the adapter to apply the address, and persists it once the adapter runs with it.
+    pub fn set_mac_addr(&mut self, mac_addr: [u8; MAC_ADDR_SIZE]) -> Result<(), win::NTSTATUS> {
+        if !mac_addr::is_assignable(&mac_addr) {
+            return Err(win::STATUS_INVALID_PARAMETER);
+        }
+        if *self.local_mac_addr.bytes() != mac_addr {
+            let previous = mem::replace(&mut self.local_mac_addr, mac_addr.into());
+            if let Err(status) = self.restart() {
+                self.local_mac_addr = previous;
+                return Err(status);
+            }
+        }
+        let key = DeviceKey::open(self.device)?;
+        key.set_value(&MAC_ADDR_VALUE, win::REG_BINARY, &mac_addr)
+    }
+
+    // Restarts the adapter to resize the receive buffers along with the MTU, and persists it once
+    // the adapter runs with it.
+    pub fn set_mtu(&mut self, mtu: u32) -> Result<(), win::NTSTATUS> {
+        if !mtu::is_valid(mtu) {
+            return Err(win::STATUS_INVALID_PARAMETER);
+        }
+        if self.mtu != mtu {
+            let previous = mem::replace(&mut self.mtu, mtu);
+            if let Err(status) = self.restart() {
+                self.mtu = previous;
+                return Err(status);
+            }
+        }
+        let key = DeviceKey::open(self.device)?;
+        key.set_value(&MTU_VALUE, win::REG_DWORD, &mtu.to_ne_bytes())
+    }
+
     pub fn set_connect_state(&mut self, connected: bool) {
+        self.connected = connected;
         let link_state = win::NET_ADAPTER_LINK_STATE_INIT(
             crate::LINK_SPEED,
             if connected {
@@ -132,32 +281,187 @@ impl VEthAdapter {
         unsafe { win::NetAdapterSetLinkState(self.adapter_handle, &link_state) };
     }
 
+    // Replaces the socket with one bound to `local_addr` while the workers are paused. The previous
+    // binding is restored on failure, and if even that fails, the workers stay paused until a later
+    // call succeeds.
     pub fn set_local_addr(&mut self, local_addr: win::SOCKADDR_IN6) -> Result<(), win::NTSTATUS> {
-        // self.socket.bind(&mut self.request, &local_addr)?;
-        // self.socket.bind(&mut self.rx_request, &local_addr)?;
+        let mut datapath = self.datapath.lock();
+        datapath.pause();
+        let previous = datapath.bound.take();
+        if previous.is_some() {
+            // The new address may overlap the old one.
+            if let Err(status) = self.socket.close(&mut self.request) {
+                datapath.restore(previous);
+                return Err(status);
+            }
+        }
+        let result = match bind_socket(&mut self.request, &local_addr) {
+            Ok(socket) => {
+                self.socket = socket;
+                datapath.bound = Some(local_addr);
+                Ok(())
+            }
+            Err(status) => {
+                if let Some(previous) = previous {
+                    if let Ok(socket) = bind_socket(&mut self.request, &previous) {
+                        self.socket = socket;
+                        datapath.bound = Some(previous);
+                    }
+                }
+                Err(status)
+            }
+        };
+        datapath.resume();
+        result
+    }
+
+    pub fn set_flood_policy(&mut self, policy: FloodPolicy) {
+        self.flood.store(policy.as_raw(), Relaxed);
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode.store(mode.as_raw(), Relaxed);
+    }
+
+    pub fn set_relay(&mut self, relay: bool) {
+        self.relay.store(relay, Relaxed);
+    }
+
+    pub fn set_local_key(
+        &mut self,
+        private_key: &[u8; KEY_SIZE],
+        cipher_suite: CipherSuite,
+        hash: HashAlgorithm,
+    ) -> Result<(), win::NTSTATUS> {
+        let local_key = Arc::new(LocalKey::import(private_key, cipher_suite, hash)?);
+        let old = self.local_key.write().replace(local_key);
+        drop(old);
+        Ok(())
+    }
+
+    pub fn add_peer(
+        &mut self,
+        remote_addr: win::SOCKADDR_IN6,
+        public_key: [u8; KEY_SIZE],
+        preshared_key: [u8; KEY_SIZE],
+        persistent_keepalive: Option<Duration>,
+        mac_addr: [u8; MAC_ADDR_SIZE],
+        allowed_ips: &[VEthPrefix],
+    ) -> Result<(), win::NTSTATUS> {
+        if !mac_addr::is_assignable(&mac_addr) {
+            return Err(win::STATUS_INVALID_PARAMETER);
+        }
+        self.peers.add(Peer::new(
+            remote_addr,
+            public_key,
+            preshared_key,
+            persistent_keepalive,
+            mac_addr.into(),
+        )?)?;
+        if let Err(status) = self.routes.set_peer(&public_key, allowed_ips) {
+            // Not added after all
+            self.peers.remove(&public_key).ok();
+            return Err(status);
+        }
         Ok(())
     }
 
-    pub fn add_peer(&mut self, remote_addr: win::SOCKADDR_IN6) -> Result<(), win::NTSTATUS> {
-        if self.peers.try_reserve(1).is_err() {
-            return Err(win::STATUS_INSUFFICIENT_RESOURCES);
+    pub fn update_peer(
+        &mut self,
+        remote_addr: win::SOCKADDR_IN6,
+        public_key: [u8; KEY_SIZE],
+        preshared_key: [u8; KEY_SIZE],
+        persistent_keepalive: Option<Duration>,
+        mac_addr: [u8; MAC_ADDR_SIZE],
+        allowed_ips: &[VEthPrefix],
+    ) -> Result<(), win::NTSTATUS> {
+        if !mac_addr::is_assignable(&mac_addr) {
+            return Err(win::STATUS_INVALID_PARAMETER);
+        }
+        let peer = self.peers.get(&public_key).ok_or(win::STATUS_NOT_FOUND)?;
+        self.routes.set_peer(&public_key, allowed_ips)?;
+        if peer.preshared_key == preshared_key {
+            peer.reconfigure(remote_addr, persistent_keepalive, mac_addr.into());
+            return Ok(());
         }
-        self.peers.push(Peer::new(remote_addr));
+        // The sessions were agreed on with the old pre-shared key.
+        self.peers.replace(Peer::new(
+            remote_addr,
+            public_key,
+            preshared_key,
+            persistent_keepalive,
+            mac_addr.into(),
+        )?)
+    }
+
+    pub fn remove_peer(&mut self, public_key: &[u8; KEY_SIZE]) -> Result<(), win::NTSTATUS> {
+        self.peers.remove(public_key)?;
+        self.fdb.remove_peer(public_key);
+        self.routes.remove_peer(public_key);
         Ok(())
     }
 
+    pub fn peers(&self) -> PeerList {
+        self.peers.snapshot()
+    }
+
+    pub fn fdb(&self) -> &Fdb {
+        &self.fdb
+    }
+
+    pub fn stats(&self) -> &AdapterStats {
+        &self.stats
+    }
+
+    pub fn events(&self) -> &Events {
+        &self.events
+    }
+
     fn init_tx_queue(
         &'static mut self,
         tx_queue: win::NETPACKETQUEUE,
     ) -> Result<&mut VEthTxQueue, win::NTSTATUS> {
-        VEthTxQueue::init(tx_queue, &self.socket, &mut self.tx_request, &self.peers)
+        VEthTxQueue::init(
+            tx_queue,
+            &self.socket,
+            &mut self.tx_request,
+            &self.local_key,
+            &self.peers,
+            &self.fdb,
+            &self.routes,
+            &self.replies,
+            &self.flood,
+            &self.mode,
+            &self.stats,
+            &self.events,
+            &self.datapath,
+            self.mtu,
+        )
     }
 
     fn init_rx_queue(
         &'static mut self,
         rx_queue: win::NETPACKETQUEUE,
     ) -> Result<&mut VEthRxQueue, win::NTSTATUS> {
-        VEthRxQueue::init(rx_queue, &self.socket, &mut self.rx_request, &self.peers)
+        VEthRxQueue::init(
+            rx_queue,
+            &self.socket,
+            &mut self.rx_request,
+            &self.local_key,
+            &self.peers,
+            &self.fdb,
+            &self.routes,
+            &self.replies,
+            &self.flood,
+            &self.mode,
+            &self.relay,
+            &self.cookie_checker,
+            &self.stats,
+            &self.events,
+            &self.datapath,
+            self.local_mac_addr,
+            self.mtu,
+        )
     }
 
     pub fn drop(&mut self) {
@@ -169,7 +473,90 @@ impl VEthAdapter {
 
 impl Drop for VEthAdapter {
     fn drop(&mut self) {
-        self.socket.close(&mut self.tx_request).unwrap();
+        if self.datapath.get_mut().bound.is_some() {
+            self.socket.close(&mut self.tx_request).unwrap();
+        }
+    }
+}
+
+fn bind_socket(
+    request: &mut IoRequest,
+    local_addr: &win::SOCKADDR_IN6,
+) -> Result<UdpSocket, win::NTSTATUS> {
+    let mut socket_init = UdpSocket::new(request)?;
+    let (socket, context) = socket_init.get();
+    socket.set_option(context, win::IPV6_V6ONLY, win::IPPROTO::IPPROTO_IPV6, false)?;
+    socket.bind(context, local_addr)?;
+    Ok(socket_init.take())
+}
+
+// The started queues, whose workers must not touch the socket while it is replaced. The workers
+// only run while a socket is bound.
+#[derive(Default)]
+pub struct Datapath {
+    bound: Option<win::SOCKADDR_IN6>,
+    tx_queue: Option<NonNull<VEthTxQueue>>,
+    rx_queue: Option<NonNull<VEthRxQueue>>,
+}
+
+impl Datapath {
+    pub fn start_tx(&mut self, tx: &mut VEthTxQueue) {
+        if self.bound.is_some() {
+            tx.resume();
+        }
+        self.tx_queue = Some(tx.into());
+    }
+
+    pub fn start_rx(&mut self, rx: &mut VEthRxQueue) {
+        if self.bound.is_some() {
+            rx.resume();
+        }
+        self.rx_queue = Some(rx.into());
+    }
+
+    pub fn cancel_tx(&mut self, tx: &mut VEthTxQueue) {
+        self.tx_queue = None;
+        if self.bound.is_some() {
+            tx.pause();
+        }
+    }
+
+    pub fn cancel_rx(&mut self, rx: &mut VEthRxQueue) {
+        self.rx_queue = None;
+        if self.bound.is_some() {
+            rx.pause();
+        }
+    }
+
+    fn pause(&mut self) {
+        if self.bound.is_none() {
+            return;
+        }
+        if let Some(mut tx) = self.tx_queue {
+            unsafe { tx.as_mut() }.pause();
+        }
+        if let Some(mut rx) = self.rx_queue {
+            unsafe { rx.as_mut() }.pause();
+        }
+    }
+
+    fn resume(&mut self) {
+        if self.bound.is_none() {
+            return;
+        }
+        if let Some(mut tx) = self.tx_queue {
+            unsafe { tx.as_mut() }.resume();
+        }
+        if let Some(mut rx) = self.rx_queue {
+            unsafe { rx.as_mut() }.resume();
+        }
+    }
+
+    // Puts back the binding taken out for a replacement that failed while its socket was still
+    // open, and resumes the workers on it.
+    fn restore(&mut self, bound: Option<win::SOCKADDR_IN6>) {
+        self.bound = bound;
+        self.resume();
     }
 }
 
@@ -242,27 +629,44 @@ pub fn veth_get_adapter_ptr_context_type() -> *const win::WDF_OBJECT_CONTEXT_TYP
     unsafe { &WDF_VETH_ADAPTER_PTR_TYPE_INFO }
 }
 
+// The layout of the header fields is owned by `shared::wire`.
 #[repr(C)]
-pub struct VEthPlainFrame {
-    pub data: [u8; crate::PLAIN_FRAME_DATA_SIZE as _],
+pub struct VEthCipherFrameHeader {
+    pub header: [u8; wire::HEADER_SIZE],
+    pub nonce: [u8; aead::NONCE_SIZE],
+    pub tag: [u8; aead::TAG_SIZE],
 }
 
-#[repr(C)]
-pub struct VEthCipherFrameHeader {
-    pub nonce: [u8; AesGcm::NONCE_SIZE12],
-    pub tag: [u8; AesGcm::TAG_SIZE16],
+const _: [(); wire::HEADER_SIZE + wire::DATA_OVERHEAD] =
+    [(); mem::size_of::<VEthCipherFrameHeader>()];
+
+// A data message: the header, then the frame
+pub fn split_datagram(datagram: &mut [u8]) -> (&mut VEthCipherFrameHeader, &mut [u8]) {
+    let (header, data) = datagram.split_at_mut(mem::size_of::<VEthCipherFrameHeader>());
+    let header = unsafe { &mut *header.as_mut_ptr().cast::<VEthCipherFrameHeader>() };
+    (header, data)
 }
 
+// Room for the MDL of a datagram of any size
 #[repr(C)]
-pub struct VEthCipherFrame {
-    pub header: VEthCipherFrameHeader,
-    pub data: [u8; crate::CIPHER_FRAME_DATA_SIZE as _],
+pub union MdlRepr {
+    mdl: MDL,
+    mdlx: [u8; unsafe { MmSizeOfMdl((PAGE_SIZE - 1) as _, mtu::datagram_size(mtu::MAX_MTU)) }],
 }
 
-#[repr(C)]
-pub union VEthFrame {
-    pub plain: VEthPlainFrame,
-    pub cipher: VEthCipherFrame,
+// Describes `length` bytes of the nonpaged `buf` for the socket.
+pub fn build_mdl(repr: &mut MaybeUninit<MdlRepr>, buf: *mut u8, length: usize) -> &mut MDL {
+    unsafe { MmInitializeMdl(ptr::raw_mut!((*repr.as_mut_ptr()).mdl), buf.cast(), length) };
+    unsafe { MmBuildMdlForNonPagedPool(ptr::raw_mut!((*repr.as_mut_ptr()).mdl)) };
+    unsafe { &mut repr.assume_init_mut().mdl }
+}
+
+pub fn alloc_buf(buf: &mut Vec<u8>, len: usize) -> Result<(), win::NTSTATUS> {
+    if buf.try_reserve_exact(len).is_err() {
+        return Err(win::STATUS_INSUFFICIENT_RESOURCES);
+    }
+    buf.resize(len, 0);
+    Ok(())
 }
 
 #[irql_requires_max(PASSIVE_LEVEL)]
@@ -0,0 +1,181 @@
+// The driver's side of `shared::cookie`: the responder checks the MACs of initiations and answers
+// them with cookie replies under load, and the initiator stores the cookies it receives.
+
+use core::{mem, ptr};
+
+use shared::{
+    cookie::{
+        self, Cookie, LoadMonitor, Mac, RateLimiter, COOKIE_LIFETIME, COOKIE_SECRET_LIFETIME,
+    },
+    wire::{self, MessageType},
+};
+
+use crate::{
+    crypto::{
+        self,
+        aead::{self, Aead, Cipher},
+    },
+    handshake::LocalKey,
+    os::{sync::RwLock, time::Instant},
+    peer::Peer,
+    windows::prelude as win,
+};
+
+const SECRET_SIZE: usize = 32;
+const RATE_LIMITER_SIZE: usize = 512;
+
+#[repr(C)]
+pub struct VEthCookieReply {
+    pub header: [u8; wire::HEADER_SIZE],
+    pub nonce: [u8; aead::NONCE_SIZE],
+    pub cookie: Cookie,
+    pub tag: [u8; aead::TAG_SIZE],
+}
+
+const _: [(); wire::HEADER_SIZE + wire::COOKIE_REPLY_SIZE] =
+    [(); mem::size_of::<VEthCookieReply>()];
+
+// The initiator's state, per peer.
+#[derive(Default)]
+pub struct CookieState {
+    last_mac1: Option<Mac>,
+    cookie: Option<(Cookie, Instant)>,
+}
+
+impl CookieState {
+    pub fn cookie(&self) -> Option<Cookie> {
+        let (cookie, received_at) = self.cookie?;
+        if received_at.elapsed() >= COOKIE_LIFETIME {
+            return None;
+        }
+        Some(cookie)
+    }
+
+    pub fn sent(&mut self, mac1: Mac) {
+        self.last_mac1 = Some(mac1);
+    }
+}
+
+pub enum Admission {
+    Accept,
+    // Answer with a cookie reply.
+    Challenge,
+    Reject,
+}
+
+struct Secret {
+    key: [u8; SECRET_SIZE],
+    created_at: Option<Instant>,
+}
+
+// The responder's state.
+pub struct CookieChecker {
+    secret: RwLock<Secret>,
+    load: RwLock<LoadMonitor>,
+    limiter: RwLock<RateLimiter<RATE_LIMITER_SIZE>>,
+}
+
+impl CookieChecker {
+    pub unsafe fn init(uninit: *mut Self) -> Result<(), win::NTSTATUS> {
+        let mut seed = [0; 8];
+        crypto::gen_random(&mut seed)?;
+
+        let secret = RwLock::init(ptr::raw_mut!((*uninit).secret));
+        secret.write(Secret {
+            key: [0; SECRET_SIZE],
+            created_at: None,
+        });
+        ptr::raw_mut!((*uninit).load).write(RwLock::new(LoadMonitor::new()));
+        let limiter = RwLock::init(ptr::raw_mut!((*uninit).limiter));
+        RateLimiter::<RATE_LIMITER_SIZE>::init(&mut *limiter.cast(), u64::from_ne_bytes(seed));
+        Ok(())
+    }
+
+    // The source is bound to both the address and the port.
+    fn cookie(&self, local: &LocalKey, addr: &win::SOCKADDR_IN6) -> Result<Cookie, win::NTSTATUS> {
+        let key = {
+            let secret = self.secret.read();
+            match secret.created_at {
+                Some(created_at) if created_at.elapsed() < COOKIE_SECRET_LIFETIME => {
+                    Some(secret.key)
+                }
+                _ => None,
+            }
+        };
+        // Only the RX worker rotates the secret, and outside the spin lock.
+        let key = match key {
+            Some(key) => key,
+            None => {
+                let mut key = [0; SECRET_SIZE];
+                crypto::gen_random(&mut key)?;
+                *self.secret.write() = Secret {
+                    key,
+                    created_at: Some(Instant::now()),
+                };
+                key
+            }
+        };
+        let mut source = [0; 18];
+        source[..16].copy_from_slice(&addr.addr);
+        source[16..].copy_from_slice(&addr.port.to_ne_bytes());
+        cookie::make_cookie(local.hash(), &key, &source)
+    }
+
+    // Decides what to do with an initiation before any DH computation.
+    pub fn admit(
+        &self,
+        local: &LocalKey,
+        msg: &[u8],
+        addr: &win::SOCKADDR_IN6,
+    ) -> Result<Admission, win::NTSTATUS> {
+        if !local.check_mac1(msg)? {
+            return Ok(Admission::Reject);
+        }
+        let now = Instant::now().as_duration();
+        if !self.load.write().record(now) {
+            return Ok(Admission::Accept);
+        }
+        let cookie = self.cookie(local, addr)?;
+        if !cookie::check_mac2(local.hash(), &cookie, msg)? {
+            return Ok(Admission::Challenge);
+        }
+        if !self.limiter.write().allow(&addr.addr, now) {
+            return Ok(Admission::Reject);
+        }
+        Ok(Admission::Accept)
+    }
+
+    pub fn create_reply(
+        &self,
+        local: &LocalKey,
+        mac1: &Mac,
+        receiver_index: u32,
+        addr: &win::SOCKADDR_IN6,
+        reply: &mut VEthCookieReply,
+    ) -> Result<(), win::NTSTATUS> {
+        reply.header = wire::Header::new(MessageType::Cookie, receiver_index).encode();
+        crypto::gen_random(&mut reply.nonce)?;
+        reply.cookie = self.cookie(local, addr)?;
+        local
+            .cookie_cipher()
+            .encrypt(&reply.nonce, mac1, &mut reply.cookie, &mut reply.tag)
+    }
+}
+
+pub fn consume_reply(
+    local: &LocalKey,
+    peer: &Peer,
+    reply: &VEthCookieReply,
+) -> Result<(), win::NTSTATUS> {
+    let mac1 = peer
+        .cookie
+        .read()
+        .last_mac1
+        .ok_or(win::STATUS_INVALID_DEVICE_STATE)?;
+    let key = cookie::cookie_key(local.hash(), &peer.public_key)?;
+    let cipher = Cipher::new(local.cipher_suite(), &key)?;
+    let mut cookie = reply.cookie;
+    cipher.decrypt(&reply.nonce, &mac1, &mut cookie, &reply.tag)?;
+    peer.cookie.write().cookie = Some((cookie, Instant::now()));
+    Ok(())
+}
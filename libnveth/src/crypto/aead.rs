@@ -0,0 +1,222 @@
+use core::{
+    default::default,
+    mem::{self, MaybeUninit},
+    ptr,
+};
+
+use shared::crypto::{BCryptAlgHandle, BCryptKeyHandle};
+
+use crate::{
+    crypto::{aes_gcm::AesGcm, chacha20_poly1305::ChaCha20Poly1305},
+    windows::prelude as win,
+};
+
+pub use shared::provider::{Aead, CipherSuite, NONCE_SIZE, TAG_SIZE};
+
+pub const MAX_KEY_SIZE: usize = 256 / 8;
+
+pub enum Cipher {
+    AesGcm(AesGcm),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+impl Cipher {
+    // Uses the leading `key_size` bytes of the key.
+    pub fn new(suite: CipherSuite, key: &[u8]) -> Result<Self, win::NTSTATUS> {
+        let key = key
+            .get(..suite.key_size())
+            .ok_or(win::STATUS_INVALID_PARAMETER)?;
+        Ok(match suite {
+            CipherSuite::ChaCha20Poly1305 => Self::ChaCha20Poly1305(ChaCha20Poly1305::new(key)?),
+            _ => Self::AesGcm(AesGcm::new(key)?),
+        })
+    }
+
+    fn key_handle(&self) -> &BCryptKeyHandle {
+        match self {
+            Self::AesGcm(cipher) => cipher.key_handle(),
+            Self::ChaCha20Poly1305(cipher) => cipher.key_handle(),
+        }
+    }
+}
+
+impl Aead for Cipher {
+    type Error = win::NTSTATUS;
+
+    fn encrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        text: &mut [u8],
+        tag: &mut [u8; TAG_SIZE],
+    ) -> Result<(), win::NTSTATUS> {
+        encrypt(self.key_handle(), nonce, aad, text, tag)
+    }
+
+    fn decrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        text: &mut [u8],
+        tag: &[u8; TAG_SIZE],
+    ) -> Result<(), win::NTSTATUS> {
+        decrypt(self.key_handle(), nonce, aad, text, tag)
+    }
+}
+
+pub(super) fn open_algorithm_provider(
+    alg_id: &[u16],
+    chaining_mode: Option<&[u16]>,
+) -> Result<BCryptAlgHandle, win::NTSTATUS> {
+    let mut alg_handle = MaybeUninit::uninit();
+    let status = unsafe {
+        win::BCryptOpenAlgorithmProvider(alg_handle.as_mut_ptr(), alg_id.as_ptr(), ptr::null(), 0)
+            .into()
+    };
+    if status != win::STATUS_SUCCESS {
+        return Err(status);
+    }
+    let alg_handle = unsafe { BCryptAlgHandle::from_raw(alg_handle.assume_init()) };
+    if let Some(chaining_mode) = chaining_mode {
+        let status = unsafe {
+            win::BCryptSetProperty(
+                alg_handle.as_raw().into(),
+                win::BCRYPT_CHAINING_MODE.as_ptr(),
+                chaining_mode.as_ptr() as *mut _,
+                mem::size_of_val(chaining_mode) as _,
+                0,
+            )
+            .into()
+        };
+        if status != win::STATUS_SUCCESS {
+            return Err(status);
+        }
+    }
+    Ok(alg_handle)
+}
+
+pub(super) fn import_key(
+    alg_handle: &BCryptAlgHandle,
+    key: &[u8],
+) -> Result<BCryptKeyHandle, win::NTSTATUS> {
+    if key.len() > MAX_KEY_SIZE {
+        return Err(win::STATUS_INVALID_PARAMETER);
+    }
+    let mut blob = BCryptKeyDataBlob {
+        header: win::BCRYPT_KEY_DATA_BLOB_HEADER {
+            dwMagic: win::BCRYPT_KEY_DATA_BLOB_MAGIC,
+            dwVersion: win::BCRYPT_KEY_DATA_BLOB_VERSION1,
+            cbKeyData: mem::size_of_val(key) as _,
+        },
+        key: [0; MAX_KEY_SIZE],
+    };
+    blob.key[..key.len()].copy_from_slice(key);
+    let blob_size = mem::size_of_val(&blob.header) + key.len();
+    let mut key_handle = MaybeUninit::uninit();
+    let status = unsafe {
+        win::BCryptImportKey(
+            alg_handle.as_raw(),
+            ptr::null_mut(),
+            win::BCRYPT_KEY_DATA_BLOB.as_ptr(),
+            key_handle.as_mut_ptr(),
+            ptr::null_mut(),
+            0,
+            &blob as *const _ as *mut _,
+            blob_size as _,
+            0,
+        )
+        .into()
+    };
+    if status != win::STATUS_SUCCESS {
+        return Err(status);
+    }
+    Ok(unsafe { BCryptKeyHandle::from_raw(key_handle.assume_init()) })
+}
+
+fn encrypt(
+    key_handle: &BCryptKeyHandle,
+    nonce: &[u8],
+    aad: &[u8],
+    text: &mut [u8],
+    tag: &mut [u8],
+) -> Result<(), win::NTSTATUS> {
+    let buf_size = mem::size_of_val(text) as _;
+    let auth_info = win::BCRYPT_AUTHENTICATED_CIPHER_MODE_INFO {
+        pbNonce: nonce.as_ptr() as *mut _,
+        cbNonce: mem::size_of_val(nonce) as _,
+        pbAuthData: aad.as_ptr() as *mut _,
+        cbAuthData: mem::size_of_val(aad) as _,
+        pbTag: tag.as_mut_ptr(),
+        cbTag: mem::size_of_val(tag) as _,
+        ..default()
+    };
+    let mut written = MaybeUninit::uninit();
+    let status = unsafe {
+        win::BCryptEncrypt(
+            key_handle.as_raw(),
+            text.as_ptr() as *mut _,
+            buf_size,
+            &auth_info as *const _ as *mut _,
+            ptr::null_mut(),
+            0,
+            text.as_mut_ptr(),
+            buf_size,
+            written.as_mut_ptr(),
+            0,
+        )
+        .into()
+    };
+    if status != win::STATUS_SUCCESS {
+        return Err(status);
+    }
+    let written = unsafe { written.assume_init() };
+    debug_assert_eq!(buf_size, written);
+    Ok(())
+}
+
+fn decrypt(
+    key_handle: &BCryptKeyHandle,
+    nonce: &[u8],
+    aad: &[u8],
+    text: &mut [u8],
+    tag: &[u8],
+) -> Result<(), win::NTSTATUS> {
+    let buf_size = mem::size_of_val(text) as _;
+    let auth_info = win::BCRYPT_AUTHENTICATED_CIPHER_MODE_INFO {
+        pbNonce: nonce.as_ptr() as *mut _,
+        cbNonce: mem::size_of_val(nonce) as _,
+        pbAuthData: aad.as_ptr() as *mut _,
+        cbAuthData: mem::size_of_val(aad) as _,
+        pbTag: tag.as_ptr() as *mut _,
+        cbTag: mem::size_of_val(tag) as _,
+        ..default()
+    };
+    let mut written = MaybeUninit::uninit();
+    let status = unsafe {
+        win::BCryptDecrypt(
+            key_handle.as_raw(),
+            text.as_ptr() as *mut _,
+            buf_size,
+            &auth_info as *const _ as *mut _,
+            ptr::null_mut(),
+            0,
+            text.as_mut_ptr(),
+            buf_size,
+            written.as_mut_ptr(),
+            0,
+        )
+        .into()
+    };
+    if status != win::STATUS_SUCCESS {
+        return Err(status);
+    }
+    let written = unsafe { written.assume_init() };
+    debug_assert_eq!(buf_size, written);
+    Ok(())
+}
+
+#[repr(C)]
+struct BCryptKeyDataBlob<T> {
+    header: win::BCRYPT_KEY_DATA_BLOB_HEADER,
+    key: T,
+}
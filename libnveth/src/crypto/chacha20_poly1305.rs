@@ -0,0 +1,25 @@
+use shared::crypto::BCryptKeyHandle;
+
+use crate::{crypto::aead, windows::prelude as win};
+
+pub struct ChaCha20Poly1305 {
+    key_handle: BCryptKeyHandle,
+}
+
+impl ChaCha20Poly1305 {
+    pub const KEY_SIZE32: usize = 256 / 8;
+
+    pub fn new(key: &[u8]) -> Result<Self, win::NTSTATUS> {
+        if key.len() != Self::KEY_SIZE32 {
+            return Err(win::STATUS_INVALID_PARAMETER);
+        }
+        let alg_handle =
+            aead::open_algorithm_provider(&win::BCRYPT_CHACHA20_POLY1305_ALGORITHM, None)?;
+        let key_handle = aead::import_key(&alg_handle, key)?;
+        Ok(Self { key_handle })
+    }
+
+    pub(super) fn key_handle(&self) -> &BCryptKeyHandle {
+        &self.key_handle
+    }
+}
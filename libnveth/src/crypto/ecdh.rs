@@ -0,0 +1,210 @@
+use core::{
+    mem::{self, MaybeUninit},
+    ptr, slice,
+};
+
+use shared::crypto::{BCryptAlgHandle, BCryptKeyHandle, BCryptSecretHandle};
+
+use crate::windows::prelude as win;
+
+fn open_algorithm_provider() -> Result<BCryptAlgHandle, win::NTSTATUS> {
+    let mut alg_handle = MaybeUninit::uninit();
+    let status = unsafe {
+        win::BCryptOpenAlgorithmProvider(
+            alg_handle.as_mut_ptr(),
+            win::BCRYPT_ECDH_ALGORITHM.as_ptr(),
+            ptr::null(),
+            0,
+        )
+        .into()
+    };
+    if status != win::STATUS_SUCCESS {
+        return Err(status);
+    }
+    let alg_handle = unsafe { BCryptAlgHandle::from_raw(alg_handle.assume_init()) };
+    let status = unsafe {
+        win::BCryptSetProperty(
+            alg_handle.as_raw().into(),
+            win::BCRYPT_ECC_CURVE_NAME.as_ptr(),
+            win::BCRYPT_ECC_CURVE_25519.as_ptr() as *mut _,
+            mem::size_of_val(&win::BCRYPT_ECC_CURVE_25519) as _,
+            0,
+        )
+        .into()
+    };
+    if status != win::STATUS_SUCCESS {
+        return Err(status);
+    }
+    Ok(alg_handle)
+}
+
+fn import_key_pair(blob_type: &[u16], key_blob: &[u8]) -> Result<BCryptKeyHandle, win::NTSTATUS> {
+    let alg_handle = open_algorithm_provider()?;
+    let mut key_handle = MaybeUninit::uninit();
+    let status = unsafe {
+        win::BCryptImportKeyPair(
+            alg_handle.as_raw(),
+            ptr::null_mut(),
+            blob_type.as_ptr(),
+            key_handle.as_mut_ptr(),
+            key_blob.as_ptr() as *mut _,
+            mem::size_of_val(key_blob) as _,
+            0,
+        )
+        .into()
+    };
+    if status != win::STATUS_SUCCESS {
+        return Err(status);
+    }
+    Ok(unsafe { BCryptKeyHandle::from_raw(key_handle.assume_init()) })
+}
+
+pub struct Ecdh {
+    key_handle: BCryptKeyHandle,
+}
+
+impl Ecdh {
+    pub const KEY_SIZE32: usize = 256 / 8;
+
+    pub fn new() -> Result<Self, win::NTSTATUS> {
+        let alg_handle = open_algorithm_provider()?;
+        let mut key_handle = MaybeUninit::uninit();
+        let status = unsafe {
+            win::BCryptGenerateKeyPair(alg_handle.as_raw(), key_handle.as_mut_ptr(), 255, 0).into()
+        };
+        if status != win::STATUS_SUCCESS {
+            return Err(status);
+        }
+        let key_handle = unsafe { BCryptKeyHandle::from_raw(key_handle.assume_init()) };
+        let status = unsafe { win::BCryptFinalizeKeyPair(key_handle.as_raw(), 0).into() };
+        if status != win::STATUS_SUCCESS {
+            return Err(status);
+        }
+        Ok(Self { key_handle })
+    }
+
+    pub fn import(private_key: &[u8; Self::KEY_SIZE32]) -> Result<Self, win::NTSTATUS> {
+        let blob = BCryptEccKeyBlob {
+            header: win::BCRYPT_ECCKEY_BLOB {
+                dwMagic: win::BCRYPT_ECDH_PRIVATE_GENERIC_MAGIC,
+                cbKey: Self::KEY_SIZE32 as _,
+            },
+            x: [0; Self::KEY_SIZE32],
+            y: [0; Self::KEY_SIZE32],
+            d: *private_key,
+        };
+        let key_blob = unsafe {
+            slice::from_raw_parts(
+                (&blob as *const BCryptEccKeyBlob<_>).cast(),
+                mem::size_of_val(&blob),
+            )
+        };
+        let key_handle = import_key_pair(&win::BCRYPT_ECCPRIVATE_BLOB, key_blob)?;
+        Ok(Self { key_handle })
+    }
+
+    pub fn export_public_key(&self) -> Result<[u8; Self::KEY_SIZE32], win::NTSTATUS> {
+        let mut blob = MaybeUninit::<BCryptEccPubKeyBlob<[u8; Self::KEY_SIZE32]>>::uninit();
+        let mut blob_size = MaybeUninit::uninit();
+        let status = unsafe {
+            win::BCryptExportKey(
+                self.key_handle.as_raw(),
+                ptr::null_mut(),
+                win::BCRYPT_ECCPUBLIC_BLOB.as_ptr(),
+                blob.as_mut_ptr().cast(),
+                mem::size_of_val(&blob) as _,
+                blob_size.as_mut_ptr(),
+                0,
+            )
+            .into()
+        };
+        if status != win::STATUS_SUCCESS {
+            return Err(status);
+        }
+        let blob_size = unsafe { blob_size.assume_init() };
+        debug_assert_eq!(blob_size as usize, mem::size_of_val(&blob));
+        let blob = unsafe { blob.assume_init() };
+        Ok(blob.x)
+    }
+
+    // The raw X25519 shared secret; key derivation is left to the caller's key schedule.
+    pub fn agree(
+        &self,
+        other_pub_key: &EcdhPubKey,
+    ) -> Result<[u8; Self::KEY_SIZE32], win::NTSTATUS> {
+        let mut secret_handle = MaybeUninit::uninit();
+        let status = unsafe {
+            win::BCryptSecretAgreement(
+                self.key_handle.as_raw(),
+                other_pub_key.key_handle.as_raw(),
+                secret_handle.as_mut_ptr(),
+                0,
+            )
+            .into()
+        };
+        if status != win::STATUS_SUCCESS {
+            return Err(status);
+        }
+        let secret_handle = unsafe { BCryptSecretHandle::from_raw(secret_handle.assume_init()) };
+        let mut secret = [0; Self::KEY_SIZE32];
+        let mut secret_size = MaybeUninit::uninit();
+        let status = unsafe {
+            win::BCryptDeriveKey(
+                secret_handle.as_raw(),
+                win::BCRYPT_KDF_RAW_SECRET.as_ptr(),
+                ptr::null_mut(),
+                secret.as_mut_ptr(),
+                mem::size_of_val(&secret) as _,
+                secret_size.as_mut_ptr(),
+                0,
+            )
+            .into()
+        };
+        if status != win::STATUS_SUCCESS {
+            return Err(status);
+        }
+        let secret_size = unsafe { secret_size.assume_init() };
+        debug_assert_eq!(secret_size as usize, mem::size_of_val(&secret));
+        Ok(secret)
+    }
+}
+
+pub struct EcdhPubKey {
+    key_handle: BCryptKeyHandle,
+}
+
+impl EcdhPubKey {
+    pub fn import(public_key: &[u8; Ecdh::KEY_SIZE32]) -> Result<Self, win::NTSTATUS> {
+        let blob = BCryptEccPubKeyBlob {
+            header: win::BCRYPT_ECCKEY_BLOB {
+                dwMagic: win::BCRYPT_ECDH_PUBLIC_GENERIC_MAGIC,
+                cbKey: Ecdh::KEY_SIZE32 as _,
+            },
+            x: *public_key,
+            y: [0; Ecdh::KEY_SIZE32],
+        };
+        let key_blob = unsafe {
+            slice::from_raw_parts(
+                (&blob as *const BCryptEccPubKeyBlob<_>).cast(),
+                mem::size_of_val(&blob),
+            )
+        };
+        let key_handle = import_key_pair(&win::BCRYPT_ECCPUBLIC_BLOB, key_blob)?;
+        Ok(Self { key_handle })
+    }
+}
+
+#[repr(C)]
+struct BCryptEccKeyBlob<T> {
+    header: win::BCRYPT_ECCKEY_BLOB,
+    x: T,
+    y: T,
+    d: T,
+}
+
+#[repr(C)]
+struct BCryptEccPubKeyBlob<T> {
+    header: win::BCRYPT_ECCKEY_BLOB,
+    x: T,
+    y: T,
+}
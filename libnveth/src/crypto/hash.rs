@@ -0,0 +1,95 @@
+use core::{mem::MaybeUninit, ptr};
+
+use shared::{
+    crypto::{BCryptAlgHandle, BCryptHashHandle},
+    kdf,
+};
+
+use crate::{crypto::aead, windows::prelude as win};
+
+pub use shared::provider::HashAlgorithm;
+
+pub struct Hash {
+    algorithm: HashAlgorithm,
+    alg_handle: BCryptAlgHandle,
+}
+
+impl Hash {
+    pub fn new(algorithm: HashAlgorithm) -> Result<Self, win::NTSTATUS> {
+        let alg_id: &[u16] = match algorithm {
+            HashAlgorithm::Sha256 => &win::BCRYPT_SHA256_ALGORITHM,
+            HashAlgorithm::Sha512 => &win::BCRYPT_SHA512_ALGORITHM,
+        };
+        let alg_handle = aead::open_algorithm_provider(alg_id, None)?;
+        Ok(Self {
+            algorithm,
+            alg_handle,
+        })
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+}
+
+impl kdf::Hash for Hash {
+    type Error = win::NTSTATUS;
+    type Context = BCryptHashHandle;
+
+    fn output_size(&self) -> usize {
+        self.algorithm.output_size()
+    }
+
+    fn block_size(&self) -> usize {
+        self.algorithm.block_size()
+    }
+
+    fn init(&self) -> Result<BCryptHashHandle, win::NTSTATUS> {
+        let mut hash_handle = MaybeUninit::uninit();
+        let status = unsafe {
+            win::BCryptCreateHash(
+                self.alg_handle.as_raw(),
+                hash_handle.as_mut_ptr(),
+                ptr::null_mut(),
+                0,
+                ptr::null_mut(),
+                0,
+                0,
+            )
+            .into()
+        };
+        if status != win::STATUS_SUCCESS {
+            return Err(status);
+        }
+        Ok(unsafe { BCryptHashHandle::from_raw(hash_handle.assume_init()) })
+    }
+
+    fn update(&self, context: &mut BCryptHashHandle, data: &[u8]) -> Result<(), win::NTSTATUS> {
+        let status = unsafe {
+            win::BCryptHashData(
+                context.as_raw(),
+                data.as_ptr() as *mut _,
+                data.len() as _,
+                0,
+            )
+            .into()
+        };
+        if status != win::STATUS_SUCCESS {
+            return Err(status);
+        }
+        Ok(())
+    }
+
+    fn finish(&self, context: BCryptHashHandle, out: &mut [u8]) -> Result<(), win::NTSTATUS> {
+        let out = out
+            .get_mut(..self.output_size())
+            .ok_or(win::STATUS_BUFFER_TOO_SMALL)?;
+        let status = unsafe {
+            win::BCryptFinishHash(context.as_raw(), out.as_mut_ptr(), out.len() as _, 0).into()
+        };
+        if status != win::STATUS_SUCCESS {
+            return Err(status);
+        }
+        Ok(())
+    }
+}
@@ -1 +1,26 @@
+pub mod aead;
 pub mod aes_gcm;
+pub mod chacha20_poly1305;
+pub mod ecdh;
+pub mod hash;
+pub mod provider;
+
+use core::{mem, ptr};
+
+use crate::windows::prelude as win;
+
+pub fn gen_random(buf: &mut [u8]) -> Result<(), win::NTSTATUS> {
+    let status = unsafe {
+        win::BCryptGenRandom(
+            ptr::null_mut(),
+            buf.as_mut_ptr(),
+            mem::size_of_val(buf) as _,
+            win::BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+        )
+        .into()
+    };
+    if status != win::STATUS_SUCCESS {
+        return Err(status);
+    }
+    Ok(())
+}
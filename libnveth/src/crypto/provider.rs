@@ -0,0 +1,41 @@
+use shared::provider::{CipherSuite, CryptoProvider, HashAlgorithm, X25519_KEY_SIZE};
+
+use crate::{
+    crypto::{
+        aead::Cipher,
+        ecdh::{Ecdh, EcdhPubKey},
+        hash::Hash,
+    },
+    windows::prelude as win,
+};
+
+pub struct BCryptProvider;
+
+impl CryptoProvider for BCryptProvider {
+    type Error = win::NTSTATUS;
+    type Hash = Hash;
+    type Aead = Cipher;
+
+    fn hash(&self, algorithm: HashAlgorithm) -> Result<Hash, win::NTSTATUS> {
+        Hash::new(algorithm)
+    }
+
+    fn aead(&self, suite: CipherSuite, key: &[u8]) -> Result<Cipher, win::NTSTATUS> {
+        Cipher::new(suite, key)
+    }
+
+    fn x25519_public_key(
+        &self,
+        private_key: &[u8; X25519_KEY_SIZE],
+    ) -> Result<[u8; X25519_KEY_SIZE], win::NTSTATUS> {
+        Ecdh::import(private_key)?.export_public_key()
+    }
+
+    fn x25519(
+        &self,
+        private_key: &[u8; X25519_KEY_SIZE],
+        public_key: &[u8; X25519_KEY_SIZE],
+    ) -> Result<[u8; X25519_KEY_SIZE], win::NTSTATUS> {
+        Ecdh::import(private_key)?.agree(&EcdhPubKey::import(public_key)?)
+    }
+}
@@ -1,14 +1,17 @@
 use core::{
-    default::default,
     mem::{self, MaybeUninit},
-    ptr,
+    ptr, slice,
+    time::Duration,
 };
 
 use libnveth_macros::*;
 
+use shared::{forward::FloodPolicy, mac_addr::MAC_ADDR_SIZE, routing::Mode};
+
 use crate::{
-    adapter::{self, VEthAdapter, VEthCipherFrame},
-    ioctl::*,
+    adapter::VEthAdapter,
+    crypto::{aead::CipherSuite, hash::HashAlgorithm},
+    ioctl::{self, *},
     windows::prelude as win,
 };
 
@@ -27,45 +30,7 @@ pub extern "system" fn evt_device_prepare_hardware(
             }
             Ok(adapter) => adapter,
         };
-        let adapter_handle = adapter.adapter_handle;
-        let link_layer_capabilities =
-            win::NET_ADAPTER_LINK_LAYER_CAPABILITIES_INIT(crate::LINK_SPEED, crate::LINK_SPEED);
-        unsafe {
-            win::NetAdapterSetLinkLayerCapabilities(adapter_handle, &link_layer_capabilities)
-        };
-        unsafe { win::NetAdapterSetLinkLayerMtuSize(adapter_handle, crate::PLAIN_FRAME_DATA_SIZE) };
-        let packet_filter = win::NET_ADAPTER_PACKET_FILTER_CAPABILITIES_INIT(
-            win::NET_PACKET_FILTER_FLAGS::NetPacketFilterFlagDirected
-                | win::NET_PACKET_FILTER_FLAGS::NetPacketFilterFlagMulticast
-                | win::NET_PACKET_FILTER_FLAGS::NetPacketFilterFlagAllMulticast
-                | win::NET_PACKET_FILTER_FLAGS::NetPacketFilterFlagBroadcast
-                | win::NET_PACKET_FILTER_FLAGS::NetPacketFilterFlagPromiscuous,
-            Some(adapter::evt_set_packet_filter),
-        );
-        unsafe { win::NetAdapterSetPacketFilterCapabilities(adapter_handle, &packet_filter) };
-        let mut link_layer_address = win::NET_ADAPTER_LINK_LAYER_ADDRESS {
-            length: 6,
-            ..default()
-        };
-        link_layer_address.address[0..6].copy_from_slice(adapter.local_mac_addr.bytes());
-        unsafe { win::NetAdapterSetPermanentLinkLayerAddress(adapter_handle, &link_layer_address) };
-        unsafe { win::NetAdapterSetCurrentLinkLayerAddress(adapter_handle, &link_layer_address) };
-        let tx_capabilities = win::NET_ADAPTER_TX_CAPABILITIES_INIT(1);
-        let rx_capabilities = win::NET_ADAPTER_RX_CAPABILITIES_INIT_SYSTEM_MANAGED(
-            mem::size_of::<VEthCipherFrame>(),
-            1,
-        );
-        unsafe {
-            win::NetAdapterSetDataPathCapabilities(
-                adapter_handle,
-                &tx_capabilities,
-                &rx_capabilities,
-            )
-        };
-        adapter.set_connect_state(false);
-        let status = unsafe { win::NetAdapterStart(adapter_handle) };
-        if !win::NT_SUCCESS(status) {
-            trace_exit_status!("NetAdapterStart", status);
+        if let Err(status) = adapter.start() {
             return status;
         }
         win::STATUS_SUCCESS
@@ -150,7 +115,51 @@ fn wdf_request_retrieve_input_buffer<'a, T>(
     Ok(buffer)
 }
 
-#[irql_requires_max(DISPATCH_LEVEL)]
+fn wdf_request_retrieve_output_buffer<'a, T>(
+    request: win::WDFREQUEST,
+) -> Result<&'a mut T, win::NTSTATUS> {
+    let mut buffer = MaybeUninit::uninit();
+    let status = unsafe {
+        win::WdfRequestRetrieveOutputBuffer(
+            request,
+            mem::size_of::<T>(),
+            buffer.as_mut_ptr(),
+            ptr::null_mut(),
+        )
+    };
+    if !win::NT_SUCCESS(status) {
+        return Err(status);
+    }
+    let buffer = unsafe { &mut *buffer.assume_init().cast::<T>() };
+    Ok(buffer)
+}
+
+pub fn wdf_request_retrieve_output_slice<'a, T>(
+    request: win::WDFREQUEST,
+) -> Result<&'a mut [T], win::NTSTATUS> {
+    let mut buffer = MaybeUninit::uninit();
+    let mut length = MaybeUninit::uninit();
+    let status = unsafe {
+        win::WdfRequestRetrieveOutputBuffer(
+            request,
+            mem::size_of::<T>(),
+            buffer.as_mut_ptr(),
+            length.as_mut_ptr(),
+        )
+    };
+    if !win::NT_SUCCESS(status) {
+        return Err(status);
+    }
+    let buffer = unsafe {
+        slice::from_raw_parts_mut(
+            buffer.assume_init().cast::<T>(),
+            length.assume_init() / mem::size_of::<T>(),
+        )
+    };
+    Ok(buffer)
+}
+
+#[irql_requires_max(PASSIVE_LEVEL)]
 pub extern "system" fn evt_wdf_io_queue_io_device_control(
     queue: win::WDFQUEUE,
     request: win::WDFREQUEST,
@@ -163,6 +172,7 @@ pub extern "system" fn evt_wdf_io_queue_io_device_control(
     let device = unsafe { win::WdfIoQueueGetDevice(queue) };
     let adapter = VEthAdapter::from_device_mut(device);
 
+    let mut information = 0;
     let status = match io_control_code {
         IOCTL_VETH_SET_CONNECT_STATE => match wdf_request_retrieve_input_buffer::<bool>(request) {
             Err(status) => status,
@@ -194,11 +204,119 @@ pub extern "system" fn evt_wdf_io_queue_io_device_control(
                 }
             }
         }
-        IOCTL_VETH_ADD_REMOTE_PEER => {
-            match wdf_request_retrieve_input_buffer::<win::SOCKADDR_IN6>(request) {
+        IOCTL_VETH_SET_LOCAL_KEY => {
+            match wdf_request_retrieve_input_buffer::<VEthLocalKey>(request) {
+                Err(status) => status,
+                Ok(local_key) => match (
+                    CipherSuite::from_raw(local_key.cipher_suite),
+                    HashAlgorithm::from_raw(local_key.hash),
+                ) {
+                    (Some(cipher_suite), Some(hash)) => {
+                        if let Err(status) =
+                            adapter.set_local_key(&local_key.private_key, cipher_suite, hash)
+                        {
+                            status
+                        } else {
+                            win::STATUS_SUCCESS
+                        }
+                    }
+                    _ => win::STATUS_NOT_SUPPORTED,
+                },
+            }
+        }
+        IOCTL_VETH_ADD_REMOTE_PEER | IOCTL_VETH_UPDATE_REMOTE_PEER => {
+            match wdf_request_retrieve_input_buffer::<VEthRemotePeer>(request) {
+                Err(status) => status,
+                Ok(remote_peer) => match ioctl::allowed_ips(remote_peer) {
+                    None => win::STATUS_INVALID_PARAMETER,
+                    Some(allowed_ips) => {
+                        let persistent_keepalive = match remote_peer.persistent_keepalive {
+                            0 => None,
+                            secs => Some(Duration::from_secs(secs.into())),
+                        };
+                        let result = if io_control_code == IOCTL_VETH_ADD_REMOTE_PEER {
+                            adapter.add_peer(
+                                remote_peer.socket_addr.clone(),
+                                remote_peer.public_key,
+                                remote_peer.preshared_key,
+                                persistent_keepalive,
+                                remote_peer.mac_addr,
+                                allowed_ips,
+                            )
+                        } else {
+                            adapter.update_peer(
+                                remote_peer.socket_addr.clone(),
+                                remote_peer.public_key,
+                                remote_peer.preshared_key,
+                                persistent_keepalive,
+                                remote_peer.mac_addr,
+                                allowed_ips,
+                            )
+                        };
+                        if let Err(status) = result {
+                            status
+                        } else {
+                            win::STATUS_SUCCESS
+                        }
+                    }
+                },
+            }
+        }
+        IOCTL_VETH_REMOVE_REMOTE_PEER => {
+            match wdf_request_retrieve_input_buffer::<[u8; KEY_SIZE]>(request) {
+                Err(status) => status,
+                Ok(public_key) => {
+                    if let Err(status) = adapter.remove_peer(public_key) {
+                        status
+                    } else {
+                        win::STATUS_SUCCESS
+                    }
+                }
+            }
+        }
+        IOCTL_VETH_GET_PEERS => {
+            match wdf_request_retrieve_output_slice::<VEthPeerStatus>(request) {
                 Err(status) => status,
-                Ok(remote_addr) => {
-                    if let Err(status) = adapter.add_peer(remote_addr.clone()) {
+                Ok(peer_status) => {
+                    // A full buffer tells the caller to retry with a larger one.
+                    let peers = adapter.peers();
+                    let count = peers.len().min(peer_status.len());
+                    for (entry, peer) in peer_status.iter_mut().zip(peers.iter()) {
+                        unsafe { ptr::write(entry, ioctl::peer_status(peer)) };
+                    }
+                    information = count * mem::size_of::<VEthPeerStatus>();
+                    win::STATUS_SUCCESS
+                }
+            }
+        }
+        IOCTL_VETH_GET_STATS => match wdf_request_retrieve_output_buffer::<VEthStats>(request) {
+            Err(status) => status,
+            Ok(stats) => {
+                unsafe { ptr::write(stats, ioctl::stats(adapter.stats())) };
+                information = mem::size_of::<VEthStats>();
+                win::STATUS_SUCCESS
+            }
+        },
+        IOCTL_VETH_GET_PEER_STATS => {
+            match wdf_request_retrieve_output_slice::<VEthPeerStats>(request) {
+                Err(status) => status,
+                Ok(peer_stats) => {
+                    // A full buffer tells the caller to retry with a larger one.
+                    let peers = adapter.peers();
+                    let count = peers.len().min(peer_stats.len());
+                    for (entry, peer) in peer_stats.iter_mut().zip(peers.iter()) {
+                        unsafe { ptr::write(entry, ioctl::peer_stats(peer)) };
+                    }
+                    information = count * mem::size_of::<VEthPeerStats>();
+                    win::STATUS_SUCCESS
+                }
+            }
+        }
+        IOCTL_VETH_SET_MAC_ADDR => {
+            match wdf_request_retrieve_input_buffer::<[u8; MAC_ADDR_SIZE]>(request) {
+                Err(status) => status,
+                Ok(mac_addr) => {
+                    if let Err(status) = adapter.set_mac_addr(*mac_addr) {
                         status
                     } else {
                         win::STATUS_SUCCESS
@@ -206,9 +324,71 @@ pub extern "system" fn evt_wdf_io_queue_io_device_control(
                 }
             }
         }
+        IOCTL_VETH_SET_MTU => match wdf_request_retrieve_input_buffer::<u32>(request) {
+            Err(status) => status,
+            Ok(mtu) => {
+                if let Err(status) = adapter.set_mtu(*mtu) {
+                    status
+                } else {
+                    win::STATUS_SUCCESS
+                }
+            }
+        },
+        IOCTL_VETH_GET_ABI_VERSION => match wdf_request_retrieve_output_buffer::<u32>(request) {
+            Err(status) => status,
+            Ok(version) => {
+                *version = ABI_VERSION;
+                information = mem::size_of::<u32>();
+                win::STATUS_SUCCESS
+            }
+        },
+        IOCTL_VETH_SET_FLOOD_POLICY => match wdf_request_retrieve_input_buffer::<u32>(request) {
+            Err(status) => status,
+            Ok(flags) => match FloodPolicy::from_raw(*flags) {
+                None => win::STATUS_INVALID_PARAMETER,
+                Some(policy) => {
+                    adapter.set_flood_policy(policy);
+                    win::STATUS_SUCCESS
+                }
+            },
+        },
+        IOCTL_VETH_SET_MODE => match wdf_request_retrieve_input_buffer::<u32>(request) {
+            Err(status) => status,
+            Ok(mode) => match Mode::from_raw(*mode) {
+                None => win::STATUS_INVALID_PARAMETER,
+                Some(mode) => {
+                    adapter.set_mode(mode);
+                    win::STATUS_SUCCESS
+                }
+            },
+        },
+        IOCTL_VETH_SET_RELAY => match wdf_request_retrieve_input_buffer::<bool>(request) {
+            Err(status) => status,
+            Ok(relay) => {
+                adapter.set_relay(*relay);
+                win::STATUS_SUCCESS
+            }
+        },
+        IOCTL_VETH_GET_FDB => match wdf_request_retrieve_output_slice::<VEthFdbEntry>(request) {
+            Err(status) => status,
+            Ok(fdb_entries) => {
+                // A full buffer tells the caller to retry with a larger one.
+                let count = adapter.fdb().copy_entries(fdb_entries);
+                information = count * mem::size_of::<VEthFdbEntry>();
+                win::STATUS_SUCCESS
+            }
+        },
+        IOCTL_VETH_GET_EVENTS => match adapter.events().hold(request) {
+            Err(status) => status,
+            // Completed along with the events
+            Ok(()) => {
+                trace_exit!("evt_wdf_io_queue_io_device_control");
+                return;
+            }
+        },
         _ => win::STATUS_NOT_SUPPORTED,
     };
 
-    unsafe { win::WdfRequestComplete(request, status) };
+    unsafe { win::WdfRequestCompleteWithInformation(request, status, information) };
     trace_exit_status!("evt_wdf_io_queue_io_device_control", status);
 }
@@ -90,14 +90,36 @@ pub extern "system" fn evt_driver_device_add(
             return status;
         }
         let adapter_handle = unsafe { adapter_handle.assume_init() };
-        let adapter = VEthAdapter::pre_init(adapter_handle);
+        // Holds the GET_EVENTS requests until there are events to complete them with.
+        let events_queue_config = win::WDF_IO_QUEUE_CONFIG_INIT(
+            win::WDF_IO_QUEUE_DISPATCH_TYPE::WdfIoQueueDispatchManual,
+        );
+        let mut events_queue = MaybeUninit::uninit();
+        let status = unsafe {
+            win::WdfIoQueueCreate(
+                device,
+                &events_queue_config,
+                ptr::null(),
+                events_queue.as_mut_ptr(),
+            )
+        };
+        if !win::NT_SUCCESS(status) {
+            trace_exit_status!("WdfIoQueueCreate", status);
+            return status;
+        }
+        let events_queue = unsafe { events_queue.assume_init() };
+        let adapter = VEthAdapter::pre_init(adapter_handle, events_queue);
         VEthAdapterPtr::init(device, adapter);
         let mut queue_config = win::WDF_IO_QUEUE_CONFIG_INIT_DEFAULT_QUEUE(
             win::WDF_IO_QUEUE_DISPATCH_TYPE::WdfIoQueueDispatchSequential,
         );
         queue_config.evt_io_device_control = Some(device::evt_wdf_io_queue_io_device_control);
-        let status =
-            unsafe { win::WdfIoQueueCreate(device, &queue_config, ptr::null(), ptr::null_mut()) };
+        // Some requests block, like rebinding the socket, so they are handled at PASSIVE_LEVEL.
+        let mut queue_attributes = win::WDF_OBJECT_ATTRIBUTES_INIT();
+        queue_attributes.execution_level = win::WDF_EXECUTION_LEVEL::WdfExecutionLevelPassive;
+        let status = unsafe {
+            win::WdfIoQueueCreate(device, &queue_config, &queue_attributes, ptr::null_mut())
+        };
         if !win::NT_SUCCESS(status) {
             trace_exit_status!("WdfIoQueueCreate", status);
             return status;
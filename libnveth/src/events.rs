@@ -0,0 +1,128 @@
+// Events reported to nvnet. They are kept in a ring until nvnet has a GET_EVENTS request held in
+// the manual queue to complete with them; when it falls behind, the oldest are overwritten.
+
+use core::{
+    mem::{self, MaybeUninit},
+    ptr,
+};
+
+use shared::{
+    ioctl::{EventKind, VEthEvent},
+    mac_addr::MAC_ADDR_SIZE,
+};
+
+use crate::{
+    device,
+    net::MacAddr,
+    os::{sync::RwLock, time},
+    peer::Peer,
+    windows::prelude as win,
+};
+
+const RING_SIZE: usize = 256;
+
+struct Ring {
+    events: [VEthEvent; RING_SIZE], // zero-init
+    head: usize,
+    len: usize,
+}
+
+impl Ring {
+    fn push(&mut self, event: VEthEvent) {
+        let tail = (self.head + self.len) % RING_SIZE;
+        self.events[tail] = event;
+        if self.len == RING_SIZE {
+            self.head = (self.head + 1) % RING_SIZE;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    // Moves the oldest events into `buf`, and returns how many.
+    fn pop_into(&mut self, buf: &mut [VEthEvent]) -> usize {
+        let count = self.len.min(buf.len());
+        for entry in &mut buf[..count] {
+            unsafe { ptr::write(entry, self.events[self.head]) };
+            self.head = (self.head + 1) % RING_SIZE;
+        }
+        self.len -= count;
+        count
+    }
+}
+
+pub struct Events {
+    queue: win::WDFQUEUE, // pre-init
+    ring: RwLock<Ring>,
+}
+
+impl Events {
+    pub unsafe fn pre_init(uninit: *mut Self, queue: win::WDFQUEUE) {
+        ptr::raw_mut!((*uninit).queue).write(queue);
+    }
+
+    // The ring is too large for the stack.
+    pub unsafe fn init(uninit: *mut Self) {
+        let ring = RwLock::init(ptr::raw_mut!((*uninit).ring));
+        ptr::raw_mut!((*ring).head).write(0);
+        ptr::raw_mut!((*ring).len).write(0);
+    }
+
+    pub fn post(&self, kind: EventKind, peer: &Peer) {
+        self.push(event(kind, peer));
+    }
+
+    pub fn post_mac_learned(&self, peer: &Peer, mac_addr: &MacAddr) {
+        self.push(VEthEvent {
+            mac_addr: *mac_addr.bytes(),
+            ..event(EventKind::MacLearned, peer)
+        });
+    }
+
+    fn push(&self, event: VEthEvent) {
+        self.ring.write().push(event);
+        self.deliver();
+    }
+
+    // Holds a GET_EVENTS request until there are events to complete it with.
+    pub fn hold(&self, request: win::WDFREQUEST) -> Result<(), win::NTSTATUS> {
+        let status = unsafe { win::WdfRequestForwardToIoQueue(request, self.queue) };
+        if !win::NT_SUCCESS(status) {
+            return Err(status);
+        }
+        self.deliver();
+        Ok(())
+    }
+
+    // Completes the held requests for as long as there are events left.
+    fn deliver(&self) {
+        while self.ring.read().len > 0 {
+            let mut request = MaybeUninit::uninit();
+            let status =
+                unsafe { win::WdfIoQueueRetrieveNextRequest(self.queue, request.as_mut_ptr()) };
+            if !win::NT_SUCCESS(status) {
+                // None held
+                return;
+            }
+            let request = unsafe { request.assume_init() };
+            let (status, information) =
+                match device::wdf_request_retrieve_output_slice::<VEthEvent>(request) {
+                    Err(status) => (status, 0),
+                    Ok(buf) => {
+                        let count = self.ring.write().pop_into(buf);
+                        (win::STATUS_SUCCESS, count * mem::size_of::<VEthEvent>())
+                    }
+                };
+            unsafe { win::WdfRequestCompleteWithInformation(request, status, information) };
+        }
+    }
+}
+
+fn event(kind: EventKind, peer: &Peer) -> VEthEvent {
+    VEthEvent {
+        kind: kind.as_raw(),
+        public_key: peer.public_key,
+        socket_addr: peer.endpoint().addr,
+        mac_addr: [0; MAC_ADDR_SIZE],
+        time: time::system_time(),
+    }
+}
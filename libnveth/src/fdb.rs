@@ -0,0 +1,74 @@
+// The driver's side of `shared::fdb`: the RX worker learns where the sources of frames are, and the
+// TX worker looks up where to send them.
+
+use core::ptr;
+
+use shared::fdb::{self, AGING_TIME};
+
+use crate::{
+    crypto,
+    handshake::KEY_SIZE,
+    ioctl::{self, VEthFdbEntry},
+    net::MacAddr,
+    os::{sync::RwLock, time::Instant},
+    peer::Peer,
+    windows::prelude as win,
+};
+
+const FDB_SIZE: usize = 1024;
+
+pub struct Fdb(RwLock<fdb::Fdb<FDB_SIZE>>);
+
+impl Fdb {
+    pub unsafe fn init(uninit: *mut Self) -> Result<(), win::NTSTATUS> {
+        let mut seed = [0; 8];
+        crypto::gen_random(&mut seed)?;
+
+        // The table is too large for the stack.
+        let table = RwLock::init(ptr::raw_mut!((*uninit).0));
+        fdb::Fdb::<FDB_SIZE>::init(&mut *table.cast(), u64::from_ne_bytes(seed));
+        Ok(())
+    }
+
+    // Returns whether the address was not known to be behind the peer.
+    pub fn learn(&self, mac_addr: &MacAddr, peer: &Peer) -> bool {
+        let now = Instant::now().as_duration();
+        let fdb = self.0.read();
+        // Most frames come from sources that are already known, which only needs refreshing now and
+        // then.
+        if let Some(entry) = fdb.lookup(mac_addr.bytes(), now) {
+            if entry.public_key == peer.public_key
+                && now.saturating_sub(entry.last_seen) < AGING_TIME / 10
+            {
+                return false;
+            }
+        }
+        drop(fdb);
+        self.0
+            .write()
+            .learn(mac_addr.bytes(), &peer.public_key, now)
+    }
+
+    // The public key of the peer that `mac_addr` is behind
+    pub fn lookup(&self, mac_addr: &[u8; 6]) -> Option<[u8; KEY_SIZE]> {
+        let now = Instant::now().as_duration();
+        let entry = self.0.read().lookup(mac_addr, now)?;
+        Some(entry.public_key)
+    }
+
+    pub fn remove_peer(&self, public_key: &[u8; KEY_SIZE]) {
+        self.0.write().remove_peer(public_key);
+    }
+
+    // Returns the number of entries written.
+    pub fn copy_entries(&self, buf: &mut [VEthFdbEntry]) -> usize {
+        let now = Instant::now().as_duration();
+        let fdb = self.0.read();
+        let mut count = 0;
+        for (slot, entry) in buf.iter_mut().zip(fdb.entries(now)) {
+            unsafe { ptr::write(slot, ioctl::fdb_entry(&entry)) };
+            count += 1;
+        }
+        count
+    }
+}
@@ -0,0 +1,543 @@
+// A Noise IK handshake: the initiator already knows the responder's static key, and the two
+// messages below establish one pair of per-direction session keys.
+//
+//   -> e, es, s, ss, {timestamp}
+//   <- e, ee, se, {}, psk, {}
+//
+// Every DH result is folded into an HKDF chaining key, and every message field into a transcript
+// hash that authenticates the encrypted fields as associated data. Both use the configured hash.
+//
+// The peer's pre-shared key, all zeros when none is configured, is mixed in last. The first empty
+// payload authenticates the response without it, so that the initiator can tell a wrong
+// pre-shared key from a forged response.
+//
+// Both messages end with the MACs of `shared::cookie`, which the recipient checks before any DH
+// computation.
+
+use alloc::sync::Arc;
+
+use core::{mem, slice, time::Duration};
+
+use shared::{
+    cookie,
+    kdf::{KeySchedule, MAX_HASH_SIZE},
+    nonce::{self, ReplayWindow, SendCounter},
+    session::SessionKeypair,
+    wire::{self, MessageType},
+};
+
+use crate::{
+    crypto::{
+        self,
+        aead::{self, Aead, Cipher, CipherSuite},
+        ecdh::{Ecdh, EcdhPubKey},
+        hash::{Hash, HashAlgorithm},
+    },
+    os::{
+        sync::RwLock,
+        time::{self, Instant},
+    },
+    peer::Peer,
+    windows::prelude as win,
+};
+
+pub const KEY_SIZE: usize = Ecdh::KEY_SIZE32;
+pub const TIMESTAMP_SIZE: usize = wire::TIMESTAMP_SIZE;
+
+pub const REKEY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const PROTOCOL_NAME: &[u8] = b"Noise_IKpsk2_25519_nvnet_v1";
+
+// The response was authentic, but the peers do not share the same pre-shared key.
+pub const STATUS_PSK_MISMATCH: win::NTSTATUS = win::STATUS_WRONG_PASSWORD;
+
+// Every handshake key encrypts exactly one message.
+const ZERO_NONCE: [u8; aead::NONCE_SIZE] = [0; aead::NONCE_SIZE];
+
+#[repr(C)]
+pub struct VEthHandshakeInit {
+    pub header: [u8; wire::HEADER_SIZE],
+    pub sender_index: u32,
+    pub cipher_suite: u32,
+    pub hash: u32,
+    pub ephemeral: [u8; KEY_SIZE],
+    pub static_key: [u8; KEY_SIZE],
+    pub static_tag: [u8; aead::TAG_SIZE],
+    pub timestamp: [u8; TIMESTAMP_SIZE],
+    pub timestamp_tag: [u8; aead::TAG_SIZE],
+    pub mac1: [u8; wire::MAC_SIZE],
+    pub mac2: [u8; wire::MAC_SIZE],
+}
+
+#[repr(C)]
+pub struct VEthHandshakeResponse {
+    pub header: [u8; wire::HEADER_SIZE],
+    pub sender_index: u32,
+    pub ephemeral: [u8; KEY_SIZE],
+    pub empty_tag: [u8; aead::TAG_SIZE],
+    pub psk_tag: [u8; aead::TAG_SIZE],
+    pub mac1: [u8; wire::MAC_SIZE],
+    pub mac2: [u8; wire::MAC_SIZE],
+}
+
+const _: [(); wire::HEADER_SIZE + wire::HANDSHAKE_INIT_SIZE] =
+    [(); mem::size_of::<VEthHandshakeInit>()];
+const _: [(); wire::HEADER_SIZE + wire::HANDSHAKE_RESPONSE_SIZE] =
+    [(); mem::size_of::<VEthHandshakeResponse>()];
+
+// The bytes of a message, e.g. to compute its MACs.
+pub fn as_bytes<T>(msg: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts((msg as *const T).cast(), mem::size_of::<T>()) }
+}
+
+fn as_bytes_mut<T>(msg: &mut T) -> &mut [u8] {
+    unsafe { slice::from_raw_parts_mut((msg as *mut T).cast(), mem::size_of::<T>()) }
+}
+
+pub struct LocalKey {
+    private_key: Ecdh,
+    public_key: [u8; KEY_SIZE],
+    cipher_suite: CipherSuite,
+    hash: Hash,
+    mac1_key: [u8; MAX_HASH_SIZE],
+    cookie_cipher: Cipher,
+}
+
+impl LocalKey {
+    pub fn import(
+        private_key: &[u8; KEY_SIZE],
+        cipher_suite: CipherSuite,
+        hash: HashAlgorithm,
+    ) -> Result<Self, win::NTSTATUS> {
+        let private_key = Ecdh::import(private_key)?;
+        let public_key = private_key.export_public_key()?;
+        let hash = Hash::new(hash)?;
+        let mac1_key = cookie::mac1_key(&hash, &public_key)?;
+        let cookie_cipher = Cipher::new(cipher_suite, &cookie::cookie_key(&hash, &public_key)?)?;
+        Ok(Self {
+            private_key,
+            public_key,
+            cipher_suite,
+            hash,
+            mac1_key,
+            cookie_cipher,
+        })
+    }
+
+    pub fn public_key(&self) -> &[u8; KEY_SIZE] {
+        &self.public_key
+    }
+
+    pub fn cipher_suite(&self) -> CipherSuite {
+        self.cipher_suite
+    }
+
+    pub fn hash(&self) -> &Hash {
+        &self.hash
+    }
+
+    // Whether a handshake message was sent by someone who knows our public key.
+    pub fn check_mac1(&self, msg: &[u8]) -> Result<bool, win::NTSTATUS> {
+        cookie::check_mac1(&self.hash, &self.mac1_key, msg)
+    }
+
+    // Encrypts the cookie replies we send.
+    pub fn cookie_cipher(&self) -> &Cipher {
+        &self.cookie_cipher
+    }
+}
+
+// The initiator's half of a handshake that is waiting for a response.
+pub struct Initiation {
+    local_index: u32,
+    cipher_suite: CipherSuite,
+    ephemeral: Ecdh,
+    schedule: KeySchedule,
+    sent_at: Instant,
+}
+
+impl Initiation {
+    pub fn local_index(&self) -> u32 {
+        self.local_index
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.sent_at.elapsed() >= REKEY_TIMEOUT
+    }
+}
+
+// The responder's view of a validated initiation.
+pub struct Responder {
+    remote_index: u32,
+    cipher_suite: CipherSuite,
+    remote_ephemeral: EcdhPubKey,
+    schedule: KeySchedule,
+    timestamp: [u8; TIMESTAMP_SIZE],
+}
+
+pub struct Keypair {
+    pub local_index: u32,
+    pub remote_index: u32,
+    cipher_suite: CipherSuite,
+    pub send: Cipher,
+    pub recv: Cipher,
+    send_counter: SendCounter,
+    replay_window: RwLock<ReplayWindow>,
+}
+
+impl Keypair {
+    pub fn next_nonce(&self) -> Option<[u8; aead::NONCE_SIZE]> {
+        self.send_counter.next().map(nonce::encode)
+    }
+
+    pub fn check_replay(&self, counter: u64) -> bool {
+        self.replay_window.write().check(counter)
+    }
+}
+
+impl SessionKeypair for Keypair {
+    fn local_index(&self) -> u32 {
+        self.local_index
+    }
+
+    fn cipher_suite(&self) -> CipherSuite {
+        self.cipher_suite
+    }
+
+    fn sent_messages(&self) -> u64 {
+        self.send_counter.get()
+    }
+}
+
+fn new_index() -> Result<u32, win::NTSTATUS> {
+    let mut index = [0; 4];
+    crypto::gen_random(&mut index)?;
+    Ok(u32::from_ne_bytes(index))
+}
+
+fn timestamp() -> [u8; TIMESTAMP_SIZE] {
+    let mut timestamp = [0; TIMESTAMP_SIZE];
+    timestamp[..8].copy_from_slice(&time::system_time().to_be_bytes());
+    timestamp
+}
+
+// Binds the negotiated algorithms and the responder's identity before anything is sent.
+fn new_schedule(
+    local: &LocalKey,
+    responder_key: &[u8; KEY_SIZE],
+) -> Result<KeySchedule, win::NTSTATUS> {
+    let hash = &local.hash;
+    let mut schedule = KeySchedule::new(hash, PROTOCOL_NAME)?;
+    let mut prologue = [0; 8];
+    prologue[..4].copy_from_slice(&local.cipher_suite.as_raw().to_le_bytes());
+    prologue[4..].copy_from_slice(&hash.algorithm().as_raw().to_le_bytes());
+    schedule.mix_hash(hash, &prologue)?;
+    schedule.mix_hash(hash, responder_key)?;
+    Ok(schedule)
+}
+
+fn mix_dh(
+    schedule: &mut KeySchedule,
+    hash: &Hash,
+    local: &Ecdh,
+    remote: &EcdhPubKey,
+) -> Result<[u8; MAX_HASH_SIZE], win::NTSTATUS> {
+    let secret = local.agree(remote)?;
+    // A low-order public key forces an all-zero secret. Checked without an early exit.
+    if secret.iter().fold(0, |acc, &b| acc | b) == 0 {
+        return Err(win::STATUS_INVALID_PARAMETER);
+    }
+    schedule.mix_key(hash, &secret)
+}
+
+// Encrypts with the transcript as associated data, then adds the ciphertext to the transcript.
+fn encrypt_and_hash(
+    schedule: &mut KeySchedule,
+    hash: &Hash,
+    cipher: &Cipher,
+    text: &mut [u8],
+    tag: &mut [u8; aead::TAG_SIZE],
+) -> Result<(), win::NTSTATUS> {
+    cipher.encrypt(&ZERO_NONCE, schedule.transcript(), text, tag)?;
+    schedule.mix_hash(hash, text)?;
+    schedule.mix_hash(hash, tag)
+}
+
+fn decrypt_and_hash(
+    schedule: &mut KeySchedule,
+    hash: &Hash,
+    cipher: &Cipher,
+    text: &mut [u8],
+    tag: &[u8; aead::TAG_SIZE],
+) -> Result<(), win::NTSTATUS> {
+    let mut ciphertext = [0; KEY_SIZE];
+    let ciphertext = &mut ciphertext[..text.len()];
+    ciphertext.copy_from_slice(text);
+    cipher.decrypt(&ZERO_NONCE, schedule.transcript(), text, tag)?;
+    schedule.mix_hash(hash, ciphertext)?;
+    schedule.mix_hash(hash, tag)
+}
+
+// Remembers MAC1, which authenticates the cookie reply the peer may answer with.
+fn write_macs(local: &LocalKey, peer: &Peer, msg: &mut [u8]) -> Result<(), win::NTSTATUS> {
+    let key = cookie::mac1_key(&local.hash, &peer.public_key)?;
+    let peer_cookie = peer.cookie.read().cookie();
+    let mac1 = cookie::write_macs(&local.hash, &key, peer_cookie.as_ref(), msg)?;
+    peer.cookie.write().sent(mac1);
+    Ok(())
+}
+
+fn new_keypair(
+    local: &LocalKey,
+    suite: CipherSuite,
+    schedule: &KeySchedule,
+    initiator: bool,
+    local_index: u32,
+    remote_index: u32,
+) -> Result<Keypair, win::NTSTATUS> {
+    let keys = schedule.split(&local.hash, initiator)?;
+    Ok(Keypair {
+        local_index,
+        remote_index,
+        cipher_suite: suite,
+        send: Cipher::new(suite, &keys.send)?,
+        recv: Cipher::new(suite, &keys.recv)?,
+        send_counter: SendCounter::new(),
+        replay_window: RwLock::new(ReplayWindow::new()),
+    })
+}
+
+pub fn create_initiation(
+    local: &LocalKey,
+    peer: &Peer,
+    msg: &mut VEthHandshakeInit,
+) -> Result<Initiation, win::NTSTATUS> {
+    let hash = &local.hash;
+    let suite = local.cipher_suite;
+    let local_index = new_index()?;
+    let ephemeral = Ecdh::new()?;
+    let mut schedule = new_schedule(local, &peer.public_key)?;
+
+    msg.header = wire::Header::new(MessageType::HandshakeInit, 0).encode();
+    msg.sender_index = local_index.to_le();
+    msg.cipher_suite = suite.as_raw().to_le();
+    msg.hash = hash.algorithm().as_raw().to_le();
+
+    // e
+    msg.ephemeral = ephemeral.export_public_key()?;
+    schedule.mix_hash(hash, &msg.ephemeral)?;
+
+    // es, s
+    let key = mix_dh(&mut schedule, hash, &ephemeral, &peer.static_key)?;
+    msg.static_key = local.public_key;
+    encrypt_and_hash(
+        &mut schedule,
+        hash,
+        &Cipher::new(suite, &key)?,
+        &mut msg.static_key,
+        &mut msg.static_tag,
+    )?;
+
+    // ss, {timestamp}
+    let key = mix_dh(&mut schedule, hash, &local.private_key, &peer.static_key)?;
+    msg.timestamp = timestamp();
+    encrypt_and_hash(
+        &mut schedule,
+        hash,
+        &Cipher::new(suite, &key)?,
+        &mut msg.timestamp,
+        &mut msg.timestamp_tag,
+    )?;
+
+    write_macs(local, peer, as_bytes_mut(msg))?;
+
+    Ok(Initiation {
+        local_index,
+        cipher_suite: suite,
+        ephemeral,
+        schedule,
+        sent_at: Instant::now(),
+    })
+}
+
+pub fn consume_initiation<'a>(
+    local: &LocalKey,
+    peers: &'a [Arc<Peer>],
+    msg: &VEthHandshakeInit,
+) -> Result<(&'a Peer, Responder), win::NTSTATUS> {
+    // Both ends must be configured with the same cipher suite and hash.
+    let hash = &local.hash;
+    let suite = local.cipher_suite;
+    if u32::from_le(msg.cipher_suite) != suite.as_raw()
+        || u32::from_le(msg.hash) != hash.algorithm().as_raw()
+    {
+        return Err(win::STATUS_NOT_SUPPORTED);
+    }
+    let mut schedule = new_schedule(local, &local.public_key)?;
+
+    // e
+    let remote_ephemeral = EcdhPubKey::import(&msg.ephemeral)?;
+    schedule.mix_hash(hash, &msg.ephemeral)?;
+
+    // es, s
+    let key = mix_dh(&mut schedule, hash, &local.private_key, &remote_ephemeral)?;
+    let mut static_key = msg.static_key;
+    decrypt_and_hash(
+        &mut schedule,
+        hash,
+        &Cipher::new(suite, &key)?,
+        &mut static_key,
+        &msg.static_tag,
+    )?;
+    let peer: &Peer = peers
+        .iter()
+        .find(|peer| peer.public_key == static_key)
+        .ok_or(win::STATUS_NOT_FOUND)?;
+
+    // ss, {timestamp}
+    let key = mix_dh(&mut schedule, hash, &local.private_key, &peer.static_key)?;
+    let mut timestamp = msg.timestamp;
+    decrypt_and_hash(
+        &mut schedule,
+        hash,
+        &Cipher::new(suite, &key)?,
+        &mut timestamp,
+        &msg.timestamp_tag,
+    )?;
+
+    // Reject replayed initiations. The timestamp is only committed once the initiation is
+    // answered.
+    if timestamp <= *peer.last_timestamp.read() {
+        return Err(win::STATUS_INVALID_PARAMETER);
+    }
+
+    Ok((
+        peer,
+        Responder {
+            remote_index: u32::from_le(msg.sender_index),
+            cipher_suite: suite,
+            remote_ephemeral,
+            schedule,
+            timestamp,
+        },
+    ))
+}
+
+pub fn create_response(
+    local: &LocalKey,
+    peer: &Peer,
+    responder: Responder,
+    msg: &mut VEthHandshakeResponse,
+) -> Result<Keypair, win::NTSTATUS> {
+    let hash = &local.hash;
+    let suite = responder.cipher_suite;
+    let mut schedule = responder.schedule;
+    let local_index = new_index()?;
+    let ephemeral = Ecdh::new()?;
+
+    msg.header = wire::Header::new(MessageType::HandshakeResponse, responder.remote_index).encode();
+    msg.sender_index = local_index.to_le();
+
+    // e
+    msg.ephemeral = ephemeral.export_public_key()?;
+    schedule.mix_hash(hash, &msg.ephemeral)?;
+
+    // ee
+    mix_dh(&mut schedule, hash, &ephemeral, &responder.remote_ephemeral)?;
+
+    // se, {}
+    let key = mix_dh(&mut schedule, hash, &ephemeral, &peer.static_key)?;
+    encrypt_and_hash(
+        &mut schedule,
+        hash,
+        &Cipher::new(suite, &key)?,
+        &mut [],
+        &mut msg.empty_tag,
+    )?;
+
+    // psk, {}
+    let key = schedule.mix_key_and_hash(hash, &peer.preshared_key)?;
+    encrypt_and_hash(
+        &mut schedule,
+        hash,
+        &Cipher::new(suite, &key)?,
+        &mut [],
+        &mut msg.psk_tag,
+    )?;
+
+    write_macs(local, peer, as_bytes_mut(msg))?;
+
+    let keypair = new_keypair(
+        local,
+        suite,
+        &schedule,
+        false,
+        local_index,
+        responder.remote_index,
+    )?;
+    let replayed = {
+        let mut last_timestamp = peer.last_timestamp.write();
+        let replayed = responder.timestamp <= *last_timestamp;
+        if !replayed {
+            *last_timestamp = responder.timestamp;
+        }
+        replayed
+    };
+    if replayed {
+        return Err(win::STATUS_INVALID_PARAMETER);
+    }
+    Ok(keypair)
+}
+
+pub fn consume_response(
+    local: &LocalKey,
+    peer: &Peer,
+    initiation: &Initiation,
+    msg: &VEthHandshakeResponse,
+) -> Result<Keypair, win::NTSTATUS> {
+    let hash = &local.hash;
+    let suite = initiation.cipher_suite;
+    let mut schedule = initiation.schedule.clone();
+
+    // e
+    let remote_ephemeral = EcdhPubKey::import(&msg.ephemeral)?;
+    schedule.mix_hash(hash, &msg.ephemeral)?;
+
+    // ee
+    mix_dh(
+        &mut schedule,
+        hash,
+        &initiation.ephemeral,
+        &remote_ephemeral,
+    )?;
+
+    // se, {}
+    let key = mix_dh(&mut schedule, hash, &local.private_key, &remote_ephemeral)?;
+    decrypt_and_hash(
+        &mut schedule,
+        hash,
+        &Cipher::new(suite, &key)?,
+        &mut [],
+        &msg.empty_tag,
+    )?;
+
+    // psk, {}
+    let key = schedule.mix_key_and_hash(hash, &peer.preshared_key)?;
+    decrypt_and_hash(
+        &mut schedule,
+        hash,
+        &Cipher::new(suite, &key)?,
+        &mut [],
+        &msg.psk_tag,
+    )
+    .map_err(|_| STATUS_PSK_MISMATCH)?;
+
+    new_keypair(
+        local,
+        suite,
+        &schedule,
+        true,
+        initiation.local_index,
+        u32::from_le(msg.sender_index),
+    )
+}
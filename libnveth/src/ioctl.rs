@@ -1,15 +1,83 @@
-use crate::windows::km::ntddk::{CTL_CODE, FILE_ANY_ACCESS, FILE_DEVICE_NETWORK, METHOD_BUFFERED};
+// The driver's side of `shared::ioctl`: checks the requests, and fills in the responses from the
+// adapter's state.
 
-const fn veth_ctl_code(function_index: u32) -> u32 {
-    CTL_CODE(
-        FILE_DEVICE_NETWORK,
-        function_index + 0x800,
-        METHOD_BUFFERED,
-        FILE_ANY_ACCESS,
-    )
+use core::sync::atomic::Ordering::Relaxed;
+
+pub use shared::ioctl::*;
+
+use shared::{fdb::FdbEntry, routing::Prefix};
+
+use crate::{
+    os::time,
+    peer::Peer,
+    stats::{AdapterStats, Traffic},
+};
+
+// The prefixes in use, if they are all valid
+pub fn allowed_ips(remote_peer: &VEthRemotePeer) -> Option<&[VEthPrefix]> {
+    let allowed_ips = remote_peer
+        .allowed_ips
+        .get(..remote_peer.allowed_ips_count as usize)?;
+    if !allowed_ips
+        .iter()
+        .all(|prefix| Prefix::from_raw(prefix).is_some())
+    {
+        return None;
+    }
+    Some(allowed_ips)
 }
 
-pub const IOCTL_VETH_SET_CONNECT_STATE: u32 = veth_ctl_code(0);
-pub const IOCTL_VETH_SET_LOCAL_ADDR: u32 = veth_ctl_code(1);
-pub const IOCTL_VETH_ADD_REMOTE_PEER: u32 = veth_ctl_code(2);
-pub const IOCTL_VETH_SET_DISCONNECT_ON_CLOSE: u32 = veth_ctl_code(3);
+pub fn peer_status(peer: &Peer) -> VEthPeerStatus {
+    let state = peer.state();
+    let endpoint = peer.endpoint();
+    let liveness = peer.liveness.read();
+    let system_time = |time: Option<_>| time.map_or(0, time::system_time_at);
+    VEthPeerStatus {
+        socket_addr: endpoint.addr,
+        public_key: peer.public_key,
+        state: state as _,
+        persistent_keepalive: liveness
+            .persistent_keepalive()
+            .map_or(0, |interval| interval.as_secs() as _),
+        last_handshake: system_time(liveness.last_handshake()),
+        last_received: system_time(liveness.last_received()),
+        roams: endpoint.roams,
+        last_roamed: system_time(endpoint.last_roamed),
+    }
+}
+
+fn traffic(traffic: &Traffic) -> VEthTraffic {
+    VEthTraffic {
+        tx_packets: traffic.tx_packets.load(Relaxed),
+        tx_bytes: traffic.tx_bytes.load(Relaxed),
+        tx_failures: traffic.tx_failures.load(Relaxed),
+        rx_packets: traffic.rx_packets.load(Relaxed),
+        rx_bytes: traffic.rx_bytes.load(Relaxed),
+        mac_learned: traffic.mac_learned.load(Relaxed),
+    }
+}
+
+pub fn stats(stats: &AdapterStats) -> VEthStats {
+    VEthStats {
+        traffic: traffic(&stats.traffic),
+        rx_drops: stats.rx_drops.load(),
+    }
+}
+
+pub fn fdb_entry(entry: &FdbEntry) -> VEthFdbEntry {
+    VEthFdbEntry {
+        public_key: entry.public_key,
+        mac_addr: entry.mac_addr,
+        last_seen: time::system_time_at(entry.last_seen),
+    }
+}
+
+pub fn peer_stats(peer: &Peer) -> VEthPeerStats {
+    VEthPeerStats {
+        public_key: peer.public_key,
+        traffic: traffic(&peer.traffic),
+        auth_failures: peer.auth_failures.load(Relaxed),
+        replays: peer.replays.load(Relaxed),
+        psk_failures: peer.psk_failures.load(Relaxed),
+    }
+}
@@ -19,19 +19,25 @@ mod macros;
 
 mod adapter;
 mod allocator;
+mod cookie;
 mod crypto;
 mod device;
 mod driver;
+mod events;
+mod fdb;
+mod handshake;
 mod init;
 mod ioctl;
-mod list;
 mod net;
 mod os;
 mod panic;
 mod peer;
+mod proxy;
 mod recv;
+mod routing;
 mod send;
 mod socket;
+mod stats;
 mod windows;
 mod worker;
 
@@ -41,20 +47,16 @@ use core::{mem, ptr};
 
 use libnveth_macros::*;
 
-use crate::{adapter::VEthCipherFrameHeader, socket::UdpSocket, windows::prelude as win};
+use shared::{
+    mtu,
+    provider::{self, SelfTestError},
+};
 
-// 1418
-const PLAIN_FRAME_DATA_SIZE: u32 = {
-    const MAX_ETH_MTU_SIZE: u32 = 1500;
-    const MAX_IP_HEADER_SIZE: u32 = 60;
-    const MAX_UDP_HEADER_SIZE: u32 = 8;
-    const MAX_ETH_HEADER_SIZE: u32 = 14;
-    MAX_ETH_MTU_SIZE - MAX_IP_HEADER_SIZE - MAX_UDP_HEADER_SIZE - MAX_ETH_HEADER_SIZE
+use crate::{
+    crypto::provider::BCryptProvider, net::EthHeader, socket::UdpSocket, windows::prelude as win,
 };
 
-// 1390
-const CIPHER_FRAME_DATA_SIZE: u32 =
-    PLAIN_FRAME_DATA_SIZE - mem::size_of::<VEthCipherFrameHeader>() as u32;
+const _: [(); mtu::ETH_HEADER_SIZE as _] = [(); mem::size_of::<EthHeader>()];
 
 const LINK_SPEED: u64 = 10_000_000_000; // 10.0 Gbps
 
@@ -82,6 +84,18 @@ pub extern "system" fn DriverEntry(
             trace_exit_status!("WdfDriverCreate", status);
             return status;
         }
+        // The peers only interoperate if BCrypt agrees with the specifications' test vectors.
+        match provider::self_test(&BCryptProvider) {
+            Ok(()) => {}
+            Err(SelfTestError::Provider(status)) => {
+                trace_exit_status!("self_test", status);
+                return status;
+            }
+            Err(SelfTestError::Mismatch(name)) => {
+                trace_println!("self test failed: %.*s", name.len() as u32, name.as_ptr());
+                return win::STATUS_CRYPTO_SYSTEM_INVALID;
+            }
+        }
         if let Err(status) = UdpSocket::register() {
             return status;
         }
@@ -1,4 +1,6 @@
-use crate::windows::km::xfilter::{ETH_IS_BROADCAST, ETH_IS_MULTICAST};
+use core::{mem, slice};
+
+use shared::routing;
 
 #[repr(transparent)]
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -12,13 +14,11 @@ impl MacAddr {
     pub fn bytes(&self) -> &[u8; 6] {
         &self.0
     }
+}
 
-    pub fn is_broadcast(&self) -> bool {
-        ETH_IS_BROADCAST(&self.0)
-    }
-
-    pub fn is_multicast(&self) -> bool {
-        ETH_IS_MULTICAST(&self.0)
+impl From<[u8; 6]> for MacAddr {
+    fn from(bytes: [u8; 6]) -> Self {
+        Self(bytes)
     }
 }
 
@@ -39,19 +39,47 @@ impl IpAddr {
 }
 
 #[repr(transparent)]
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Ipv4Addr([u8; 4]);
 
 impl Ipv4Addr {
     pub fn is_unspecified(&self) -> bool {
         self.0 == [0; 4]
     }
+
+    pub fn bytes(&self) -> &[u8; 4] {
+        &self.0
+    }
 }
 
 #[repr(transparent)]
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Ipv6Addr([u8; 16]);
 
+impl Ipv6Addr {
+    pub fn is_unspecified(&self) -> bool {
+        self.0 == [0; 16]
+    }
+
+    pub fn bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+const ETHER_TYPE_ARP: u16 = 0x0806;
+const ARP_REQUEST: [u8; 2] = [0x00, 0x01];
+const ARP_REPLY: [u8; 2] = [0x00, 0x02];
+
+const IPPROTO_ICMPV6: u8 = 0x3a;
+// Neighbor discovery messages from off-link are discarded.
+const ND_HOP_LIMIT: u8 = 255;
+const NA_SOLICITED: u8 = 0x40;
+const NA_OVERRIDE: u8 = 0x20;
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts((value as *const T).cast(), mem::size_of::<T>()) }
+}
+
 #[repr(C)]
 pub struct EthHeader {
     dst_addr: MacAddr,
@@ -60,8 +88,16 @@ pub struct EthHeader {
 }
 
 impl EthHeader {
-    pub fn dst(&self) -> &MacAddr {
-        &self.dst_addr
+    pub fn new(dst_addr: MacAddr, src_addr: MacAddr, eth_type: u16) -> Self {
+        Self {
+            dst_addr,
+            src_addr,
+            eth_type: eth_type.to_be_bytes(),
+        }
+    }
+
+    pub fn src(&self) -> &MacAddr {
+        &self.src_addr
     }
 
     pub fn is_arp(&self) -> bool {
@@ -74,6 +110,7 @@ impl EthHeader {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct ArpPacket {
     hardware_type: [u8; 2],
     protocol_type: [u8; 2],
@@ -102,6 +139,10 @@ impl ArpPacket {
     pub fn src_ipv4(&self) -> &Ipv4Addr {
         &self.src_protocol_addr
     }
+
+    pub fn dst_ipv4(&self) -> &Ipv4Addr {
+        &self.dst_protocol_addr
+    }
 }
 
 #[repr(C)]
@@ -110,6 +151,31 @@ pub struct Layer2ArpPacket {
     pub arp: ArpPacket,
 }
 
+impl Layer2ArpPacket {
+    pub fn is_request(&self) -> bool {
+        self.eth.is_arp()
+            && self.arp.is_eth()
+            && self.arp.is_ipv4()
+            && self.arp.operation == ARP_REQUEST
+    }
+
+    // The reply of the host that has the requested address, at `owner_mac`
+    pub fn reply(&self, owner_mac: MacAddr) -> Self {
+        let arp = &self.arp;
+        Self {
+            eth: EthHeader::new(arp.src_hardware_addr, owner_mac, ETHER_TYPE_ARP),
+            arp: ArpPacket {
+                operation: ARP_REPLY,
+                src_hardware_addr: owner_mac,
+                src_protocol_addr: arp.dst_protocol_addr,
+                dst_hardware_addr: arp.src_hardware_addr,
+                dst_protocol_addr: arp.src_protocol_addr,
+                ..*arp
+            },
+        }
+    }
+}
+
 #[repr(C)]
 pub struct Ipv6Header {
     unused: [u8; 4],
@@ -122,7 +188,34 @@ pub struct Ipv6Header {
 
 impl Ipv6Header {
     pub fn is_icmpv6(&self) -> bool {
-        self.next_header == 0x3a
+        self.next_header == IPPROTO_ICMPV6
+    }
+
+    pub fn src(&self) -> &Ipv6Addr {
+        &self.src_addr
+    }
+
+    // Over the pseudo-header and `message`, in network byte order like the field
+    fn icmpv6_checksum(&self, message: &[u8]) -> u16 {
+        let len = (message.len() as u32).to_be_bytes();
+        let next_header = [0, 0, 0, self.next_header];
+        let bytes = self.src_addr.0.iter().chain(&self.dst_addr.0);
+        let bytes = bytes.chain(&len).chain(&next_header).chain(message);
+        let mut sum = 0u32;
+        let mut high = None;
+        for &byte in bytes {
+            match high.take() {
+                None => high = Some(byte),
+                Some(high) => sum += u32::from(u16::from_be_bytes([high, byte])),
+            }
+        }
+        if let Some(high) = high {
+            sum += u32::from(high) << 8;
+        }
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        (!(sum as u16)).to_be()
     }
 }
 
@@ -177,6 +270,19 @@ pub struct L2Icmpv6NsHeader {
     pub icmpv6_ns: Icmpv6NsHeader,
 }
 
+impl L2Icmpv6NsHeader {
+    pub fn is_solicitation(&self) -> bool {
+        self.eth.is_ipv6()
+            && self.ipv6.is_icmpv6()
+            && self.ipv6.hop_limit == ND_HOP_LIMIT
+            && self.icmpv6_ns.header.is_neighbor_solicitation()
+    }
+
+    pub fn target(&self) -> &Ipv6Addr {
+        &self.icmpv6_ns.target_addr
+    }
+}
+
 #[repr(C)]
 pub struct Icmpv6NaHeader {
     header: Icmpv6Header,
@@ -203,3 +309,37 @@ pub struct L2Icmpv6NaHeader {
     pub ipv6: Ipv6Header,
     pub icmpv6_na: Icmpv6NaHeader,
 }
+
+impl L2Icmpv6NaHeader {
+    // The solicited advertisement of the host that has the target address, at `owner_mac`
+    pub fn reply(ns: &L2Icmpv6NsHeader, owner_mac: MacAddr) -> Self {
+        let target_addr = ns.icmpv6_ns.target_addr;
+        let ipv6 = Ipv6Header {
+            // Version 6
+            unused: [0x60, 0, 0, 0],
+            payload_len: (mem::size_of::<Icmpv6NaHeader>() as u16).to_be(),
+            next_header: IPPROTO_ICMPV6,
+            hop_limit: ND_HOP_LIMIT,
+            src_addr: target_addr,
+            dst_addr: ns.ipv6.src_addr,
+        };
+        let mut icmpv6_na = Icmpv6NaHeader {
+            header: Icmpv6Header {
+                r#type: 136,
+                code: 0,
+                checksum: 0,
+            },
+            unused: [NA_SOLICITED | NA_OVERRIDE, 0, 0, 0],
+            target_addr,
+            opt_type: 2,
+            opt_len: 1,
+            target_mac_addr: owner_mac,
+        };
+        icmpv6_na.header.checksum = ipv6.icmpv6_checksum(as_bytes(&icmpv6_na));
+        Self {
+            eth: EthHeader::new(ns.eth.src_addr, owner_mac, routing::ETHER_TYPE_IPV6),
+            ipv6,
+            icmpv6_na,
+        }
+    }
+}
@@ -1,14 +1,17 @@
-use core::ptr;
+use core::{ptr, time::Duration};
 
 use crate::windows::{
     km::{
         ntddk::IO_NO_INCREMENT,
         wdm::{
-            KeInitializeEvent, KeSetEvent, KeWaitForSingleObject, KEVENT, KPROCESSOR_MODE,
-            KWAIT_REASON,
+            KeInitializeEvent, KeSetEvent, KeWaitForMultipleObjects, KeWaitForSingleObject, KEVENT,
+            KPROCESSOR_MODE, KWAIT_REASON, WAIT_TYPE,
         },
     },
-    shared::{ntdef::EVENT_TYPE, ntstatus::STATUS_SUCCESS},
+    shared::{
+        ntdef::EVENT_TYPE,
+        ntstatus::{STATUS_SUCCESS, STATUS_TIMEOUT, STATUS_WAIT_1},
+    },
 };
 
 pub struct AutoEvent(KEVENT);
@@ -35,6 +38,52 @@ impl AutoEvent {
         assert_eq!(status, STATUS_SUCCESS);
     }
 
+    // Returns whether the event was set before the timeout elapsed.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        // Negative values are relative, in 100-nanosecond units.
+        let timeout = -((timeout.as_nanos() / 100) as i64);
+        let status = unsafe {
+            KeWaitForSingleObject(
+                &self.0 as *const _ as *mut _,
+                KWAIT_REASON::Executive,
+                KPROCESSOR_MODE::KernelMode,
+                false,
+                &timeout as *const i64 as *const _,
+            )
+        };
+        if status == STATUS_TIMEOUT {
+            return false;
+        }
+        assert_eq!(status, STATUS_SUCCESS);
+        true
+    }
+
+    // Returns whether it was this event rather than `other` that was set.
+    pub fn wait_either(&self, other: &AutoEvent) -> bool {
+        let objects = [
+            &self.0 as *const _ as *mut _,
+            &other.0 as *const _ as *mut _,
+        ];
+        // Up to three objects wait on the thread's own wait blocks.
+        let status = unsafe {
+            KeWaitForMultipleObjects(
+                objects.len() as u32,
+                objects.as_ptr(),
+                WAIT_TYPE::WaitAny,
+                KWAIT_REASON::Executive,
+                KPROCESSOR_MODE::KernelMode,
+                false,
+                ptr::null(),
+                ptr::null_mut(),
+            )
+        };
+        if status == STATUS_WAIT_1 {
+            return false;
+        }
+        assert_eq!(status, STATUS_SUCCESS);
+        true
+    }
+
     pub fn set(&self) {
         unsafe { KeSetEvent(&self.0 as *const _ as *mut _, IO_NO_INCREMENT.into(), false) };
     }
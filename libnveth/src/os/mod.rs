@@ -1,3 +1,5 @@
 pub mod event;
+pub mod registry;
 pub mod sync;
 pub mod thread;
+pub mod time;
@@ -0,0 +1,101 @@
+// Values under the device's own key in the PnP driver (software) key, which belongs to the device
+// instance: they survive reboots and disabling and enabling the device. Names are NUL-terminated
+// UTF-16.
+
+use core::{
+    mem::{self, MaybeUninit},
+    ptr,
+};
+
+use crate::windows::prelude as win;
+
+pub struct DeviceKey(win::WDFKEY);
+
+impl DeviceKey {
+    pub fn open(device: win::WDFDEVICE) -> Result<Self, win::NTSTATUS> {
+        let mut key = MaybeUninit::uninit();
+        let status = unsafe {
+            win::WdfDeviceOpenRegistryKey(
+                device,
+                win::PLUGPLAY_REGKEY_DRIVER,
+                win::ACCESS_MASK(win::KEY_READ.0 | win::KEY_WRITE.0),
+                ptr::null(),
+                key.as_mut_ptr(),
+            )
+        };
+        if !win::NT_SUCCESS(status) {
+            trace_exit_status!("WdfDeviceOpenRegistryKey", status);
+            return Err(status);
+        }
+        Ok(Self(unsafe { key.assume_init() }))
+    }
+
+    // Fills `buf` from a value of `value_type` and exactly its length.
+    pub fn query_value(
+        &self,
+        name: &[u16],
+        value_type: u32,
+        buf: &mut [u8],
+    ) -> Result<(), win::NTSTATUS> {
+        let name = value_name(name);
+        let mut length = 0;
+        let mut queried_type = 0;
+        let status = unsafe {
+            win::WdfRegistryQueryValue(
+                self.0,
+                &name,
+                buf.len() as _,
+                buf.as_mut_ptr().cast(),
+                &mut length,
+                &mut queried_type,
+            )
+        };
+        if !win::NT_SUCCESS(status) {
+            return Err(status);
+        }
+        if queried_type != value_type || length as usize != buf.len() {
+            return Err(win::STATUS_OBJECT_TYPE_MISMATCH);
+        }
+        Ok(())
+    }
+
+    pub fn set_value(
+        &self,
+        name: &[u16],
+        value_type: u32,
+        data: &[u8],
+    ) -> Result<(), win::NTSTATUS> {
+        let name = value_name(name);
+        let status = unsafe {
+            win::WdfRegistryAssignValue(
+                self.0,
+                &name,
+                value_type,
+                data.len() as _,
+                data.as_ptr() as win::PVOID,
+            )
+        };
+        if !win::NT_SUCCESS(status) {
+            trace_exit_status!("WdfRegistryAssignValue", status);
+            return Err(status);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DeviceKey {
+    fn drop(&mut self) {
+        unsafe { win::WdfRegistryClose(self.0) };
+    }
+}
+
+// The counted string leaves the NUL out.
+fn value_name(name: &[u16]) -> win::UNICODE_STRING {
+    debug_assert_eq!(name.last(), Some(&0));
+    let length = (mem::size_of_val(name) - mem::size_of::<u16>()) as _;
+    win::UNICODE_STRING {
+        length,
+        maximum_length: length,
+        buffer: name.as_ptr() as *mut _,
+    }
+}
@@ -2,9 +2,10 @@ use core::{
     cell::UnsafeCell,
     default::default,
     ops::{Deref, DerefMut},
+    ptr,
 };
 
-use crate::windows::prelude as win;
+use crate::{os::event::AutoEvent, windows::prelude as win};
 
 pub struct RwLock<T> {
     lock: UnsafeCell<win::EX_SPIN_LOCK>,
@@ -12,6 +13,21 @@ pub struct RwLock<T> {
 }
 
 impl<T> RwLock<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            lock: default(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    // Initializes the lock in place, and returns the data for the caller to initialize, e.g. when
+    // it is too large for the stack.
+    pub unsafe fn init(uninit: *mut Self) -> *mut T {
+        ptr::raw_mut!((*uninit).lock).write(default());
+        // UnsafeCell is repr(transparent).
+        ptr::raw_mut!((*uninit).data).cast()
+    }
+
     pub fn read(&self) -> RwLockReadGuard<T> {
         let old_irql = unsafe { win::ExAcquireSpinLockShared(self.lock.get()) };
         RwLockReadGuard::new(self, old_irql)
@@ -87,3 +103,51 @@ impl<T> Drop for RwLockWriteGuard<'_, T> {
         unsafe { win::ExReleaseSpinLockExclusive(self.lock.lock.get(), self.old_irql) }
     }
 }
+
+// A lock for PASSIVE_LEVEL, which unlike `RwLock` may be held while waiting.
+pub struct Mutex<T> {
+    // Set while the lock is free.
+    event: AutoEvent,
+    data: UnsafeCell<T>,
+}
+
+impl<T> Mutex<T> {
+    pub unsafe fn init(uninit: *mut Self, data: T) {
+        AutoEvent::init(ptr::raw_mut!((*uninit).event));
+        (*uninit).event.set();
+        ptr::raw_mut!((*uninit).data).write(UnsafeCell::new(data));
+    }
+
+    pub fn lock(&self) -> MutexGuard<T> {
+        self.event.wait();
+        MutexGuard { lock: self }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    lock: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.event.set();
+    }
+}
@@ -0,0 +1,43 @@
+use core::{mem::MaybeUninit, time::Duration};
+
+use crate::windows::km::wdm::{KeQueryInterruptTimePrecise, KeQuerySystemTimePrecise};
+
+// 100-nanosecond units
+const UNITS_PER_SEC: u64 = 10_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        let mut qpc_time_stamp = MaybeUninit::uninit();
+        Self(unsafe { KeQueryInterruptTimePrecise(qpc_time_stamp.as_mut_ptr()) })
+    }
+
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        let units = self.0.saturating_sub(earlier.0);
+        Duration::new(units / UNITS_PER_SEC, (units % UNITS_PER_SEC * 100) as _)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+
+    // Time since boot
+    pub fn as_duration(&self) -> Duration {
+        self.duration_since(Self(0))
+    }
+}
+
+// 100-nanosecond intervals since January 1, 1601 (UTC)
+pub fn system_time() -> u64 {
+    let mut current_time = MaybeUninit::uninit();
+    unsafe { KeQuerySystemTimePrecise(current_time.as_mut_ptr()) };
+    unsafe { current_time.assume_init() as _ }
+}
+
+// The system time at an offset returned by `Instant::as_duration`
+pub fn system_time_at(since_boot: Duration) -> u64 {
+    let ago = Instant::now().as_duration().saturating_sub(since_boot);
+    system_time().saturating_sub((ago.as_nanos() / 100) as _)
+}
@@ -1,23 +1,267 @@
-use core::default::default;
+use alloc::{sync::Arc, vec::Vec};
+
+use core::{default::default, mem, sync::atomic::AtomicU64, time::Duration};
+
+use shared::{
+    liveness::{Liveness, Message, PeerState},
+    session::{KeySlot, Session},
+};
 
 use crate::{
+    cookie::CookieState,
+    crypto::ecdh::EcdhPubKey,
+    handshake::{Initiation, Keypair, KEY_SIZE, TIMESTAMP_SIZE},
     net::{IpAddr, MacAddr},
-    os::sync::RwLock,
+    os::{sync::RwLock, time::Instant},
+    stats::Traffic,
     windows::prelude as win,
 };
 
+// Where the peer is reached: the configured address until authenticated traffic arrives from
+// another one.
+#[derive(Clone)]
+pub struct Endpoint {
+    pub addr: win::SOCKADDR_IN6,
+    pub configured: win::SOCKADDR_IN6,
+    pub roams: u64,
+    pub last_roamed: Option<Duration>,
+}
+
 pub struct Peer {
-    pub socket_addr: win::SOCKADDR_IN6,
-    pub mac_addr: RwLock<Option<MacAddr>>,
+    endpoint: RwLock<Endpoint>,
+    // What ARP requests and neighbor solicitations for the peer's addresses are answered with
+    mac_addr: RwLock<MacAddr>,
     pub ip_addr: IpAddr,
+    pub public_key: [u8; KEY_SIZE],
+    pub static_key: EcdhPubKey,
+    pub preshared_key: [u8; KEY_SIZE],
+    pub last_timestamp: RwLock<[u8; TIMESTAMP_SIZE]>,
+    pub initiation: RwLock<Option<Arc<Initiation>>>,
+    pub cookie: RwLock<CookieState>,
+    pub session: RwLock<Session<Arc<Keypair>>>,
+    pub liveness: RwLock<Liveness>,
+    pub traffic: Traffic,
+    pub auth_failures: AtomicU64,
+    pub replays: AtomicU64,
+    pub psk_failures: AtomicU64,
 }
 
 impl Peer {
-    pub fn new(addr: win::SOCKADDR_IN6) -> Self {
-        Self {
+    pub fn new(
+        addr: win::SOCKADDR_IN6,
+        public_key: [u8; KEY_SIZE],
+        preshared_key: [u8; KEY_SIZE],
+        persistent_keepalive: Option<Duration>,
+        mac_addr: MacAddr,
+    ) -> Result<Self, win::NTSTATUS> {
+        Ok(Self {
             ip_addr: IpAddr::from_ipv6(&addr.addr),
-            socket_addr: addr,
-            mac_addr: default(),
+            endpoint: RwLock::new(Endpoint {
+                configured: addr.clone(),
+                addr,
+                roams: 0,
+                last_roamed: None,
+            }),
+            mac_addr: RwLock::new(mac_addr),
+            static_key: EcdhPubKey::import(&public_key)?,
+            public_key,
+            preshared_key,
+            last_timestamp: default(),
+            initiation: default(),
+            cookie: default(),
+            session: default(),
+            liveness: RwLock::new(Liveness::new(persistent_keepalive)),
+            traffic: default(),
+            auth_failures: default(),
+            replays: default(),
+            psk_failures: default(),
+        })
+    }
+
+    pub fn endpoint(&self) -> Endpoint {
+        self.endpoint.read().clone()
+    }
+
+    pub fn mac_addr(&self) -> MacAddr {
+        *self.mac_addr.read()
+    }
+
+    // Follows the peer to the source of authenticated traffic, and returns whether it moved.
+    pub fn update_endpoint(&self, addr: &win::SOCKADDR_IN6) -> bool {
+        if same_addr(&self.endpoint.read().addr, addr) {
+            return false;
+        }
+        let now = Instant::now().as_duration();
+        let mut endpoint = self.endpoint.write();
+        if same_addr(&endpoint.addr, addr) {
+            return false;
+        }
+        endpoint.addr = addr.clone();
+        endpoint.roams += 1;
+        endpoint.last_roamed = Some(now);
+        true
+    }
+
+    // Applies a new configuration without dropping the sessions. A roamed endpoint is kept unless
+    // the configured one changed.
+    pub fn reconfigure(
+        &self,
+        addr: win::SOCKADDR_IN6,
+        persistent_keepalive: Option<Duration>,
+        mac_addr: MacAddr,
+    ) {
+        {
+            let mut endpoint = self.endpoint.write();
+            if !same_addr(&endpoint.configured, &addr) {
+                endpoint.addr = addr.clone();
+                endpoint.configured = addr;
+            }
+        }
+        self.liveness
+            .write()
+            .set_persistent_keepalive(persistent_keepalive);
+        *self.mac_addr.write() = mac_addr;
+    }
+
+    pub fn current_keypair(&self) -> Option<Arc<Keypair>> {
+        let now = Instant::now().as_duration();
+        self.session.read().current(now).cloned()
+    }
+
+    pub fn find_keypair(&self, local_index: u32) -> Option<(Arc<Keypair>, KeySlot)> {
+        let now = Instant::now().as_duration();
+        let session = self.session.read();
+        let (keypair, slot) = session.find(local_index, now)?;
+        Some((keypair.clone(), slot))
+    }
+
+    pub fn needs_rekey(&self) -> bool {
+        let now = Instant::now().as_duration();
+        self.session.read().needs_rekey(now)
+    }
+
+    pub fn insert_keypair(&self, keypair: Arc<Keypair>, initiator: bool) {
+        let now = Instant::now().as_duration();
+        let evicted = self.session.write().insert(keypair, initiator, now);
+        // Destroy the old keys outside the spin lock.
+        drop(evicted);
+        if initiator {
+            self.liveness.write().handshake_completed(now);
+        }
+    }
+
+    pub fn confirm_keypair(&self, local_index: u32) {
+        let now = Instant::now().as_duration();
+        let evicted = self.session.write().confirm(local_index, now);
+        drop(evicted);
+        self.liveness.write().handshake_completed(now);
+    }
+
+    pub fn received(&self, message: Message) {
+        let now = Instant::now().as_duration();
+        self.liveness.write().received(message, now);
+    }
+
+    pub fn sent(&self, message: Message) {
+        let now = Instant::now().as_duration();
+        self.liveness.write().sent(message, now);
+    }
+
+    pub fn keepalive_due(&self) -> bool {
+        let now = Instant::now().as_duration();
+        self.liveness.read().keepalive_due(now)
+    }
+
+    pub fn state(&self) -> PeerState {
+        let now = Instant::now().as_duration();
+        self.liveness.read().state(now)
+    }
+
+    pub fn state_change(&self) -> Option<PeerState> {
+        let now = Instant::now().as_duration();
+        self.liveness.write().state_change(now)
+    }
+}
+
+fn same_addr(a: &win::SOCKADDR_IN6, b: &win::SOCKADDR_IN6) -> bool {
+    a.addr == b.addr && a.port == b.port
+}
+
+pub type PeerList = Arc<Vec<Arc<Peer>>>;
+
+// The peers of an adapter. Changes replace the whole list, so that the workers go through a
+// snapshot without holding the spin lock, and removed peers live until the last snapshot is gone.
+#[derive(Default)]
+pub struct Peers(RwLock<PeerList>);
+
+impl Peers {
+    pub fn snapshot(&self) -> PeerList {
+        self.0.read().clone()
+    }
+
+    pub fn get(&self, public_key: &[u8; KEY_SIZE]) -> Option<Arc<Peer>> {
+        let peers = self.snapshot();
+        let peer = peers.iter().find(|peer| peer.public_key == *public_key)?;
+        Some(peer.clone())
+    }
+
+    pub fn add(&self, peer: Peer) -> Result<(), win::NTSTATUS> {
+        let peers = self.snapshot();
+        if peers.iter().any(|old| old.public_key == peer.public_key) {
+            return Err(win::STATUS_OBJECT_NAME_COLLISION);
+        }
+        let peer = Arc::new(peer);
+        self.publish(peers.len() + 1, peers.iter().cloned().chain(Some(peer)))
+    }
+
+    pub fn remove(&self, public_key: &[u8; KEY_SIZE]) -> Result<(), win::NTSTATUS> {
+        let peers = self.snapshot();
+        if !peers.iter().any(|peer| peer.public_key == *public_key) {
+            return Err(win::STATUS_NOT_FOUND);
+        }
+        self.publish(
+            peers.len() - 1,
+            peers
+                .iter()
+                .filter(|peer| peer.public_key != *public_key)
+                .cloned(),
+        )
+    }
+
+    // Swaps the peer with the same public key for a new one.
+    pub fn replace(&self, peer: Peer) -> Result<(), win::NTSTATUS> {
+        let peers = self.snapshot();
+        if !peers.iter().any(|old| old.public_key == peer.public_key) {
+            return Err(win::STATUS_NOT_FOUND);
+        }
+        let peer = Arc::new(peer);
+        self.publish(
+            peers.len(),
+            peers.iter().map(|old| {
+                if old.public_key == peer.public_key {
+                    peer.clone()
+                } else {
+                    old.clone()
+                }
+            }),
+        )
+    }
+
+    // There is a single writer: IOCTLs are dispatched sequentially.
+    fn publish(
+        &self,
+        len: usize,
+        peers: impl Iterator<Item = Arc<Peer>>,
+    ) -> Result<(), win::NTSTATUS> {
+        let mut list = Vec::new();
+        if list.try_reserve_exact(len).is_err() {
+            return Err(win::STATUS_INSUFFICIENT_RESOURCES);
         }
+        list.extend(peers);
+        let list = Arc::new(list);
+        let old = mem::replace(&mut *self.0.write(), list);
+        // Destroy the removed peers outside the spin lock.
+        drop(old);
+        Ok(())
     }
 }
@@ -0,0 +1,126 @@
+// Proxy ARP and NDP: the TX worker answers the stack's ARP requests and neighbor solicitations for
+// the addresses of peers itself, with the adapter addresses nvnet gave the peers, instead of sending
+// them to every peer. The replies are handed to the RX worker to indicate.
+
+use core::{
+    mem::{self, MaybeUninit},
+    ptr,
+};
+
+use shared::routing::{self, ADDR_SIZE};
+
+use crate::{
+    net::{L2Icmpv6NaHeader, L2Icmpv6NsHeader, Layer2ArpPacket, MacAddr},
+    os::{event::AutoEvent, sync::RwLock},
+};
+
+pub const MAX_REPLY_SIZE: usize = mem::size_of::<L2Icmpv6NaHeader>();
+
+// The source link-layer address option is left out of unicast solicitations.
+const MIN_NS_SIZE: usize = mem::size_of::<L2Icmpv6NsHeader>() - 8;
+
+// The stack retries what is dropped when the RX worker falls behind.
+const REPLIES_SIZE: usize = 8;
+
+// Writes the reply to `frame` into `buf` if it asks for an address that `owner` knows the adapter
+// address of, and returns its length.
+pub fn reply(
+    frame: &[u8],
+    owner: impl Fn(&[u8; ADDR_SIZE]) -> Option<MacAddr>,
+    buf: &mut [u8; MAX_REPLY_SIZE],
+) -> Option<usize> {
+    if frame.len() >= mem::size_of::<Layer2ArpPacket>() {
+        let request = unsafe { ptr::read_unaligned(frame.as_ptr().cast::<Layer2ArpPacket>()) };
+        if request.is_request() {
+            let (src, dst) = (request.arp.src_ipv4(), request.arp.dst_ipv4());
+            // Probes and announcements are the stack's duplicate address detection.
+            if src.is_unspecified() || src == dst {
+                return None;
+            }
+            let owner_mac = owner(&routing::ipv4_mapped(*dst.bytes()))?;
+            unsafe { ptr::write_unaligned(buf.as_mut_ptr().cast(), request.reply(owner_mac)) };
+            return Some(mem::size_of::<Layer2ArpPacket>());
+        }
+    }
+    if frame.len() >= MIN_NS_SIZE {
+        let mut ns = MaybeUninit::<L2Icmpv6NsHeader>::zeroed();
+        let len = frame.len().min(mem::size_of::<L2Icmpv6NsHeader>());
+        unsafe { ptr::copy_nonoverlapping(frame.as_ptr(), ns.as_mut_ptr().cast(), len) };
+        let ns = unsafe { ns.assume_init() };
+        if ns.is_solicitation() && !ns.ipv6.src().is_unspecified() {
+            let owner_mac = owner(ns.target().bytes())?;
+            let na = L2Icmpv6NaHeader::reply(&ns, owner_mac);
+            unsafe { ptr::write_unaligned(buf.as_mut_ptr().cast(), na) };
+            return Some(mem::size_of::<L2Icmpv6NaHeader>());
+        }
+    }
+    None
+}
+
+#[derive(Clone, Copy)]
+struct Reply {
+    len: usize,
+    buf: [u8; MAX_REPLY_SIZE],
+}
+
+struct Ring {
+    head: usize,
+    len: usize,
+    replies: [Reply; REPLIES_SIZE],
+}
+
+// The replies waiting for the RX worker, oldest first
+pub struct Replies {
+    ring: RwLock<Ring>,
+    // Set for every reply, to wake the RX worker up from its receive
+    wake: AutoEvent,
+}
+
+impl Replies {
+    pub unsafe fn init(uninit: *mut Self) {
+        ptr::raw_mut!((*uninit).ring).write(RwLock::new(Ring {
+            head: 0,
+            len: 0,
+            replies: [Reply {
+                len: 0,
+                buf: [0; MAX_REPLY_SIZE],
+            }; REPLIES_SIZE],
+        }));
+        AutoEvent::init(ptr::raw_mut!((*uninit).wake));
+    }
+
+    // Returns whether there was room for the reply.
+    pub fn push(&self, reply: &[u8]) -> bool {
+        {
+            let mut ring = self.ring.write();
+            if ring.len == REPLIES_SIZE {
+                return false;
+            }
+            let index = (ring.head + ring.len) % REPLIES_SIZE;
+            let slot = &mut ring.replies[index];
+            slot.len = reply.len();
+            slot.buf[..reply.len()].copy_from_slice(reply);
+            ring.len += 1;
+        }
+        self.wake.set();
+        true
+    }
+
+    pub fn wake_event(&self) -> &AutoEvent {
+        &self.wake
+    }
+
+    // Copies the oldest reply into `buf`, which must have room for `MAX_REPLY_SIZE` bytes, and
+    // returns its length.
+    pub fn pop(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut ring = self.ring.write();
+        if ring.len == 0 {
+            return None;
+        }
+        let slot = ring.replies[ring.head];
+        buf[..slot.len].copy_from_slice(&slot.buf[..slot.len]);
+        ring.head = (ring.head + 1) % REPLIES_SIZE;
+        ring.len -= 1;
+        Some(slot.len)
+    }
+}
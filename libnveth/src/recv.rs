@@ -1,20 +1,41 @@
-use alloc::vec::Vec;
+use alloc::{sync::Arc, vec::Vec};
 
 use core::{
     mem::{self, MaybeUninit},
-    ptr,
-    sync::atomic::{AtomicBool, Ordering::Relaxed},
+    ptr, slice,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering::Relaxed},
 };
 
 use libnveth_macros::*;
 
+use shared::{
+    forward::FloodPolicy,
+    ioctl::{EventKind, RxDrop},
+    liveness::Message,
+    mtu, nonce,
+    relay::{self, Relay},
+    routing::{self, Mode},
+    session::KeySlot,
+    wire::{self, MessageType},
+};
+
 use crate::{
-    adapter::{VEthCipherFrame, VEthCipherFrameHeader},
-    crypto::aes_gcm::AesGcm,
-    net::{EthHeader, L2Icmpv6Header, L2Icmpv6NaHeader, L2Icmpv6NsHeader, Layer2ArpPacket},
-    os::thread::Thread,
-    peer::Peer,
+    adapter::{self, alloc_buf, Datapath, MdlRepr, VEthCipherFrameHeader},
+    cookie::{self, Admission, CookieChecker, VEthCookieReply},
+    crypto::aead::Aead,
+    events::Events,
+    fdb::Fdb,
+    handshake::{self, Keypair, LocalKey, VEthHandshakeInit, VEthHandshakeResponse, KEY_SIZE},
+    net::{EthHeader, MacAddr},
+    os::{
+        sync::{Mutex, RwLock},
+        thread::Thread,
+    },
+    peer::{Peer, Peers},
+    proxy::Replies,
+    routing::Routes,
     socket::{IoRequest, UdpSocket, UdpSocketWorker},
+    stats::AdapterStats,
     windows::prelude as win,
     worker::{Worker, WorkerState},
 };
@@ -30,6 +51,8 @@ pub struct VEthRxQueue {
     worker: VEthRxWorker<'static>,
 
     state: Worker,
+
+    datapath: &'static Mutex<Datapath>,
 }
 
 impl VEthRxQueue {
@@ -37,7 +60,20 @@ impl VEthRxQueue {
         rx_queue: win::NETPACKETQUEUE,
         socket: &'static UdpSocket,
         request: &'static mut IoRequest,
-        peers: &'static Vec<Peer>,
+        local_key: &'static RwLock<Option<Arc<LocalKey>>>,
+        peers: &'static Peers,
+        fdb: &'static Fdb,
+        routes: &'static Routes,
+        replies: &'static Replies,
+        flood: &'static AtomicU32,
+        mode: &'static AtomicU32,
+        relay: &'static AtomicBool,
+        cookies: &'static CookieChecker,
+        stats: &'static AdapterStats,
+        events: &'static Events,
+        datapath: &'static Mutex<Datapath>,
+        local_mac_addr: MacAddr,
+        mtu: u32,
     ) -> Result<&'a mut Self, win::NTSTATUS> {
         unsafe {
             let uninit = Self::from_queue_mut_ptr(rx_queue);
@@ -65,6 +101,8 @@ impl VEthRxQueue {
 
             ptr::raw_mut!((*uninit).notify).write(AtomicBool::new(false));
 
+            ptr::raw_mut!((*uninit).datapath).write(datapath);
+
             let state = Worker::init(ptr::raw_mut!((*uninit).state));
 
             let init = &mut *uninit;
@@ -73,11 +111,23 @@ impl VEthRxQueue {
                 init,
                 socket,
                 request,
+                local_key,
                 peers,
+                fdb,
+                routes,
+                replies,
+                flood,
+                mode,
+                relay,
+                cookies,
+                stats,
+                events,
                 state,
+                local_mac_addr,
             );
 
             let init = &mut *uninit;
+            alloc_buf(&mut init.worker.relay_datagram, mtu::datagram_size(mtu))?;
             init.state
                 .init_thread(Thread::spawn_mut(veth_rx_worker, &mut init.worker)?);
 
@@ -93,7 +143,14 @@ impl VEthRxQueue {
         veth_get_rx_queue_context(rx_queue.into())
     }
 
-    pub fn start(&mut self) {
+    pub fn pause(&mut self) {
+        self.state.cancel();
+        self.worker.socket.request.cancel();
+        self.state.wait_for_stopped();
+    }
+
+    pub fn resume(&mut self) {
+        self.worker.socket.request.reset();
         self.state.start();
     }
 
@@ -134,11 +191,28 @@ struct VEthRxWorker<'a> {
 
     notify: &'a AtomicBool,
 
-    peers: &'a Vec<Peer>,
+    local_key: &'a RwLock<Option<Arc<LocalKey>>>,
+    peers: &'a Peers,
+    fdb: &'a Fdb,
+    routes: &'a Routes,
+    replies: &'a Replies,
+    flood: &'a AtomicU32,
+    mode: &'a AtomicU32,
+    relay: &'a AtomicBool,
+    cookies: &'a CookieChecker,
+    stats: &'a AdapterStats,
+    events: &'a Events,
 
     state: &'a mut WorkerState,
 
+    // The destination of the frames made up for packets in layer-3 mode
+    local_mac_addr: MacAddr,
+
     addr: MaybeUninit<win::SOCKADDR_IN6>,
+
+    mdl: MaybeUninit<MdlRepr>,
+    // Data re-encrypted for another peer, sized for the adapter's MTU
+    relay_datagram: Vec<u8>,
 }
 
 impl<'a> VEthRxWorker<'a> {
@@ -147,8 +221,19 @@ impl<'a> VEthRxWorker<'a> {
         rx: &'a VEthRxQueue,
         socket: &'a UdpSocket,
         request: &'a mut IoRequest,
-        peers: &'a Vec<Peer>,
+        local_key: &'a RwLock<Option<Arc<LocalKey>>>,
+        peers: &'a Peers,
+        fdb: &'a Fdb,
+        routes: &'a Routes,
+        replies: &'a Replies,
+        flood: &'a AtomicU32,
+        mode: &'a AtomicU32,
+        relay: &'a AtomicBool,
+        cookies: &'a CookieChecker,
+        stats: &'a AdapterStats,
+        events: &'a Events,
         state: &'a mut WorkerState,
+        local_mac_addr: MacAddr,
     ) {
         ptr::raw_mut!((*uninit).socket).write(UdpSocketWorker::new(socket, request));
 
@@ -159,79 +244,414 @@ impl<'a> VEthRxWorker<'a> {
 
         ptr::raw_mut!((*uninit).notify).write(&rx.notify);
 
+        ptr::raw_mut!((*uninit).local_key).write(local_key);
         ptr::raw_mut!((*uninit).peers).write(peers);
+        ptr::raw_mut!((*uninit).fdb).write(fdb);
+        ptr::raw_mut!((*uninit).routes).write(routes);
+        ptr::raw_mut!((*uninit).replies).write(replies);
+        ptr::raw_mut!((*uninit).flood).write(flood);
+        ptr::raw_mut!((*uninit).mode).write(mode);
+        ptr::raw_mut!((*uninit).relay).write(relay);
+        ptr::raw_mut!((*uninit).cookies).write(cookies);
+        ptr::raw_mut!((*uninit).stats).write(stats);
+        ptr::raw_mut!((*uninit).events).write(events);
 
         ptr::raw_mut!((*uninit).state).write(state);
+
+        ptr::raw_mut!((*uninit).local_mac_addr).write(local_mac_addr);
+
+        ptr::raw_mut!((*uninit).relay_datagram).write(Vec::new());
     }
 
-    fn parse_eth(&mut self, peer: &Peer, buf: *const u8, len: usize) {
-        if len < mem::size_of::<EthHeader>() {
-            return;
-        }
-        let eth = unsafe { &*buf.cast::<EthHeader>() };
-        if eth.is_arp() {
-            self.parse_arp(peer, buf, len);
-            return;
-        }
-        if eth.is_ipv6() {
-            self.parse_icmpv6(peer, buf, len);
-            return;
+    // Returns the offset of the Ethernet frame to be indicated if the datagram carries one, and why
+    // it is dropped otherwise. Handshake messages are consumed here.
+    fn parse_message(
+        &mut self,
+        mdl: *mut win::MDL,
+        buf: *mut u8,
+        len: usize,
+    ) -> Result<Option<usize>, RxDrop> {
+        let datagram = unsafe { slice::from_raw_parts(buf, len) };
+        let header = match wire::Header::decode(datagram) {
+            Err(_) => return Err(RxDrop::Malformed),
+            Ok((header, _)) => header,
+        };
+        match header.message_type {
+            MessageType::Data | MessageType::Keepalive => {
+                // The session identifies the peer, wherever it sends from.
+                let addr = unsafe { self.addr.assume_init_ref() };
+                let peers = self.peers.snapshot();
+                let local_index = header.receiver_index;
+                let (peer, (keypair, slot)) = peers
+                    .iter()
+                    .find_map(|peer| Some((peer, peer.find_keypair(local_index)?)))
+                    .ok_or_else(|| {
+                        let known = peers
+                            .iter()
+                            .any(|peer| peer.endpoint().addr.addr == addr.addr);
+                        if known {
+                            RxDrop::UnknownSession
+                        } else {
+                            RxDrop::UnknownSource
+                        }
+                    })?;
+                let datagram = unsafe { slice::from_raw_parts_mut(buf, len) };
+                let (frame_header, data) = adapter::split_datagram(datagram);
+                let counter = nonce::decode(&frame_header.nonce).ok_or(RxDrop::InvalidNonce)?;
+                let data_length = data.len();
+                if keypair
+                    .recv
+                    .decrypt(
+                        &frame_header.nonce,
+                        &frame_header.header,
+                        data,
+                        &frame_header.tag,
+                    )
+                    .is_err()
+                {
+                    peer.auth_failures.fetch_add(1, Relaxed);
+                    return Err(RxDrop::AuthFailed);
+                }
+                // Only authenticated counters may move the window.
+                if !keypair.check_replay(counter) {
+                    peer.replays.fetch_add(1, Relaxed);
+                    return Err(RxDrop::Replayed);
+                }
+                self.update_endpoint(peer, addr);
+                peer.traffic.received(len);
+                self.stats.traffic.received(len);
+                // The responder's side of the handshake completes here.
+                if slot == KeySlot::Next {
+                    peer.confirm_keypair(local_index);
+                    self.events.post(EventKind::HandshakeCompleted, peer);
+                }
+                if header.message_type == MessageType::Keepalive {
+                    peer.received(Message::Keepalive);
+                    return Ok(None);
+                }
+                peer.received(Message::Data);
+                let offset = mem::size_of::<VEthCipherFrameHeader>();
+                if Mode::from_raw(self.mode.load(Relaxed)) == Some(Mode::Layer3) {
+                    let ip_header = routing::parse_ip(data).ok_or(RxDrop::Malformed)?;
+                    if self.routes.lookup(&ip_header.src) != Some(peer.public_key) {
+                        return Err(RxDrop::DisallowedSource);
+                    }
+                    if self.relay.load(Relaxed) && header.may_relay() {
+                        let owner = self.routes.lookup(&ip_header.dst);
+                        let relay = relay::relay_packet(owner, &peer.public_key);
+                        if !self.relay_data(&header, peer, data, relay) {
+                            return Ok(None);
+                        }
+                    }
+                    // The packet is framed for the stack in place of the end of the message
+                    // header, which is done with.
+                    let offset = offset - mem::size_of::<EthHeader>();
+                    let eth =
+                        EthHeader::new(self.local_mac_addr, peer.mac_addr(), ip_header.ether_type);
+                    unsafe { ptr::write(buf.add(offset).cast::<EthHeader>(), eth) };
+                    return Ok(Some(offset));
+                }
+                if data_length < mem::size_of::<EthHeader>() {
+                    return Err(RxDrop::Runt);
+                }
+                self.learn_mac(peer, data.as_ptr());
+                if self.relay.load(Relaxed) && header.may_relay() {
+                    let policy =
+                        FloodPolicy::from_raw(self.flood.load(Relaxed)).unwrap_or_default();
+                    let fdb = self.fdb;
+                    let relay = relay::relay_frame(
+                        data,
+                        self.local_mac_addr.bytes(),
+                        &peer.public_key,
+                        policy,
+                        |dst| fdb.lookup(dst),
+                    );
+                    if !self.relay_data(&header, peer, data, relay) {
+                        return Ok(None);
+                    }
+                }
+                Ok(Some(offset))
+            }
+            MessageType::HandshakeInit => {
+                if let Err(status) = self.consume_initiation(mdl, buf) {
+                    trace_exit_status!("consume_initiation", status);
+                    return Err(RxDrop::HandshakeFailed);
+                }
+                Ok(None)
+            }
+            MessageType::HandshakeResponse => {
+                if let Err(status) = self.consume_response(mdl, buf, header.receiver_index) {
+                    trace_exit_status!("consume_response", status);
+                    return Err(RxDrop::HandshakeFailed);
+                }
+                Ok(None)
+            }
+            MessageType::Cookie => {
+                if let Err(status) = self.consume_cookie_reply(buf, header.receiver_index) {
+                    trace_exit_status!("consume_cookie_reply", status);
+                    return Err(RxDrop::HandshakeFailed);
+                }
+                Ok(None)
+            }
         }
     }
 
-    fn parse_arp(&mut self, peer: &Peer, buf: *const u8, len: usize) {
-        if len < mem::size_of::<Layer2ArpPacket>() {
-            return;
-        }
-        let l2 = unsafe { &*buf.cast::<Layer2ArpPacket>() };
-        let arp = &l2.arp;
-        if !arp.is_eth() || !arp.is_ipv4() {
-            return;
+    fn consume_initiation(
+        &mut self,
+        mdl: *mut win::MDL,
+        buf: *mut u8,
+    ) -> Result<(), win::NTSTATUS> {
+        let local_key = self
+            .local_key
+            .read()
+            .clone()
+            .ok_or(win::STATUS_INVALID_DEVICE_STATE)?;
+        let msg = unsafe { &*buf.cast::<VEthHandshakeInit>() };
+        let addr = unsafe { self.addr.assume_init_ref() };
+        let admission = self
+            .cookies
+            .admit(&local_key, handshake::as_bytes(msg), addr)?;
+        match admission {
+            Admission::Accept => {}
+            Admission::Reject => {
+                self.stats.rx_drops.record(RxDrop::HandshakeRejected);
+                return Ok(());
+            }
+            Admission::Challenge => {
+                let (mac1, sender_index) = (msg.mac1, u32::from_le(msg.sender_index));
+                // The cookie reply is written over the initiation it answers.
+                let reply = unsafe { &mut *buf.cast::<VEthCookieReply>() };
+                self.cookies
+                    .create_reply(&local_key, &mac1, sender_index, addr, reply)?;
+                let sent = self
+                    .socket
+                    .send_to(mdl, mem::size_of::<VEthCookieReply>(), addr)?;
+                trace_println!("<-- %u", sent);
+                return Ok(());
+            }
         }
-        if arp.src_ipv4().is_unspecified() {
-            return;
+        let peers = self.peers.snapshot();
+        let (peer, responder) = handshake::consume_initiation(&local_key, &peers, msg)?;
+        peer.received(Message::Keepalive);
+        self.update_endpoint(peer, addr);
+
+        // Both sides initiated at the same time: the one with the greater public key wins.
+        let pending = match peer.initiation.read().as_ref() {
+            Some(initiation) => !initiation.is_expired(),
+            None => false,
+        };
+        if pending && local_key.public_key() > &peer.public_key {
+            return Ok(());
         }
-        peer.mac_addr.write().replace(*arp.src_mac());
+        let old = peer.initiation.write().take();
+        drop(old);
+
+        // The response is written over the initiation it answers.
+        let msg = unsafe { &mut *buf.cast::<VEthHandshakeResponse>() };
+        let keypair = handshake::create_response(&local_key, peer, responder, msg)?;
+        peer.insert_keypair(Arc::new(keypair), false);
+        let addr = unsafe { self.addr.assume_init_ref() };
+        let sent = self
+            .socket
+            .send_to(mdl, mem::size_of::<VEthHandshakeResponse>(), addr)?;
+        trace_println!("<-- %u", sent);
+        peer.traffic.sent(sent);
+        self.stats.traffic.sent(sent);
+        Ok(())
     }
 
-    fn parse_icmpv6(&mut self, peer: &Peer, buf: *const u8, len: usize) {
-        if len < mem::size_of::<L2Icmpv6Header>() {
-            return;
-        }
-        let l2 = unsafe { &*buf.cast::<L2Icmpv6Header>() };
-        let (ipv6, icmpv6) = (&l2.ipv6, &l2.icmpv6);
-        if !ipv6.is_icmpv6() {
-            return;
+    fn consume_response(
+        &mut self,
+        mdl: *mut win::MDL,
+        buf: *mut u8,
+        receiver_index: u32,
+    ) -> Result<(), win::NTSTATUS> {
+        let local_key = self
+            .local_key
+            .read()
+            .clone()
+            .ok_or(win::STATUS_INVALID_DEVICE_STATE)?;
+        let msg = unsafe { &*buf.cast::<VEthHandshakeResponse>() };
+        // Responses are not rate limited: only those that answer a pending initiation get past the
+        // MAC1 check to a DH computation.
+        if !local_key.check_mac1(handshake::as_bytes(msg))? {
+            self.stats.rx_drops.record(RxDrop::HandshakeRejected);
+            return Ok(());
         }
-        if icmpv6.is_neighbor_solicitation() {
-            self.parse_nd_ns(peer, buf, len);
-            return;
+        let peers = self.peers.snapshot();
+        let (peer, initiation) = peers
+            .iter()
+            .find_map(|peer| {
+                let initiation = peer.initiation.read().clone()?;
+                if initiation.local_index() != receiver_index {
+                    return None;
+                }
+                Some((peer, initiation))
+            })
+            .ok_or(win::STATUS_NOT_FOUND)?;
+        let keypair = match handshake::consume_response(&local_key, peer, &initiation, msg) {
+            Ok(keypair) => Arc::new(keypair),
+            Err(status) => {
+                if status == handshake::STATUS_PSK_MISMATCH {
+                    peer.psk_failures.fetch_add(1, Relaxed);
+                }
+                return Err(status);
+            }
+        };
+        // Only a response that authenticates answers the initiation, unless a new one replaced it
+        // in the meantime.
+        {
+            let mut pending = peer.initiation.write();
+            match pending.as_ref() {
+                Some(current) if Arc::ptr_eq(current, &initiation) => {}
+                _ => return Err(win::STATUS_NOT_FOUND),
+            }
+            pending.take();
         }
-        if icmpv6.is_neighbor_advertisement() {
-            self.parse_nd_na(peer, buf, len);
-            return;
+        peer.received(Message::Keepalive);
+        self.update_endpoint(peer, unsafe { self.addr.assume_init_ref() });
+        peer.insert_keypair(keypair.clone(), true);
+        self.events.post(EventKind::HandshakeCompleted, peer);
+        self.send_empty(mdl, buf, peer, &keypair)
+    }
+
+    fn consume_cookie_reply(
+        &mut self,
+        buf: *mut u8,
+        receiver_index: u32,
+    ) -> Result<(), win::NTSTATUS> {
+        let local_key = self
+            .local_key
+            .read()
+            .clone()
+            .ok_or(win::STATUS_INVALID_DEVICE_STATE)?;
+        let reply = unsafe { &*buf.cast::<VEthCookieReply>() };
+        // The reply answers our pending initiation; the next one carries MAC2.
+        let peers = self.peers.snapshot();
+        let peer = peers
+            .iter()
+            .find(|peer| match peer.initiation.read().as_ref() {
+                Some(initiation) => initiation.local_index() == receiver_index,
+                None => false,
+            })
+            .ok_or(win::STATUS_NOT_FOUND)?;
+        cookie::consume_reply(&local_key, peer, reply)
+    }
+
+    // A keepalive lets the responder start sending with the new keypair.
+    fn send_empty(
+        &mut self,
+        mdl: *mut win::MDL,
+        buf: *mut u8,
+        peer: &Peer,
+        keypair: &Keypair,
+    ) -> Result<(), win::NTSTATUS> {
+        let nonce = keypair
+            .next_nonce()
+            .ok_or(win::STATUS_INVALID_DEVICE_STATE)?;
+        let header = unsafe { &mut *buf.cast::<VEthCipherFrameHeader>() };
+        header.header = wire::Header::new(MessageType::Keepalive, keypair.remote_index).encode();
+        header.nonce = nonce;
+        keypair
+            .send
+            .encrypt(&header.nonce, &header.header, &mut [], &mut header.tag)?;
+        let addr = unsafe { self.addr.assume_init_ref() };
+        let sent = self
+            .socket
+            .send_to(mdl, mem::size_of::<VEthCipherFrameHeader>(), addr)?;
+        trace_println!("<-- %u", sent);
+        peer.traffic.sent(sent);
+        self.stats.traffic.sent(sent);
+        peer.sent(Message::Keepalive);
+        Ok(())
+    }
+
+    // Relays data from `peer` where it goes, and returns whether it is for the local stack too.
+    fn relay_data(
+        &mut self,
+        header: &wire::Header,
+        peer: &Peer,
+        payload: &[u8],
+        relay: Relay<[u8; KEY_SIZE]>,
+    ) -> bool {
+        let peers = self.peers.snapshot();
+        match relay {
+            Relay::Deliver => true,
+            Relay::To(public_key) => {
+                if let Some(next) = peers.iter().find(|next| next.public_key == public_key) {
+                    self.relay_to(next, payload, header.hops);
+                }
+                false
+            }
+            Relay::Flood => {
+                for next in peers
+                    .iter()
+                    .filter(|next| next.public_key != peer.public_key)
+                {
+                    self.relay_to(next, payload, header.hops);
+                }
+                true
+            }
         }
     }
 
-    fn parse_nd_ns(&mut self, peer: &Peer, buf: *const u8, len: usize) {
-        if len < mem::size_of::<L2Icmpv6NsHeader>() {
+    // Re-encrypts `payload` for `peer`, one hop further. Only an established session carries it:
+    // handshakes are initiated by the TX worker.
+    fn relay_to(&mut self, peer: &Peer, payload: &[u8], hops: u8) {
+        let keypair = match peer.current_keypair() {
+            None => return,
+            Some(keypair) => keypair,
+        };
+        let nonce = match keypair.next_nonce() {
+            None => return,
+            Some(nonce) => nonce,
+        };
+
+        let (header, data) = adapter::split_datagram(&mut self.relay_datagram);
+        header.header = wire::Header::relayed(hops, keypair.remote_index).encode();
+        header.nonce = nonce;
+        let data = &mut data[..payload.len()];
+        data.copy_from_slice(payload);
+        if let Err(status) =
+            keypair
+                .send
+                .encrypt(&header.nonce, &header.header, data, &mut header.tag)
+        {
+            trace_exit_status!("encrypt", status);
             return;
         }
-        let l2 = unsafe { &*buf.cast::<L2Icmpv6NsHeader>() };
-        let icmpv6_ns = &l2.icmpv6_ns;
-        if let Some(source_mac) = icmpv6_ns.source_mac() {
-            peer.mac_addr.write().replace(*source_mac);
+
+        let length = mem::size_of::<VEthCipherFrameHeader>() + payload.len();
+        let mdl = adapter::build_mdl(&mut self.mdl, self.relay_datagram.as_mut_ptr(), length);
+        match self.socket.send_to(mdl, length, &peer.endpoint().addr) {
+            Err(status) => {
+                trace_exit_status!("send_to", status);
+                peer.traffic.send_failed();
+                self.stats.traffic.send_failed();
+            }
+            Ok(sent) => {
+                trace_println!("<-- %u", sent);
+                peer.traffic.sent(sent);
+                self.stats.traffic.sent(sent);
+            }
         }
+        peer.sent(Message::Data);
     }
 
-    fn parse_nd_na(&mut self, peer: &Peer, buf: *const u8, len: usize) {
-        if len < mem::size_of::<L2Icmpv6NaHeader>() {
-            return;
+    fn update_endpoint(&self, peer: &Peer, addr: &win::SOCKADDR_IN6) {
+        if peer.update_endpoint(addr) {
+            self.events.post(EventKind::EndpointRoamed, peer);
         }
-        let l2 = unsafe { &*buf.cast::<L2Icmpv6NaHeader>() };
-        let icmpv6_na = &l2.icmpv6_na;
-        if let Some(target_mac) = icmpv6_na.target_mac() {
-            peer.mac_addr.write().replace(*target_mac);
+    }
+
+    // Every authenticated frame teaches where its source is.
+    fn learn_mac(&self, peer: &Peer, buf: *const u8) {
+        let eth = unsafe { &*buf.cast::<EthHeader>() };
+        let mac_addr = eth.src();
+        if self.fdb.learn(mac_addr, peer) {
+            peer.traffic.mac_learned();
+            self.stats.traffic.mac_learned();
+            self.events.post_mac_learned(peer, mac_addr);
         }
     }
 }
@@ -264,36 +684,47 @@ extern "system" fn veth_rx_worker(rx: &mut VEthRxWorker) {
             let mdl =
                 unsafe { &*win::NetExtensionGetFragmentMdl(rx.mdl_extension, fragment_index) };
             let mdl = mdl.mdl;
-            match rx.socket.recv_from(mdl, length, &mut rx.addr) {
+            // The TX worker's replies come first.
+            let buf = unsafe { slice::from_raw_parts_mut(virtual_address, length) };
+            if let Some(reply_length) = rx.replies.pop(buf) {
+                fragment.set_valid_length(reply_length as _);
+                fragment.set_offset(0);
+                fragments.next_index =
+                    unsafe { win::NetRingIncrementIndex(fragments, fragment_index) };
+                if rx.notify.load(Relaxed) {
+                    unsafe { win::NetRxQueueNotifyMoreReceivedPacketsAvailable(rx.rx_queue) };
+                }
+                continue;
+            }
+            // Only authenticated frames are indicated: the fragment is reused for the next datagram
+            // otherwise.
+            match rx
+                .socket
+                .recv_from(mdl, length, &mut rx.addr, rx.replies.wake_event())
+            {
+                Err(win::STATUS_CANCELLED) if !rx.state.is_canceled() => {
+                    // Woken up by the TX worker with replies to indicate
+                    continue;
+                }
                 Err(_status) => {
-                    // TODO
+                    // Pausing the worker cancels the receive.
+                    if !rx.state.is_canceled() {
+                        rx.stats.rx_drops.record(RxDrop::ReceiveFailed);
+                    }
+                    continue;
                 }
                 Ok(received) => {
                     trace_println!("--> %u", received);
-                    fragment.set_valid_length(received as _);
-                    fragment.set_offset(0);
-
-                    if received >= mem::size_of::<VEthCipherFrameHeader>() {
-                        let data_length = received - mem::size_of::<VEthCipherFrameHeader>();
-                        if false {
-                            let _: Result<(), win::NTSTATUS> = (|| {
-                                let cipher = AesGcm::new([0; AesGcm::KEY_SIZE128])?;
-                                let frame =
-                                    unsafe { &mut *virtual_address.cast::<VEthCipherFrame>() };
-                                cipher.decrypt(
-                                    &frame.header.nonce,
-                                    &mut frame.data[..data_length],
-                                    &frame.header.tag,
-                                )?;
-                                Ok(())
-                            })();
+                    let offset = match rx.parse_message(mdl, virtual_address, received) {
+                        Ok(Some(offset)) => offset,
+                        Ok(None) => continue,
+                        Err(reason) => {
+                            rx.stats.rx_drops.record(reason);
+                            continue;
                         }
-                    }
-
-                    let addr = unsafe { rx.addr.assume_init_ref().addr };
-                    if let Some(peer) = rx.peers.iter().find(|peer| peer.socket_addr.addr == addr) {
-                        rx.parse_eth(peer, virtual_address, received);
-                    }
+                    };
+                    fragment.set_valid_length((received - offset) as _);
+                    fragment.set_offset(offset as _);
                 }
             }
             fragments.next_index = unsafe { win::NetRingIncrementIndex(fragments, fragment_index) };
@@ -315,7 +746,8 @@ pub extern "system" fn evt_rx_queue_start(rx_queue: win::NETPACKETQUEUE) {
     trace_entry!("evt_rx_queue_start");
 
     let rx = VEthRxQueue::from_queue_mut(rx_queue);
-    rx.start();
+    let datapath = rx.datapath;
+    datapath.lock().start_rx(rx);
 }
 
 #[irql_requires_max(DISPATCH_LEVEL)]
@@ -368,9 +800,8 @@ pub extern "system" fn evt_rx_queue_cancel(rx_queue: win::NETPACKETQUEUE) {
     trace_entry!("evt_rx_queue_cancel");
 
     let rx = VEthRxQueue::from_queue_mut(rx_queue);
-    rx.state.cancel();
-    rx.worker.socket.request.cancel();
-    rx.state.wait_for_stopped();
+    let datapath = rx.datapath;
+    datapath.lock().cancel_rx(rx);
 
     let rings = rx.rings;
     let packets = unsafe { &mut *win::NetRingCollectionGetPacketRing(rings) };
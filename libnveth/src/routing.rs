@@ -0,0 +1,54 @@
+// The driver's side of `shared::routing`: nvnet gives each peer its allowed IPs, the TX worker looks
+// up where packets go in layer-3 mode, and the RX worker checks where they came from.
+
+use core::ptr;
+
+use shared::{
+    ioctl::VEthPrefix,
+    routing::{self, Prefix, ADDR_SIZE},
+};
+
+use crate::{crypto, handshake::KEY_SIZE, os::sync::RwLock, windows::prelude as win};
+
+const ROUTES_SIZE: usize = 1024;
+
+pub struct Routes(RwLock<routing::Routes<ROUTES_SIZE>>);
+
+impl Routes {
+    pub unsafe fn init(uninit: *mut Self) -> Result<(), win::NTSTATUS> {
+        let mut seed = [0; 8];
+        crypto::gen_random(&mut seed)?;
+
+        // The table is too large for the stack.
+        let table = RwLock::init(ptr::raw_mut!((*uninit).0));
+        routing::Routes::<ROUTES_SIZE>::init(&mut *table.cast(), u64::from_ne_bytes(seed));
+        Ok(())
+    }
+
+    // The prefixes must have been checked with `ioctl::allowed_ips`.
+    pub fn set_peer(
+        &self,
+        public_key: &[u8; KEY_SIZE],
+        allowed_ips: &[VEthPrefix],
+    ) -> Result<(), win::NTSTATUS> {
+        let prefixes = allowed_ips.iter().filter_map(Prefix::from_raw);
+        if self.0.write().set_peer(public_key, prefixes).is_err() {
+            return Err(win::STATUS_INSUFFICIENT_RESOURCES);
+        }
+        Ok(())
+    }
+
+    pub fn remove_peer(&self, public_key: &[u8; KEY_SIZE]) {
+        self.0.write().remove_peer(public_key);
+    }
+
+    // The public key of the peer that owns `addr`
+    pub fn lookup(&self, addr: &[u8; ADDR_SIZE]) -> Option<[u8; KEY_SIZE]> {
+        self.0.read().lookup(addr).copied()
+    }
+
+    // The public key of the peer that has `addr` as an address of its own
+    pub fn lookup_host(&self, addr: &[u8; ADDR_SIZE]) -> Option<[u8; KEY_SIZE]> {
+        self.0.read().lookup_host(addr).copied()
+    }
+}
@@ -1,27 +1,47 @@
-use alloc::vec::Vec;
+use alloc::{sync::Arc, vec::Vec};
 
 use core::{
     mem::{self, MaybeUninit},
+    ops::Range,
     ptr, slice,
-    sync::atomic::{AtomicBool, Ordering::Relaxed},
+    sync::atomic::{AtomicBool, AtomicU32, Ordering::Relaxed},
+    time::Duration,
 };
 
 use libnveth_macros::*;
 
+use shared::{
+    forward::{self, FloodPolicy, Forward},
+    ioctl::EventKind,
+    liveness::{Message, PeerState},
+    mtu,
+    routing::{self, Mode},
+    wire::{self, MessageType},
+};
+
 use crate::{
-    adapter::{VEthCipherFrameHeader, VEthFrame},
-    crypto::aes_gcm::AesGcm,
-    net::EthHeader,
-    os::thread::Thread,
-    peer::Peer,
-    socket::{IoRequest, UdpSocket, UdpSocketWorker},
-    windows::{
-        km::wdm::{MmBuildMdlForNonPagedPool, MmInitializeMdl, MmSizeOfMdl, MDL, PAGE_SIZE},
-        prelude as win,
+    adapter::{self, alloc_buf, Datapath, MdlRepr, VEthCipherFrameHeader},
+    crypto::aead::Aead,
+    events::Events,
+    fdb::Fdb,
+    handshake::{self, Keypair, LocalKey, VEthHandshakeInit},
+    os::{
+        sync::{Mutex, RwLock},
+        thread::Thread,
+        time::Instant,
     },
+    peer::{Peer, Peers},
+    proxy::{self, Replies, MAX_REPLY_SIZE},
+    routing::Routes,
+    socket::{IoRequest, UdpSocket, UdpSocketWorker},
+    stats::AdapterStats,
+    windows::prelude as win,
     worker::{Worker, WorkerState},
 };
 
+// How often the worker looks for peers that are due a keepalive.
+const KEEPALIVE_TICK: Duration = Duration::from_secs(1);
+
 pub struct VEthTxQueue {
     tx_queue: win::NETPACKETQUEUE,
     rings: *const win::NET_RING_COLLECTION,
@@ -32,6 +52,8 @@ pub struct VEthTxQueue {
     worker: VEthTxWorker<'static>,
 
     state: Worker,
+
+    datapath: &'static Mutex<Datapath>,
 }
 
 impl VEthTxQueue {
@@ -39,7 +61,17 @@ impl VEthTxQueue {
         tx_queue: win::NETPACKETQUEUE,
         socket: &'static UdpSocket,
         request: &'static mut IoRequest,
-        peers: &'static Vec<Peer>,
+        local_key: &'static RwLock<Option<Arc<LocalKey>>>,
+        peers: &'static Peers,
+        fdb: &'static Fdb,
+        routes: &'static Routes,
+        replies: &'static Replies,
+        flood: &'static AtomicU32,
+        mode: &'static AtomicU32,
+        stats: &'static AdapterStats,
+        events: &'static Events,
+        datapath: &'static Mutex<Datapath>,
+        mtu: u32,
     ) -> Result<&'a mut Self, win::NTSTATUS> {
         unsafe {
             let uninit = Self::from_queue_mut_ptr(tx_queue);
@@ -60,6 +92,8 @@ impl VEthTxQueue {
 
             ptr::raw_mut!((*uninit).notify).write(AtomicBool::new(false));
 
+            ptr::raw_mut!((*uninit).datapath).write(datapath);
+
             let state = Worker::init(ptr::raw_mut!((*uninit).state));
 
             let init = &mut *uninit;
@@ -68,11 +102,20 @@ impl VEthTxQueue {
                 init,
                 socket,
                 request,
+                local_key,
                 peers,
+                fdb,
+                routes,
+                replies,
+                flood,
+                mode,
+                stats,
+                events,
                 state,
             );
 
             let init = &mut *uninit;
+            init.worker.alloc_bufs(mtu)?;
             init.state
                 .init_thread(Thread::spawn_mut(veth_tx_worker, &mut init.worker)?);
 
@@ -88,7 +131,12 @@ impl VEthTxQueue {
         veth_get_tx_queue_context(tx_queue.into())
     }
 
-    pub fn start(&mut self) {
+    pub fn pause(&mut self) {
+        self.state.cancel();
+        self.state.wait_for_stopped();
+    }
+
+    pub fn resume(&mut self) {
         self.state.start();
     }
 
@@ -119,12 +167,6 @@ pub fn veth_get_tx_queue_context_type() -> *const win::WDF_OBJECT_CONTEXT_TYPE_I
     unsafe { &WDF_VETH_TX_QUEUE_TYPE_INFO }
 }
 
-#[repr(C)]
-union MdlRepr {
-    mdl: MDL,
-    mdlx: [u8; unsafe { MmSizeOfMdl((PAGE_SIZE - 1) as _, mem::size_of::<VEthFrame>()) }],
-}
-
 struct VEthTxWorker<'a> {
     socket: UdpSocketWorker<'a>,
 
@@ -134,12 +176,23 @@ struct VEthTxWorker<'a> {
 
     notify: &'a AtomicBool,
 
-    peers: &'a Vec<Peer>,
+    local_key: &'a RwLock<Option<Arc<LocalKey>>>,
+    peers: &'a Peers,
+    fdb: &'a Fdb,
+    routes: &'a Routes,
+    replies: &'a Replies,
+    flood: &'a AtomicU32,
+    mode: &'a AtomicU32,
+    stats: &'a AdapterStats,
+    events: &'a Events,
 
     state: &'a mut WorkerState,
 
     mdl: MaybeUninit<MdlRepr>,
-    frame: VEthFrame,
+    // The plain frame, and the data message carrying it, sized for the adapter's MTU
+    data: Vec<u8>,
+    datagram: Vec<u8>,
+    handshake: VEthHandshakeInit,
 }
 
 impl<'a> VEthTxWorker<'a> {
@@ -148,7 +201,15 @@ impl<'a> VEthTxWorker<'a> {
         tx: &'a VEthTxQueue,
         socket: &'a UdpSocket,
         request: &'a mut IoRequest,
-        peers: &'a Vec<Peer>,
+        local_key: &'a RwLock<Option<Arc<LocalKey>>>,
+        peers: &'a Peers,
+        fdb: &'a Fdb,
+        routes: &'a Routes,
+        replies: &'a Replies,
+        flood: &'a AtomicU32,
+        mode: &'a AtomicU32,
+        stats: &'a AdapterStats,
+        events: &'a Events,
         state: &'a mut WorkerState,
     ) {
         ptr::raw_mut!((*uninit).socket).write(UdpSocketWorker::new(socket, request));
@@ -159,9 +220,227 @@ impl<'a> VEthTxWorker<'a> {
 
         ptr::raw_mut!((*uninit).notify).write(&tx.notify);
 
+        ptr::raw_mut!((*uninit).local_key).write(local_key);
         ptr::raw_mut!((*uninit).peers).write(peers);
+        ptr::raw_mut!((*uninit).fdb).write(fdb);
+        ptr::raw_mut!((*uninit).routes).write(routes);
+        ptr::raw_mut!((*uninit).replies).write(replies);
+        ptr::raw_mut!((*uninit).flood).write(flood);
+        ptr::raw_mut!((*uninit).mode).write(mode);
+        ptr::raw_mut!((*uninit).stats).write(stats);
+        ptr::raw_mut!((*uninit).events).write(events);
 
         ptr::raw_mut!((*uninit).state).write(state);
+
+        ptr::raw_mut!((*uninit).data).write(Vec::new());
+        ptr::raw_mut!((*uninit).datagram).write(Vec::new());
+    }
+
+    // The MTU only changes while the queues are destroyed.
+    fn alloc_bufs(&mut self, mtu: u32) -> Result<(), win::NTSTATUS> {
+        alloc_buf(&mut self.data, mtu::frame_size(mtu))?;
+        alloc_buf(&mut self.datagram, mtu::datagram_size(mtu))
+    }
+
+    fn send_to(&mut self, peer: &Peer, buf: *mut u8, length: usize) {
+        let mdl = adapter::build_mdl(&mut self.mdl, buf, length);
+        match self.socket.send_to(mdl, length, &peer.endpoint().addr) {
+            Err(status) => {
+                trace_exit_status!("send_to", status);
+                self.send_failed(peer);
+            }
+            Ok(sent) => {
+                trace_println!("<-- %u", sent);
+                peer.traffic.sent(sent);
+                self.stats.traffic.sent(sent);
+            }
+        }
+    }
+
+    // Counts a frame for `peer` that never left, whatever kept it.
+    fn send_failed(&self, peer: &Peer) {
+        peer.traffic.send_failed();
+        self.stats.traffic.send_failed();
+    }
+
+    // Sends `payload` of the plain frame: all of it, or only the IP packet in layer-3 mode.
+    fn send_frame(&mut self, peer: &Peer, payload: Range<usize>) {
+        let keypair = match peer.current_keypair() {
+            None => {
+                // No Ethernet frame leaves before the session keys are agreed on. The stack
+                // retransmits what matters once the handshake completes.
+                self.send_failed(peer);
+                self.initiate_handshake(peer);
+                return;
+            }
+            Some(keypair) => keypair,
+        };
+        if peer.needs_rekey() {
+            self.initiate_handshake(peer);
+        }
+
+        let nonce = match keypair.next_nonce() {
+            None => {
+                // The counter is exhausted until the next handshake.
+                self.send_failed(peer);
+                return;
+            }
+            Some(nonce) => nonce,
+        };
+
+        // The plain frame is kept intact since a broadcast is encrypted once per peer.
+        let (header, data) = adapter::split_datagram(&mut self.datagram);
+        header.header = wire::Header::new(MessageType::Data, keypair.remote_index).encode();
+        header.nonce = nonce;
+        let data_length = payload.len();
+        let data = &mut data[..data_length];
+        data.copy_from_slice(&self.data[payload]);
+        // The header is authenticated along with the frame.
+        if let Err(status) =
+            keypair
+                .send
+                .encrypt(&header.nonce, &header.header, data, &mut header.tag)
+        {
+            trace_exit_status!("encrypt", status);
+            self.send_failed(peer);
+            return;
+        }
+
+        let buf = self.datagram.as_mut_ptr();
+        let length = mem::size_of::<VEthCipherFrameHeader>() + data_length;
+        self.send_to(peer, buf, length);
+        peer.sent(Message::Data);
+    }
+
+    fn forward_frame(&mut self, frame_length: usize) {
+        let peers = self.peers.snapshot();
+        let policy = FloodPolicy::from_raw(self.flood.load(Relaxed)).unwrap_or_default();
+        let fdb = self.fdb;
+        let frame = &self.data[..frame_length];
+        let forward = forward::forward(frame, policy, |dst| {
+            let public_key = fdb.lookup(dst)?;
+            peers.iter().find(|peer| peer.public_key == public_key)
+        });
+        match forward {
+            Forward::To(peer) => self.send_frame(peer, 0..frame_length),
+            Forward::Flood => peers
+                .iter()
+                .for_each(|peer| self.send_frame(peer, 0..frame_length)),
+            Forward::Drop => {}
+        }
+    }
+
+    // Answers an ARP request or a neighbor solicitation for the address of a peer in its place, and
+    // returns whether the frame was answered. Only host prefixes are peer addresses in layer-2 mode.
+    fn answer_locally(&mut self, frame_length: usize, mode: Mode) -> bool {
+        let peers = self.peers.snapshot();
+        let routes = self.routes;
+        let frame = &self.data[..frame_length];
+        let mut reply = [0; MAX_REPLY_SIZE];
+        let reply_length = proxy::reply(
+            frame,
+            |addr| {
+                let public_key = match mode {
+                    Mode::Layer2 => routes.lookup_host(addr)?,
+                    Mode::Layer3 => routes.lookup(addr)?,
+                };
+                let peer = peers.iter().find(|peer| peer.public_key == public_key)?;
+                Some(peer.mac_addr())
+            },
+            &mut reply,
+        );
+        match reply_length {
+            None => false,
+            // A request that finds no room is forwarded instead.
+            Some(reply_length) => self.replies.push(&reply[..reply_length]),
+        }
+    }
+
+    // Anything but IP and answered ARP has nowhere to go in layer-3 mode.
+    fn route_packet(&mut self, frame_length: usize) {
+        let peers = self.peers.snapshot();
+        let routes = self.routes;
+        let frame = &self.data[..frame_length];
+        let peer = routing::ip_packet(frame).and_then(|packet| {
+            let public_key = routes.lookup(&routing::parse_ip(packet)?.dst)?;
+            peers.iter().find(|peer| peer.public_key == public_key)
+        });
+        if let Some(peer) = peer {
+            self.send_frame(peer, mtu::ETH_HEADER_SIZE as usize..frame_length);
+        }
+    }
+
+    fn send_keepalives(&mut self) {
+        let peers = self.peers.snapshot();
+        for peer in peers.iter() {
+            match peer.state_change() {
+                None => {}
+                Some(PeerState::Up) => self.events.post(EventKind::PeerUp, peer),
+                Some(_) => self.events.post(EventKind::PeerDown, peer),
+            }
+        }
+        for peer in peers.iter().filter(|peer| peer.keepalive_due()) {
+            match peer.current_keypair() {
+                // Persistent keepalives also bring the tunnel up.
+                None => self.initiate_handshake(peer),
+                Some(keypair) => self.send_keepalive(peer, &keypair),
+            }
+        }
+    }
+
+    fn send_keepalive(&mut self, peer: &Peer, keypair: &Keypair) {
+        let nonce = match keypair.next_nonce() {
+            None => {
+                // The counter is exhausted until the next handshake.
+                self.send_failed(peer);
+                return;
+            }
+            Some(nonce) => nonce,
+        };
+        let (header, _) = adapter::split_datagram(&mut self.datagram);
+        header.header = wire::Header::new(MessageType::Keepalive, keypair.remote_index).encode();
+        header.nonce = nonce;
+        if let Err(status) =
+            keypair
+                .send
+                .encrypt(&header.nonce, &header.header, &mut [], &mut header.tag)
+        {
+            trace_exit_status!("encrypt", status);
+            self.send_failed(peer);
+            return;
+        }
+
+        let buf = self.datagram.as_mut_ptr();
+        let length = mem::size_of::<VEthCipherFrameHeader>();
+        self.send_to(peer, buf, length);
+        peer.sent(Message::Keepalive);
+    }
+
+    fn initiate_handshake(&mut self, peer: &Peer) {
+        // An expired initiation went unanswered; a response would have taken it.
+        let unanswered = match peer.initiation.read().as_ref() {
+            Some(initiation) if !initiation.is_expired() => return,
+            initiation => initiation.is_some(),
+        };
+        let local_key = match self.local_key.read().clone() {
+            None => return,
+            Some(local_key) => local_key,
+        };
+        match handshake::create_initiation(&local_key, peer, &mut self.handshake) {
+            Err(status) => {
+                trace_exit_status!("create_initiation", status);
+            }
+            Ok(initiation) => {
+                let old = peer.initiation.write().replace(Arc::new(initiation));
+                drop(old);
+                if unanswered {
+                    self.events.post(EventKind::HandshakeFailed, peer);
+                }
+                let buf = &mut self.handshake as *mut VEthHandshakeInit;
+                let length = mem::size_of::<VEthHandshakeInit>();
+                self.send_to(peer, buf.cast(), length);
+            }
+        }
     }
 }
 
@@ -170,14 +449,20 @@ extern "system" fn veth_tx_worker(tx: &mut VEthTxWorker) {
     while tx.state.wait_for_start() {
         let packets = unsafe { &mut *win::NetRingCollectionGetPacketRing(tx.rings) };
         let fragments = unsafe { &mut *win::NetRingCollectionGetFragmentRing(tx.rings) };
+        let mut last_tick = Instant::now();
         while !tx.state.is_canceled() {
+            if last_tick.elapsed() >= KEEPALIVE_TICK {
+                last_tick = Instant::now();
+                tx.send_keepalives();
+            }
+
             let packet_index = packets.next_index;
             let packet_end_index = packets.end_index;
             if packet_index == packet_end_index {
                 if tx.notify.load(Relaxed) {
                     unsafe { win::NetTxQueueNotifyMoreCompletedPacketsAvailable(tx.tx_queue) };
                 }
-                tx.state.wait_for_work();
+                tx.state.wait_for_work_timeout(KEEPALIVE_TICK);
                 continue;
             }
 
@@ -187,6 +472,7 @@ extern "system" fn veth_tx_worker(tx: &mut VEthTxWorker) {
                 win::NetRingAdvanceIndex(fragments, fragment_index, packet.fragment_count.into())
             };
             let mut frame_offset = 0;
+            let mut oversized = false;
             while fragment_index != fragment_end_index {
                 let fragment =
                     unsafe { &mut *win::NetRingGetFragmentAtIndex(fragments, fragment_index) };
@@ -198,8 +484,14 @@ extern "system" fn veth_tx_worker(tx: &mut VEthTxWorker) {
                 };
                 let virtual_address = virtual_address.virtual_address;
                 let length = fragment.valid_length() as _;
+                // The stack honors the MTU; a larger frame is dropped rather than overrun the
+                // buffer.
+                if frame_offset + length > tx.data.len() {
+                    oversized = true;
+                    break;
+                }
                 unsafe {
-                    tx.frame.plain.data[frame_offset..frame_offset + length].copy_from_slice(
+                    tx.data[frame_offset..frame_offset + length].copy_from_slice(
                         slice::from_raw_parts(
                             virtual_address.offset(fragment.offset() as _),
                             length,
@@ -209,58 +501,13 @@ extern "system" fn veth_tx_worker(tx: &mut VEthTxWorker) {
                 frame_offset += length;
                 fragment_index = unsafe { win::NetRingIncrementIndex(fragments, fragment_index) };
             }
-            unsafe {
-                MmInitializeMdl(
-                    ptr::raw_mut!((*tx.mdl.as_mut_ptr()).mdl),
-                    tx.frame.plain.data.as_mut_ptr().cast(),
-                    frame_offset,
-                )
-            };
-            unsafe { MmBuildMdlForNonPagedPool(ptr::raw_mut!((*tx.mdl.as_mut_ptr()).mdl)) };
-            let mdl = unsafe { &mut tx.mdl.assume_init_mut().mdl };
-            let socket = &mut tx.socket;
-            let mut send_to = |frame: &mut VEthFrame, addr| {
-                if false {
-                    let _: Result<(), win::NTSTATUS> = (|| {
-                        let cipher = AesGcm::new([0; AesGcm::KEY_SIZE128])?;
-                        let frame = unsafe { &mut frame.cipher };
-                        let data_length = frame_offset - mem::size_of::<VEthCipherFrameHeader>();
-                        cipher.encrypt(
-                            &frame.header.nonce,
-                            &mut frame.data[..data_length],
-                            &mut frame.header.tag,
-                        )?;
-                        Ok(())
-                    })();
-                }
-
-                match socket.send_to(mdl, frame_offset, addr) {
-                    Err(_status) => {
-                        // TODO
-                    }
-                    Ok(sent) => {
-                        trace_println!("<-- %u", sent);
-                    }
-                }
-            };
-            if frame_offset >= mem::size_of::<EthHeader>() {
-                let frame = &mut tx.frame;
-                let eth = unsafe { &*frame.plain.data.as_ptr().cast::<EthHeader>() };
-                let dst = eth.dst();
-                if dst.is_multicast() {
-                    if dst.is_broadcast() {
-                        tx.peers
-                            .iter()
-                            .for_each(|peer| send_to(frame, &peer.socket_addr));
-                    }
-                } else if let Some(peer) = tx.peers.iter().find(|peer| {
-                    if let Some(addr) = peer.mac_addr.read().as_ref() {
-                        dst == addr
-                    } else {
-                        false
+            if !oversized {
+                let mode = Mode::from_raw(tx.mode.load(Relaxed)).unwrap_or(Mode::Layer2);
+                if !tx.answer_locally(frame_offset, mode) {
+                    match mode {
+                        Mode::Layer2 => tx.forward_frame(frame_offset),
+                        Mode::Layer3 => tx.route_packet(frame_offset),
                     }
-                }) {
-                    send_to(frame, &peer.socket_addr);
                 }
             }
             packets.next_index = unsafe { win::NetRingIncrementIndex(packets, packet_index) };
@@ -278,7 +525,8 @@ pub extern "system" fn evt_tx_queue_start(tx_queue: win::NETPACKETQUEUE) {
     trace_entry!("evt_tx_queue_start");
 
     let tx = VEthTxQueue::from_queue_mut(tx_queue);
-    tx.start();
+    let datapath = tx.datapath;
+    datapath.lock().start_tx(tx);
 }
 
 #[irql_requires_max(DISPATCH_LEVEL)]
@@ -324,8 +572,8 @@ pub extern "system" fn evt_tx_queue_cancel(tx_queue: win::NETPACKETQUEUE) {
     trace_entry!("evt_tx_queue_cancel");
 
     let tx = VEthTxQueue::from_queue_mut(tx_queue);
-    tx.state.cancel();
-    tx.state.wait_for_stopped();
+    let datapath = tx.datapath;
+    datapath.lock().cancel_tx(tx);
 
     let rings = tx.rings;
     let packets = unsafe { &mut *win::NetRingCollectionGetPacketRing(rings) };
@@ -194,6 +194,7 @@ impl UdpSocket {
         request: &mut IoRequest,
         buf: &win::WSK_BUF,
         addr: &mut MaybeUninit<win::SOCKADDR_IN6>,
+        wake: &AutoEvent,
     ) -> Result<usize, win::NTSTATUS> {
         let dispatch = self.datagram_dispatch();
         let wsk_receive_from = dispatch.wsk_receive_from.unwrap();
@@ -207,7 +208,7 @@ impl UdpSocket {
             ptr::null_mut(),
             request.reuse()?,
         );
-        let status = request.wait(status);
+        let status = request.wait_or_wake(status, wake);
         if !win::NT_SUCCESS(status) {
             trace_exit_status!("wsk_receive_from", status);
             Err(status)
@@ -281,13 +282,14 @@ impl<'a> UdpSocketWorker<'a> {
         mdl: *mut win::MDL,
         length: usize,
         addr: &mut MaybeUninit<win::SOCKADDR_IN6>,
+        wake: &AutoEvent,
     ) -> Result<usize, win::NTSTATUS> {
         let buf = win::WSK_BUF {
             mdl,
             offset: 0,
             length,
         };
-        self.socket.recv_from(&mut self.request, &buf, addr)
+        self.socket.recv_from(&mut self.request, &buf, addr, wake)
     }
 }
 
@@ -335,15 +337,31 @@ impl IoRequest {
 
     fn wait(&mut self, status: win::NTSTATUS) -> win::NTSTATUS {
         assert!(self.pending);
-        let result = if status != win::STATUS_PENDING {
+        if status == win::STATUS_PENDING {
+            self.event.wait();
+        }
+        self.result(status)
+    }
+
+    // Like `wait`, but cancels the operation if `wake` is set first. Only the thread that owns the
+    // request cancels it this way, so the IRP is never reused under a cancellation.
+    fn wait_or_wake(&mut self, status: win::NTSTATUS, wake: &AutoEvent) -> win::NTSTATUS {
+        assert!(self.pending);
+        if status == win::STATUS_PENDING && !self.event.wait_either(wake) {
+            unsafe { win::IoCancelIrp(self.irp) };
+            self.event.wait();
+        }
+        self.result(status)
+    }
+
+    fn result(&mut self, status: win::NTSTATUS) -> win::NTSTATUS {
+        self.pending = false;
+        if status != win::STATUS_PENDING {
             status
         } else {
-            self.event.wait();
             let irp = unsafe { self.irp.as_ref().unwrap() };
             irp.io_status.status
-        };
-        self.pending = false;
-        result
+        }
     }
 
     fn info(&mut self) -> usize {
@@ -359,6 +377,11 @@ impl IoRequest {
         unsafe { win::IoCancelIrp(self.irp) };
     }
 
+    // Lets the request be reused once the operation that `cancel` interrupted is over.
+    pub fn reset(&self) {
+        self.canceled.store(false, Relaxed);
+    }
+
     extern "system" fn complete(
         _device_object: *const win::DEVICE_OBJECT,
         _irp: *const win::IRP,
@@ -0,0 +1,57 @@
+use core::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+use shared::ioctl::RxDrop;
+
+#[derive(Default)]
+pub struct RxDrops([AtomicU64; RxDrop::COUNT]);
+
+impl RxDrops {
+    pub fn record(&self, reason: RxDrop) {
+        self.0[reason as usize].fetch_add(1, Relaxed);
+    }
+
+    pub fn load(&self) -> [u64; RxDrop::COUNT] {
+        let mut drops = [0; RxDrop::COUNT];
+        for (count, counter) in drops.iter_mut().zip(self.0.iter()) {
+            *count = counter.load(Relaxed);
+        }
+        drops
+    }
+}
+
+// Kept both per peer and per adapter. Packets and bytes are whole datagrams exchanged with peers.
+#[derive(Default)]
+pub struct Traffic {
+    pub tx_packets: AtomicU64,
+    pub tx_bytes: AtomicU64,
+    pub tx_failures: AtomicU64,
+    pub rx_packets: AtomicU64,
+    pub rx_bytes: AtomicU64,
+    pub mac_learned: AtomicU64,
+}
+
+impl Traffic {
+    pub fn sent(&self, bytes: usize) {
+        self.tx_packets.fetch_add(1, Relaxed);
+        self.tx_bytes.fetch_add(bytes as _, Relaxed);
+    }
+
+    pub fn send_failed(&self) {
+        self.tx_failures.fetch_add(1, Relaxed);
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.rx_packets.fetch_add(1, Relaxed);
+        self.rx_bytes.fetch_add(bytes as _, Relaxed);
+    }
+
+    pub fn mac_learned(&self) {
+        self.mac_learned.fetch_add(1, Relaxed);
+    }
+}
+
+#[derive(Default)]
+pub struct AdapterStats {
+    pub traffic: Traffic,
+    pub rx_drops: RxDrops,
+}
@@ -271,6 +271,13 @@ net_fn!(
     }
 );
 
+net_fn!(
+    #[irql_requires_max(PASSIVE_LEVEL)]
+    pub fn NetAdapterStop(adapter: NETADAPTER) -> () {
+        NetAdapterStopTableIndex
+    }
+);
+
 net_fn!(
     #[irql_requires(PASSIVE_LEVEL)]
     pub fn NetAdapterSetLinkLayerCapabilities(
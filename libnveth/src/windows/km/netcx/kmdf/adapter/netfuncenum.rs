@@ -14,6 +14,7 @@ impl NETFUNCENUM {
     pub const NetAdapterInitSetDatapathCallbacksTableIndex: isize = 2;
     pub const NetAdapterCreateTableIndex: isize = 3;
     pub const NetAdapterStartTableIndex: isize = 4;
+    pub const NetAdapterStopTableIndex: isize = 5;
     pub const NetAdapterSetLinkLayerCapabilitiesTableIndex: isize = 6;
     pub const NetAdapterSetLinkLayerMtuSizeTableIndex: isize = 7;
     pub const NetAdapterSetDataPathCapabilitiesTableIndex: isize = 14;
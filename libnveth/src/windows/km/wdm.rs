@@ -34,6 +34,9 @@ c_type!(
 pub const SYNCHRONIZE: ACCESS_MASK = ACCESS_MASK(0x00100000);
 pub const STANDARD_RIGHTS_REQUIRED: ACCESS_MASK = ACCESS_MASK(0x000F0000);
 
+pub const KEY_READ: ACCESS_MASK = ACCESS_MASK(0x00020019);
+pub const KEY_WRITE: ACCESS_MASK = ACCESS_MASK(0x00020006);
+
 c_type!(
     pub struct IO_STATUS_BLOCK {
         pub status: NTSTATUS, // ...
@@ -167,6 +170,13 @@ c_type!(
     }
 );
 
+c_type!(
+    pub enum WAIT_TYPE {
+        WaitAll = 0,
+        WaitAny = 1,
+    }
+);
+
 c_type!(
     pub type PKSTART_ROUTINE = fn(start_context: PVOID) -> ();
 );
@@ -186,6 +196,24 @@ extern "system" {
         alertable: bool,
         timeout: *const LARGE_INTEGER,
     ) -> NTSTATUS;
+
+    #[when(timeout.is_null(), irql_requires_max(APC_LEVEL))]
+    pub fn KeWaitForMultipleObjects(
+        count: u32,
+        object: *const PVOID,
+        wait_type: WAIT_TYPE,
+        wait_reason: KWAIT_REASON,
+        wait_mode: KPROCESSOR_MODE,
+        alertable: bool,
+        timeout: *const LARGE_INTEGER,
+        wait_block_array: PVOID,
+    ) -> NTSTATUS;
+}
+
+extern "system" {
+    pub fn KeQueryInterruptTimePrecise(qpc_time_stamp: *mut u64) -> u64;
+
+    pub fn KeQuerySystemTimePrecise(current_time: *mut i64) -> ();
 }
 
 c_type!(
@@ -539,3 +567,6 @@ extern "system" {
     #[irql_requires_max(PASSIVE_LEVEL)]
     pub fn ZwClose(handle: HANDLE) -> NTSTATUS;
 }
+
+pub const REG_BINARY: u32 = 3;
+pub const REG_DWORD: u32 = 4;
@@ -19,7 +19,7 @@ pub use crate::windows::{
         ws2ipdef::*,
     },
     wdf::kmdf::{
-        wdfdevice::*, wdfdriver::*, wdffileobject::*, wdfio::*, wdfobject::*, wdfrequest::*,
-        wdftypes::*,
+        wdfdevice::*, wdfdriver::*, wdffileobject::*, wdfio::*, wdfobject::*, wdfregistry::*,
+        wdfrequest::*, wdftypes::*,
     },
 };
@@ -29,8 +29,49 @@ pub use bcrypt::BCRYPT_KEY_DATA_BLOB_VERSION1;
 
 pub const BCRYPT_AES_ALGORITHM: [u16; 4] = utf16_str!(bcrypt::BCRYPT_AES_ALGORITHM);
 
+pub const BCRYPT_CHACHA20_POLY1305_ALGORITHM: [u16; 18] = utf16_str!("CHACHA20_POLY1305");
+
+pub const BCRYPT_ECDH_ALGORITHM: [u16; 5] = utf16_str!(bcrypt::BCRYPT_ECDH_ALGORITHM);
+
+pub const BCRYPT_SHA256_ALGORITHM: [u16; 7] = utf16_str!(bcrypt::BCRYPT_SHA256_ALGORITHM);
+
+pub const BCRYPT_SHA512_ALGORITHM: [u16; 7] = utf16_str!(bcrypt::BCRYPT_SHA512_ALGORITHM);
+
+pub const BCRYPT_ECC_CURVE_NAME: [u16; 13] = utf16_str!(bcrypt::BCRYPT_ECC_CURVE_NAME);
+
+pub const BCRYPT_ECC_CURVE_25519: [u16; 11] = utf16_str!(bcrypt::BCRYPT_ECC_CURVE_25519);
+
+pub const BCRYPT_ECCPRIVATE_BLOB: [u16; 15] = utf16_str!(bcrypt::BCRYPT_ECCPRIVATE_BLOB);
+
+pub const BCRYPT_ECCPUBLIC_BLOB: [u16; 14] = utf16_str!(bcrypt::BCRYPT_ECCPUBLIC_BLOB);
+
+pub const BCRYPT_KDF_HASH: [u16; 5] = utf16_str!(bcrypt::BCRYPT_KDF_HASH);
+
+pub const BCRYPT_KDF_RAW_SECRET: [u16; 9] = utf16_str!(bcrypt::BCRYPT_KDF_RAW_SECRET);
+
+pub use bcrypt::BCryptBuffer;
+pub use bcrypt::BCryptBufferDesc;
+pub use bcrypt::BCRYPTBUFFER_VERSION;
+pub use bcrypt::BCRYPT_ECCKEY_BLOB;
+pub use bcrypt::BCRYPT_ECDH_PRIVATE_GENERIC_MAGIC;
+pub use bcrypt::BCRYPT_ECDH_PUBLIC_GENERIC_MAGIC;
+pub use bcrypt::BCRYPT_USE_SYSTEM_PREFERRED_RNG;
+pub use bcrypt::KDF_HASH_ALGORITHM;
+pub use bcrypt::KDF_SECRET_APPEND;
+pub use bcrypt::KDF_SECRET_PREPEND;
+
+pub use bcrypt::BCryptCreateHash;
 pub use bcrypt::BCryptDecrypt;
+pub use bcrypt::BCryptDeriveKey;
 pub use bcrypt::BCryptEncrypt;
+pub use bcrypt::BCryptExportKey;
+pub use bcrypt::BCryptFinalizeKeyPair;
+pub use bcrypt::BCryptFinishHash;
+pub use bcrypt::BCryptGenRandom;
+pub use bcrypt::BCryptGenerateKeyPair;
+pub use bcrypt::BCryptHashData;
 pub use bcrypt::BCryptImportKey;
+pub use bcrypt::BCryptImportKeyPair;
 pub use bcrypt::BCryptOpenAlgorithmProvider;
+pub use bcrypt::BCryptSecretAgreement;
 pub use bcrypt::BCryptSetProperty;
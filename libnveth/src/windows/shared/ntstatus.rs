@@ -1,8 +1,18 @@
 use crate::windows::shared::ntdef::NTSTATUS;
 
 pub const STATUS_SUCCESS: NTSTATUS = NTSTATUS(0x00000000);
+pub const STATUS_WAIT_1: NTSTATUS = NTSTATUS(0x00000001);
+pub const STATUS_TIMEOUT: NTSTATUS = NTSTATUS(0x00000102);
 pub const STATUS_PENDING: NTSTATUS = NTSTATUS(0x00000103);
+pub const STATUS_INVALID_PARAMETER: NTSTATUS = NTSTATUS(0xC000000D);
 pub const STATUS_MORE_PROCESSING_REQUIRED: NTSTATUS = NTSTATUS(0xC0000016);
+pub const STATUS_BUFFER_TOO_SMALL: NTSTATUS = NTSTATUS(0xC0000023);
+pub const STATUS_OBJECT_TYPE_MISMATCH: NTSTATUS = NTSTATUS(0xC0000024);
+pub const STATUS_OBJECT_NAME_COLLISION: NTSTATUS = NTSTATUS(0xC0000035);
+pub const STATUS_WRONG_PASSWORD: NTSTATUS = NTSTATUS(0xC000006A);
 pub const STATUS_INSUFFICIENT_RESOURCES: NTSTATUS = NTSTATUS(0xC000009A);
 pub const STATUS_NOT_SUPPORTED: NTSTATUS = NTSTATUS(0xC00000BB);
 pub const STATUS_CANCELLED: NTSTATUS = NTSTATUS(0xC0000120);
+pub const STATUS_INVALID_DEVICE_STATE: NTSTATUS = NTSTATUS(0xC0000184);
+pub const STATUS_NOT_FOUND: NTSTATUS = NTSTATUS(0xC0000225);
+pub const STATUS_CRYPTO_SYSTEM_INVALID: NTSTATUS = NTSTATUS(0xC00002F3);
@@ -9,5 +9,6 @@ pub mod wdffuncenum;
 pub mod wdfglobals;
 pub mod wdfio;
 pub mod wdfobject;
+pub mod wdfregistry;
 pub mod wdfrequest;
 pub mod wdftypes;
@@ -3,15 +3,19 @@ use core::{default::default, mem};
 use libnveth_macros::*;
 
 use crate::windows::{
+    km::wdm::ACCESS_MASK,
     shared::ntdef::{NTSTATUS, UNICODE_STRING},
     wdf::kmdf::{
         wdfobject::WDF_OBJECT_ATTRIBUTES,
         wdftypes::{
-            WDFCMRESLIST, WDFDEVICE, WDFDEVICE_INIT, WDFFILEOBJECT, WDFREQUEST, WDF_TRI_STATE,
+            WDFCMRESLIST, WDFDEVICE, WDFDEVICE_INIT, WDFFILEOBJECT, WDFKEY, WDFREQUEST,
+            WDF_TRI_STATE,
         },
     },
 };
 
+pub const PLUGPLAY_REGKEY_DRIVER: u32 = 2;
+
 c_type!(
     pub enum WDF_FILEOBJECT_CLASS {
         WdfFileObjectInvalid = 0,
@@ -196,6 +200,19 @@ wdf_fn!(
     }
 );
 
+wdf_fn!(
+    #[irql_requires_max(PASSIVE_LEVEL)]
+    pub fn WdfDeviceOpenRegistryKey(
+        device: WDFDEVICE,
+        device_instance_key_type: u32,
+        desired_access: ACCESS_MASK,
+        key_attributes: *const WDF_OBJECT_ATTRIBUTES,
+        key: *mut WDFKEY,
+    ) -> NTSTATUS {
+        WdfDeviceOpenRegistryKeyTableIndex
+    }
+);
+
 wdf_fn!(
     #[irql_requires_max(PASSIVE_LEVEL)]
     pub fn WdfDeviceCreateSymbolicLink(
@@ -9,6 +9,7 @@ c_type!(
 );
 
 impl WDFFUNCENUM {
+    pub const WdfDeviceOpenRegistryKeyTableIndex: isize = 48;
     pub const WdfDeviceInitSetPnpPowerEventCallbacksTableIndex: isize = 55;
     pub const WdfDeviceInitSetFileObjectConfigTableIndex: isize = 71;
     pub const WdfDeviceCreateTableIndex: isize = 75;
@@ -17,8 +18,15 @@ impl WDFFUNCENUM {
     pub const WdfFileObjectGetDeviceTableIndex: isize = 139;
     pub const WdfIoQueueCreateTableIndex: isize = 152;
     pub const WdfIoQueueGetDeviceTableIndex: isize = 157;
+    pub const WdfIoQueueRetrieveNextRequestTableIndex: isize = 158;
     pub const WdfObjectGetTypedContextWorkerTableIndex: isize = 202;
+    pub const WdfRegistryCloseTableIndex: isize = 231;
+    pub const WdfRegistryQueryValueTableIndex: isize = 235;
+    pub const WdfRegistryAssignValueTableIndex: isize = 241;
     pub const WdfRequestCompleteTableIndex: isize = 263;
+    pub const WdfRequestCompleteWithInformationTableIndex: isize = 265;
     pub const WdfRequestRetrieveInputBufferTableIndex: isize = 269;
+    pub const WdfRequestRetrieveOutputBufferTableIndex: isize = 270;
     pub const WdfRequestGetFileObjectTableIndex: isize = 277;
+    pub const WdfRequestForwardToIoQueueTableIndex: isize = 281;
 }
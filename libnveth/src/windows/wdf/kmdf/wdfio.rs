@@ -15,6 +15,7 @@ c_type!(
         WdfIoQueueDispatchInvalid = 0,
         WdfIoQueueDispatchSequential,
         WdfIoQueueDispatchParallel,
+        WdfIoQueueDispatchManual,
     }
 );
 
@@ -103,6 +104,13 @@ pub fn WDF_IO_QUEUE_CONFIG_INIT_DEFAULT_QUEUE(
     config
 }
 
+pub fn WDF_IO_QUEUE_CONFIG_INIT(dispatch_type: WDF_IO_QUEUE_DISPATCH_TYPE) -> WDF_IO_QUEUE_CONFIG {
+    WDF_IO_QUEUE_CONFIG {
+        default_queue: false,
+        ..WDF_IO_QUEUE_CONFIG_INIT_DEFAULT_QUEUE(dispatch_type)
+    }
+}
+
 wdf_fn!(
     #[irql_requires_max(DISPATCH_LEVEL)]
     pub fn WdfIoQueueCreate(
@@ -121,3 +129,13 @@ wdf_fn!(
         WdfIoQueueGetDeviceTableIndex
     }
 );
+
+wdf_fn!(
+    #[irql_requires_max(DISPATCH_LEVEL)]
+    pub fn WdfIoQueueRetrieveNextRequest(
+        queue: WDFQUEUE,
+        out_request: *mut WDFREQUEST,
+    ) -> NTSTATUS {
+        WdfIoQueueRetrieveNextRequestTableIndex
+    }
+);
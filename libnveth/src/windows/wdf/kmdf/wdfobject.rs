@@ -6,6 +6,8 @@ c_type!(
     pub enum WDF_EXECUTION_LEVEL {
         WdfExecutionLevelInvalid = 0x00,
         WdfExecutionLevelInheritFromParent,
+        WdfExecutionLevelPassive,
+        WdfExecutionLevelDispatch,
     }
 );
 
@@ -0,0 +1,40 @@
+use libnveth_macros::*;
+
+use crate::windows::{
+    shared::ntdef::{NTSTATUS, PVOID, UNICODE_STRING},
+    wdf::kmdf::wdftypes::WDFKEY,
+};
+
+wdf_fn!(
+    #[irql_requires_max(PASSIVE_LEVEL)]
+    pub fn WdfRegistryClose(key: WDFKEY) -> () {
+        WdfRegistryCloseTableIndex
+    }
+);
+
+wdf_fn!(
+    #[irql_requires_max(PASSIVE_LEVEL)]
+    pub fn WdfRegistryQueryValue(
+        key: WDFKEY,
+        value_name: *const UNICODE_STRING,
+        value_length: u32,
+        value: PVOID,
+        value_length_queried: *mut u32,
+        value_type: *mut u32,
+    ) -> NTSTATUS {
+        WdfRegistryQueryValueTableIndex
+    }
+);
+
+wdf_fn!(
+    #[irql_requires_max(PASSIVE_LEVEL)]
+    pub fn WdfRegistryAssignValue(
+        key: WDFKEY,
+        value_name: *const UNICODE_STRING,
+        value_type: u32,
+        value_length: u32,
+        value: PVOID,
+    ) -> NTSTATUS {
+        WdfRegistryAssignValueTableIndex
+    }
+);
@@ -2,7 +2,7 @@ use libnveth_macros::*;
 
 use crate::windows::{
     shared::ntdef::{NTSTATUS, PVOID},
-    wdf::kmdf::wdftypes::{WDFFILEOBJECT, WDFREQUEST},
+    wdf::kmdf::wdftypes::{WDFFILEOBJECT, WDFQUEUE, WDFREQUEST},
 };
 
 wdf_fn!(
@@ -12,6 +12,17 @@ wdf_fn!(
     }
 );
 
+wdf_fn!(
+    #[irql_requires_max(DISPATCH_LEVEL)]
+    pub fn WdfRequestCompleteWithInformation(
+        request: WDFREQUEST,
+        status: NTSTATUS,
+        information: usize,
+    ) -> () {
+        WdfRequestCompleteWithInformationTableIndex
+    }
+);
+
 wdf_fn!(
     #[irql_requires_max(DISPATCH_LEVEL)]
     pub fn WdfRequestRetrieveInputBuffer(
@@ -24,9 +35,31 @@ wdf_fn!(
     }
 );
 
+wdf_fn!(
+    #[irql_requires_max(DISPATCH_LEVEL)]
+    pub fn WdfRequestRetrieveOutputBuffer(
+        request: WDFREQUEST,
+        minimum_required_size: usize,
+        buffer: *mut PVOID,
+        length: *mut usize,
+    ) -> NTSTATUS {
+        WdfRequestRetrieveOutputBufferTableIndex
+    }
+);
+
 wdf_fn!(
     #[irql_requires_max(DISPATCH_LEVEL)]
     pub fn WdfRequestGetFileObject(request: WDFREQUEST) -> WDFFILEOBJECT {
         WdfRequestGetFileObjectTableIndex
     }
 );
+
+wdf_fn!(
+    #[irql_requires_max(DISPATCH_LEVEL)]
+    pub fn WdfRequestForwardToIoQueue(
+        request: WDFREQUEST,
+        destination_queue: WDFQUEUE,
+    ) -> NTSTATUS {
+        WdfRequestForwardToIoQueueTableIndex
+    }
+);
@@ -20,6 +20,7 @@ win_handle!(WDFREQUEST);
 win_handle!(WDFFILEOBJECT);
 win_handle!(WDFDMAENABLER);
 win_handle!(WDFCMRESLIST);
+win_handle!(WDFKEY);
 
 impl From<WDFDEVICE> for WDFOBJECT {
     fn from(handle: WDFDEVICE) -> Self {
@@ -1,6 +1,7 @@
 use core::{
     ptr,
     sync::atomic::{AtomicBool, Ordering::Relaxed},
+    time::Duration,
 };
 
 use crate::{
@@ -87,6 +88,11 @@ impl WorkerState {
         !self.is_canceled()
     }
 
+    pub fn wait_for_work_timeout(&mut self, timeout: Duration) -> bool {
+        self.inner.wait_timeout(timeout);
+        !self.is_canceled()
+    }
+
     pub fn signal_stopped(&mut self) {
         self.inner_stopped.set();
     }
@@ -5,16 +5,15 @@ use std::{
 
 use winapi::shared::{
     bcrypt::{
-        BCryptBuffer, BCryptBufferDesc, BCryptDeriveKey, BCryptExportKey, BCryptFinalizeKeyPair,
-        BCryptGenerateKeyPair, BCryptImportKeyPair, BCryptOpenAlgorithmProvider,
-        BCryptSecretAgreement, BCryptSetProperty, BCRYPTBUFFER_VERSION, BCRYPT_ECCPRIVATE_BLOB,
+        BCryptExportKey, BCryptFinalizeKeyPair, BCryptGenerateKeyPair, BCryptImportKeyPair,
+        BCryptOpenAlgorithmProvider, BCryptSetProperty, BCRYPT_ECCPRIVATE_BLOB,
         BCRYPT_ECCPUBLIC_BLOB, BCRYPT_ECC_CURVE_25519, BCRYPT_ECC_CURVE_NAME,
-        BCRYPT_ECDH_ALGORITHM, BCRYPT_KDF_HASH, BCRYPT_SHA256_ALGORITHM, KDF_HASH_ALGORITHM,
+        BCRYPT_ECDH_ALGORITHM,
     },
     ntstatus::STATUS_SUCCESS,
 };
 
-use shared::crypto::{BCryptAlgHandle, BCryptKeyHandle, BCryptSecretHandle};
+use shared::crypto::{BCryptAlgHandle, BCryptKeyHandle};
 
 use crate::error::WinError;
 
@@ -33,12 +32,10 @@ macro_rules! utf16_str {
     }};
 }
 
-const U16_BCRYPT_KDF_HASH: [u16; 5] = utf16_str!(BCRYPT_KDF_HASH);
 const U16_BCRYPT_ECCPRIVATE_BLOB: [u16; 15] = utf16_str!(BCRYPT_ECCPRIVATE_BLOB);
 const U16_BCRYPT_ECCPUBLIC_BLOB: [u16; 14] = utf16_str!(BCRYPT_ECCPUBLIC_BLOB);
 const U16_BCRYPT_ECC_CURVE_NAME: [u16; 13] = utf16_str!(BCRYPT_ECC_CURVE_NAME);
 const U16_BCRYPT_ECC_CURVE_25519: [u16; 11] = utf16_str!(BCRYPT_ECC_CURVE_25519);
-const U16_BCRYPT_SHA256_ALGORITHM: [u16; 7] = utf16_str!(BCRYPT_SHA256_ALGORITHM);
 const U16_BCRYPT_ECDH_ALGORITHM: [u16; 5] = utf16_str!(BCRYPT_ECDH_ALGORITHM);
 
 fn open_algorithm_provider() -> Result<BCryptAlgHandle, WinError> {
@@ -127,64 +124,6 @@ impl Ecdh {
         self.export_slice(&U16_BCRYPT_ECCPUBLIC_BLOB, buf)
     }
 
-    pub fn derive_key(&self, other_pub_key: &EcdhPubKey) -> Result<Vec<u8>, WinError> {
-        let mut secret_handle = MaybeUninit::uninit();
-        let status = unsafe {
-            BCryptSecretAgreement(
-                self.key_handle.as_raw(),
-                other_pub_key.key_handle.as_raw(),
-                secret_handle.as_mut_ptr(),
-                0,
-            )
-        };
-        if status != STATUS_SUCCESS {
-            return Err(WinError::from_nt_status(status));
-        }
-        let secret_handle = unsafe { BCryptSecretHandle::from_raw(secret_handle.assume_init()) };
-        let mut param = BCryptBuffer {
-            cbBuffer: mem::size_of_val(&U16_BCRYPT_SHA256_ALGORITHM) as _,
-            BufferType: KDF_HASH_ALGORITHM,
-            pvBuffer: U16_BCRYPT_SHA256_ALGORITHM.as_ptr() as *mut _,
-        };
-        let mut param_desc = BCryptBufferDesc {
-            ulVersion: BCRYPTBUFFER_VERSION,
-            cBuffers: 1,
-            pBuffers: &mut param,
-        };
-        let mut key_size = MaybeUninit::uninit();
-        let status = unsafe {
-            BCryptDeriveKey(
-                secret_handle.as_raw(),
-                U16_BCRYPT_KDF_HASH.as_ptr(),
-                &mut param_desc,
-                ptr::null_mut(),
-                0,
-                key_size.as_mut_ptr(),
-                0,
-            )
-        };
-        if status != STATUS_SUCCESS {
-            return Err(WinError::from_nt_status(status));
-        }
-        let mut key_size = unsafe { key_size.assume_init() };
-        let mut key = vec![0; key_size as _];
-        let status = unsafe {
-            BCryptDeriveKey(
-                secret_handle.as_raw(),
-                U16_BCRYPT_KDF_HASH.as_ptr(),
-                &mut param_desc,
-                key.as_mut_ptr(),
-                key_size,
-                &mut key_size,
-                0,
-            )
-        };
-        if status != STATUS_SUCCESS {
-            return Err(WinError::from_nt_status(status));
-        }
-        Ok(key)
-    }
-
     pub fn import(key_blob: &[u8]) -> Result<Self, WinError> {
         let alg_handle = open_algorithm_provider()?;
         let mut key_handle = MaybeUninit::uninit();
@@ -206,30 +145,3 @@ impl Ecdh {
         Ok(Self { key_handle })
     }
 }
-
-pub struct EcdhPubKey {
-    key_handle: BCryptKeyHandle,
-}
-
-impl EcdhPubKey {
-    pub fn import(key_blob: &[u8]) -> Result<Self, WinError> {
-        let alg_handle = open_algorithm_provider()?;
-        let mut key_handle = MaybeUninit::uninit();
-        let status = unsafe {
-            BCryptImportKeyPair(
-                alg_handle.as_raw(),
-                ptr::null_mut(),
-                U16_BCRYPT_ECCPUBLIC_BLOB.as_ptr(),
-                key_handle.as_mut_ptr(),
-                key_blob.as_ptr() as *mut _,
-                mem::size_of_val(key_blob) as _,
-                0,
-            )
-        };
-        if status != STATUS_SUCCESS {
-            return Err(WinError::from_nt_status(status));
-        }
-        let key_handle = unsafe { BCryptKeyHandle::from_raw(key_handle.assume_init()) };
-        Ok(Self { key_handle })
-    }
-}
@@ -66,6 +66,49 @@ impl Device {
             Ok(())
         }
     }
+
+    pub fn control_out_ref<T>(&self, control: u32, value: &mut T) -> Result<(), WinError> {
+        let mut returned = 0;
+        let success = unsafe {
+            DeviceIoControl(
+                self.0,
+                control,
+                ptr::null_mut(),
+                0,
+                (value as *mut T).cast(),
+                mem::size_of::<T>() as _,
+                &mut returned,
+                ptr::null_mut(),
+            )
+        };
+        if !success.as_bool() {
+            Err(WinError::new())
+        } else {
+            Ok(())
+        }
+    }
+
+    // Returns the number of elements written.
+    pub fn control_out_slice<T>(&self, control: u32, values: &mut [T]) -> Result<usize, WinError> {
+        let mut returned = 0;
+        let success = unsafe {
+            DeviceIoControl(
+                self.0,
+                control,
+                ptr::null_mut(),
+                0,
+                values.as_mut_ptr().cast(),
+                mem::size_of_val(values) as _,
+                &mut returned,
+                ptr::null_mut(),
+            )
+        };
+        if !success.as_bool() {
+            Err(WinError::new())
+        } else {
+            Ok(returned as usize / mem::size_of::<T>())
+        }
+    }
 }
 
 impl Drop for Device {
@@ -1,15 +1,13 @@
 #![feature(default_free_fn)]
-#![feature(duration_constants)]
 
 mod bindings;
 mod crypto;
 mod device;
 mod error;
 mod ext;
-mod ioctl;
-mod windows;
 
 use std::{
+    collections::BTreeMap,
     convert::TryFrom,
     default::default,
     env,
@@ -18,18 +16,28 @@ use std::{
     io::stdin,
     mem,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
-    str, thread,
-    time::Duration,
+    str,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use winapi::shared::{
     bcrypt::{BCRYPT_ECCKEY_BLOB, BCRYPT_ECDH_PRIVATE_GENERIC_MAGIC},
     ws2def::AF_INET6,
 };
 
-use crate::{crypto::ecdh::Ecdh, device::Device, ext::AsBytesExt, ioctl::*};
+use shared::{
+    forward::FloodPolicy,
+    ioctl::*,
+    liveness::PeerState,
+    mac_addr::{self, MAC_ADDR_SIZE},
+    mtu,
+    provider::{CipherSuite, HashAlgorithm},
+    routing,
+};
+
+use crate::{crypto::ecdh::Ecdh, device::Device, ext::AsBytesExt};
 
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -42,6 +50,15 @@ struct Config {
     hash: Hash,
     #[serde(default = "Config::default_dev")]
     dev: String,
+    #[serde(default = "Config::default_mtu")]
+    mtu: Mtu,
+    #[serde(default)]
+    flood: Flood,
+    #[serde(default = "Config::default_mode")]
+    mode: Mode,
+    // Whether to relay data between the peers, as a hub does for its spokes
+    #[serde(default)]
+    relay: bool,
     local: LocalEndPoint,
     remote: Vec<RemoteEndPoint>,
 }
@@ -62,6 +79,81 @@ impl Config {
     fn default_dev() -> String {
         "NVEth0".into()
     }
+
+    fn default_mtu() -> Mtu {
+        Mtu(mtu::DEFAULT_MTU)
+    }
+
+    fn default_mode() -> Mode {
+        Mode::Layer2
+    }
+}
+
+// The adapter's, which the underlay must fit along with `mtu::ENCAPSULATION_OVERHEAD`
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(try_from = "u32")]
+struct Mtu(u32);
+
+impl TryFrom<u32> for Mtu {
+    type Error = String;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        if !mtu::is_valid(value) {
+            return Err(format!(
+                "mtu must be between {} and {}: the underlay needs {} more bytes, up to 9000",
+                mtu::MIN_MTU,
+                mtu::MAX_MTU,
+                mtu::ENCAPSULATION_OVERHEAD,
+            ));
+        }
+        Ok(Self(value))
+    }
+}
+
+// Whether frames no peer is known for go to every peer
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+struct Flood {
+    unknown_unicast: bool,
+    multicast: bool,
+}
+
+impl Default for Flood {
+    fn default() -> Self {
+        let policy = FloodPolicy::default();
+        Self {
+            unknown_unicast: policy.unknown_unicast,
+            multicast: policy.multicast,
+        }
+    }
+}
+
+impl Flood {
+    fn to_raw(&self) -> u32 {
+        FloodPolicy {
+            unknown_unicast: self.unknown_unicast,
+            multicast: self.multicast,
+        }
+        .as_raw()
+    }
+}
+
+// Whether Ethernet frames go to peers by MAC, or IP packets by the remotes' allowed-ips
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Mode {
+    Layer2,
+    Layer3,
+}
+
+impl Mode {
+    fn to_raw(&self) -> u32 {
+        match self {
+            Self::Layer2 => routing::Mode::Layer2,
+            Self::Layer3 => routing::Mode::Layer3,
+        }
+        .as_raw()
+    }
 }
 
 #[derive(Deserialize)]
@@ -78,6 +170,20 @@ enum Cipher {
     Aes192Gcm,
     #[serde(rename = "aes-256-gcm")]
     Aes256Gcm,
+    #[serde(rename = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn to_raw(&self) -> u32 {
+        match self {
+            Self::Aes128Gcm => CipherSuite::Aes128Gcm,
+            Self::Aes192Gcm => CipherSuite::Aes192Gcm,
+            Self::Aes256Gcm => CipherSuite::Aes256Gcm,
+            Self::ChaCha20Poly1305 => CipherSuite::ChaCha20Poly1305,
+        }
+        .as_raw()
+    }
 }
 
 #[derive(Deserialize)]
@@ -88,20 +194,39 @@ enum Hash {
     Sha512,
 }
 
+impl Hash {
+    fn to_raw(&self) -> u32 {
+        match self {
+            Self::Sha256 => HashAlgorithm::Sha256,
+            Self::Sha512 => HashAlgorithm::Sha512,
+        }
+        .as_raw()
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 struct LocalEndPoint {
     endpoint: IpEndpoint,
     private_key: Option<Key>,
     public_key: Option<Key>,
+    // Derived from the public key when missing
+    mac: Option<MacAddr>,
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 struct RemoteEndPoint {
     endpoint: IpEndpoint,
-    addr: IpAddr,
+    // Once a single `addr`
+    #[serde(alias = "addr", default)]
+    allowed_ips: AllowedIps,
     public_key: Option<Key>,
+    preshared_key: Option<Key>,
+    // In seconds
+    persistent_keepalive: Option<u32>,
+    // The peer's adapter address, derived from its public key when missing
+    mac: Option<MacAddr>,
 }
 
 #[derive(Deserialize)]
@@ -112,6 +237,92 @@ enum IpEndpoint {
     Mapping { addr: IpAddr, port: u16 },
 }
 
+// An address and the length of its prefix, or a bare address for a single host
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+struct IpPrefix {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl TryFrom<String> for IpPrefix {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid prefix {:?}", value);
+        let (addr, len) = match value.find('/') {
+            Some(slash) => (&value[..slash], Some(&value[slash + 1..])),
+            None => (value.as_str(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let len = match len {
+            None => max_len,
+            Some(len) => len
+                .parse()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(invalid)?,
+        };
+        Ok(Self { addr, len })
+    }
+}
+
+impl IpPrefix {
+    fn to_raw(&self) -> VEthPrefix {
+        match self.addr {
+            IpAddr::V4(v4) => VEthPrefix {
+                addr: v4.to_ipv6_mapped().octets(),
+                len: 96 + u32::from(self.len),
+            },
+            IpAddr::V6(v6) => VEthPrefix {
+                addr: v6.octets(),
+                len: self.len.into(),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+// The prefixes a remote owns in layer-3 mode: one or a list of them
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(try_from = "OneOrMany<IpPrefix>")]
+struct AllowedIps(Vec<IpPrefix>);
+
+impl TryFrom<OneOrMany<IpPrefix>> for AllowedIps {
+    type Error = String;
+
+    fn try_from(value: OneOrMany<IpPrefix>) -> Result<Self, Self::Error> {
+        let prefixes = match value {
+            OneOrMany::One(prefix) => vec![prefix],
+            OneOrMany::Many(prefixes) => prefixes,
+        };
+        if prefixes.len() > MAX_ALLOWED_IPS {
+            return Err(format!(
+                "at most {} allowed-ips per remote",
+                MAX_ALLOWED_IPS
+            ));
+        }
+        Ok(Self(prefixes))
+    }
+}
+
+impl AllowedIps {
+    fn to_raw(&self) -> (u32, [VEthPrefix; MAX_ALLOWED_IPS]) {
+        let mut raw = [VEthPrefix::default(); MAX_ALLOWED_IPS];
+        for (raw, prefix) in raw.iter_mut().zip(&self.0) {
+            *raw = prefix.to_raw();
+        }
+        (self.0.len() as _, raw)
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(try_from = "String")]
 struct Key(Vec<u8>);
@@ -130,14 +341,342 @@ impl AsRef<[u8]> for Key {
     }
 }
 
-#[repr(C)]
-#[derive(Default)]
-struct SOCKADDR_IN6 {
-    sin6_family: u16,
-    sin6_port: u16,
-    sin6_flowinfo: u32,
-    sin6_addr: [u8; 16],
-    sin6_scope_id: u32,
+// Six pairs of hex digits separated by colons or hyphens
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+struct MacAddr([u8; MAC_ADDR_SIZE]);
+
+impl TryFrom<String> for MacAddr {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid mac {:?}", value);
+        let mut addr = [0; MAC_ADDR_SIZE];
+        let mut parts = value.split(|c| c == ':' || c == '-');
+        for byte in addr.iter_mut() {
+            let part = parts
+                .next()
+                .filter(|part| part.len() == 2 && part.bytes().all(|b| b.is_ascii_hexdigit()))
+                .ok_or_else(invalid)?;
+            *byte = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        if !mac_addr::is_assignable(&addr) {
+            return Err(format!("mac {} is not a unicast address", value));
+        }
+        Ok(Self(addr))
+    }
+}
+
+fn from_raw_socket_addr(addr: &SOCKADDR_IN6) -> SocketAddr {
+    let ip = Ipv6Addr::from(addr.addr);
+    let port = u16::from_be(addr.port);
+    match ip.to_ipv4() {
+        Some(v4) if ip.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => {
+            SocketAddr::new(v4.into(), port)
+        }
+        _ => SocketAddr::new(ip.into(), port),
+    }
+}
+
+// 100-nanosecond intervals between January 1, 1601 and January 1, 1970
+const UNIX_EPOCH_FILETIME: u64 = 116_444_736_000_000_000;
+
+fn from_filetime(filetime: u64) -> Option<SystemTime> {
+    if filetime == 0 {
+        return None;
+    }
+    let since_epoch = filetime.checked_sub(UNIX_EPOCH_FILETIME)?;
+    Some(UNIX_EPOCH + Duration::from_nanos(since_epoch * 100))
+}
+
+fn format_elapsed(filetime: u64, now: SystemTime) -> String {
+    match from_filetime(filetime) {
+        None => "never".into(),
+        Some(time) => {
+            let elapsed = now.duration_since(time).unwrap_or_default();
+            format!("{}s ago", elapsed.as_secs())
+        }
+    }
+}
+
+fn format_mac(addr: &[u8; MAC_ADDR_SIZE]) -> String {
+    let bytes: Vec<_> = addr.iter().map(|byte| format!("{:02x}", byte)).collect();
+    bytes.join(":")
+}
+
+// One line per event: the Unix time, what happened, and to which peer.
+fn format_event(event: &VEthEvent) -> String {
+    let time = from_filetime(event.time)
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let kind = EventKind::from_raw(event.kind).map_or("unknown", EventKind::name);
+    let mut line = format!(
+        "{}.{:03} {} peer {} endpoint {}",
+        time.as_secs(),
+        time.subsec_millis(),
+        kind,
+        base64::encode(event.public_key),
+        from_raw_socket_addr(&event.socket_addr)
+    );
+    if event.kind == EventKind::MacLearned.as_raw() {
+        line += &format!(" mac {}", format_mac(&event.mac_addr));
+    }
+    line
+}
+
+// The driver holds each request until there are events, so there is always one pending.
+fn log_events(device: &Device) -> Result<(), Box<dyn Error>> {
+    let mut events = vec![VEthEvent::default(); 64];
+    loop {
+        let count = device.control_out_slice(IOCTL_VETH_GET_EVENTS, &mut events)?;
+        for event in &events[..count] {
+            println!("{}", format_event(event));
+        }
+    }
+}
+
+// Fails unless the driver speaks the same IOCTL ABI, before any request whose layout could differ.
+fn open_device(dev: &str) -> Result<Device, Box<dyn Error>> {
+    let device = Device::open(format!(r"\\.\Global\{}", dev))?;
+    let mut version = 0u32;
+    if let Err(e) = device.control_out_ref(IOCTL_VETH_GET_ABI_VERSION, &mut version) {
+        return Err(format!(
+            "{} does not report its IOCTL ABI version ({}); the driver is older than nvnet, which \
+             speaks version {}",
+            dev, e, ABI_VERSION
+        )
+        .into());
+    }
+    if version != ABI_VERSION {
+        return Err(format!(
+            "{} speaks IOCTL ABI version {}, but nvnet speaks version {}; install matching builds",
+            dev, version, ABI_VERSION
+        )
+        .into());
+    }
+    Ok(device)
+}
+
+// Retries with a larger buffer as long as the driver fills it up.
+fn get_list<T: Default>(device: &Device, control: u32) -> Result<Vec<T>, Box<dyn Error>> {
+    let mut list = Vec::new();
+    let mut capacity = 16;
+    loop {
+        list.resize_with(capacity, T::default);
+        let count = device.control_out_slice(control, &mut list)?;
+        if count < capacity {
+            list.truncate(count);
+            return Ok(list);
+        }
+        capacity *= 2;
+    }
+}
+
+fn print_peers(dev: &str) -> Result<(), Box<dyn Error>> {
+    let device = open_device(dev)?;
+    let peers = get_list::<VEthPeerStatus>(&device, IOCTL_VETH_GET_PEERS)?;
+
+    let now = SystemTime::now();
+    for peer in &peers {
+        println!("peer: {}", base64::encode(peer.public_key));
+        println!("  endpoint: {}", from_raw_socket_addr(&peer.socket_addr));
+        let state = match peer.state {
+            state if state == PeerState::Up as u32 => "up",
+            _ => "down",
+        };
+        println!("  state: {}", state);
+        println!(
+            "  latest handshake: {}",
+            format_elapsed(peer.last_handshake, now)
+        );
+        println!(
+            "  latest received: {}",
+            format_elapsed(peer.last_received, now)
+        );
+        if peer.roams != 0 {
+            println!(
+                "  roamed: {} times, latest {}",
+                peer.roams,
+                format_elapsed(peer.last_roamed, now)
+            );
+        }
+        if peer.persistent_keepalive != 0 {
+            println!(
+                "  persistent keepalive: every {}s",
+                peer.persistent_keepalive
+            );
+        }
+    }
+    Ok(())
+}
+
+// One line per address, most recently seen first
+fn format_fdb(fdb: &mut [VEthFdbEntry], now: SystemTime) -> Vec<String> {
+    fdb.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+    fdb.iter()
+        .map(|entry| {
+            format!(
+                "{} peer {} seen {}",
+                format_mac(&entry.mac_addr),
+                base64::encode(entry.public_key),
+                format_elapsed(entry.last_seen, now)
+            )
+        })
+        .collect()
+}
+
+fn print_fdb(dev: &str) -> Result<(), Box<dyn Error>> {
+    let device = open_device(dev)?;
+    let mut fdb = get_list::<VEthFdbEntry>(&device, IOCTL_VETH_GET_FDB)?;
+    for line in format_fdb(&mut fdb, SystemTime::now()) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Traffic {
+    tx_packets: u64,
+    tx_bytes: u64,
+    tx_failures: u64,
+    rx_packets: u64,
+    rx_bytes: u64,
+    mac_learned: u64,
+}
+
+impl From<VEthTraffic> for Traffic {
+    fn from(traffic: VEthTraffic) -> Self {
+        Self {
+            tx_packets: traffic.tx_packets,
+            tx_bytes: traffic.tx_bytes,
+            tx_failures: traffic.tx_failures,
+            rx_packets: traffic.rx_packets,
+            rx_bytes: traffic.rx_bytes,
+            mac_learned: traffic.mac_learned,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Stats {
+    #[serde(flatten)]
+    traffic: Traffic,
+    rx_drops: BTreeMap<&'static str, u64>,
+    peers: Vec<PeerStats>,
+}
+
+#[derive(Serialize)]
+struct PeerStats {
+    public_key: String,
+    #[serde(flatten)]
+    traffic: Traffic,
+    auth_failures: u64,
+    replays: u64,
+    psk_failures: u64,
+}
+
+impl Stats {
+    fn new(stats: &VEthStats, peers: &[VEthPeerStats]) -> Self {
+        Self {
+            traffic: stats.traffic.into(),
+            rx_drops: RxDrop::ALL
+                .iter()
+                .map(|reason| reason.name())
+                .zip(stats.rx_drops.iter().copied())
+                .collect(),
+            peers: peers
+                .iter()
+                .map(|peer| PeerStats {
+                    public_key: base64::encode(peer.public_key),
+                    traffic: peer.traffic.into(),
+                    auth_failures: peer.auth_failures,
+                    replays: peer.replays,
+                    psk_failures: peer.psk_failures,
+                })
+                .collect(),
+        }
+    }
+
+    fn print(&self) {
+        println!("adapter:");
+        print_traffic(&self.traffic);
+        let drops: Vec<_> = self
+            .rx_drops
+            .iter()
+            .filter(|(_, count)| **count != 0)
+            .collect();
+        if !drops.is_empty() {
+            println!("  rx drops:");
+            for (reason, count) in drops {
+                println!("    {}: {}", reason, count);
+            }
+        }
+        for peer in &self.peers {
+            println!("peer: {}", peer.public_key);
+            print_traffic(&peer.traffic);
+            println!("  auth failures: {}", peer.auth_failures);
+            println!("  replays: {}", peer.replays);
+            println!("  psk failures: {}", peer.psk_failures);
+        }
+    }
+}
+
+fn print_traffic(traffic: &Traffic) {
+    println!(
+        "  tx: {} packets, {} bytes, {} failures",
+        traffic.tx_packets, traffic.tx_bytes, traffic.tx_failures
+    );
+    println!(
+        "  rx: {} packets, {} bytes",
+        traffic.rx_packets, traffic.rx_bytes
+    );
+    println!("  mac learned: {}", traffic.mac_learned);
+}
+
+fn print_stats(dev: &str, json: bool) -> Result<(), Box<dyn Error>> {
+    let device = open_device(dev)?;
+    let mut stats = VEthStats::default();
+    device.control_out_ref(IOCTL_VETH_GET_STATS, &mut stats)?;
+    let peers = get_list::<VEthPeerStats>(&device, IOCTL_VETH_GET_PEER_STATS)?;
+
+    let stats = Stats::new(&stats, &peers);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+    } else {
+        stats.print();
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum PeerChange<'a> {
+    Add(&'a VEthRemotePeer),
+    Update(&'a VEthRemotePeer),
+    Remove([u8; KEY_SIZE]),
+}
+
+// Brings the driver's peers, identified by their public keys, in line with the configuration. The
+// driver keeps the sessions of updated peers unless their pre-shared key changes.
+fn peer_changes<'a>(
+    current: &[[u8; KEY_SIZE]],
+    remote_peers: &'a [VEthRemotePeer],
+) -> Vec<PeerChange<'a>> {
+    let removed = current
+        .iter()
+        .filter(|key| !remote_peers.iter().any(|peer| peer.public_key == **key))
+        .map(|key| PeerChange::Remove(*key));
+    let configured = remote_peers.iter().map(|peer| {
+        if current.contains(&peer.public_key) {
+            PeerChange::Update(peer)
+        } else {
+            PeerChange::Add(peer)
+        }
+    });
+    // Peers that are gone are removed before any is added.
+    removed.chain(configured).collect()
 }
 
 #[repr(C)]
@@ -148,9 +687,18 @@ struct BCryptEccKeyBlob<T> {
     d: T,
 }
 
+fn public_key(private_key: &[u8; KEY_SIZE]) -> Result<[u8; KEY_SIZE], Box<dyn Error>> {
+    let mut key_blob = unsafe { mem::zeroed::<BCryptEccKeyBlob<[u8; KEY_SIZE]>>() };
+    key_blob.d = *private_key;
+    key_blob.header.dwMagic = BCRYPT_ECDH_PRIVATE_GENERIC_MAGIC;
+    key_blob.header.cbKey = KEY_SIZE as _;
+    let key = Ecdh::import(key_blob.as_bytes())?;
+    key.export_public_key_slice(key_blob.as_mut_bytes())?;
+    Ok(key_blob.x)
+}
+
 pub fn main() -> Result<(), Box<dyn Error>> {
     if let Some(cmd) = env::args().nth(1) {
-        const KEY_SIZE: usize = 32;
         let mut key_blob = unsafe { mem::zeroed::<BCryptEccKeyBlob<[u8; KEY_SIZE]>>() };
         match cmd.as_str() {
             "eckv" => {
@@ -166,14 +714,24 @@ pub fn main() -> Result<(), Box<dyn Error>> {
                 let key_str = key_str.trim_end();
                 let key_bytes = &mut key_blob.d;
                 base64::decode_config_slice(key_str, base64::STANDARD, key_bytes)?;
-                key_blob.header.dwMagic = BCRYPT_ECDH_PRIVATE_GENERIC_MAGIC;
-                key_blob.header.cbKey = KEY_SIZE as _;
-                let key = Ecdh::import(key_blob.as_bytes())?;
-                key.export_public_key_slice(key_blob.as_mut_bytes())?;
-                let pub_key_bytes = &key_blob.x;
+                let pub_key_bytes = public_key(&key_blob.d)?;
                 println!("{}", base64::encode(pub_key_bytes));
                 return Ok(());
             }
+            "status" => {
+                let dev = env::args().nth(2).unwrap_or_else(Config::default_dev);
+                return print_peers(&dev);
+            }
+            "fdb" => {
+                let dev = env::args().nth(2).unwrap_or_else(Config::default_dev);
+                return print_fdb(&dev);
+            }
+            "stats" => {
+                let (flags, args): (Vec<_>, Vec<_>) =
+                    env::args().skip(2).partition(|arg| arg.starts_with("--"));
+                let dev = args.into_iter().next().unwrap_or_else(Config::default_dev);
+                return print_stats(&dev, flags.iter().any(|flag| flag == "--json"));
+            }
             _ => {}
         }
     }
@@ -183,39 +741,101 @@ pub fn main() -> Result<(), Box<dyn Error>> {
 
     let config: Config = serde_yaml::from_reader(file)?;
 
-    let device = Device::open(format!(r"\\.\Global\{}", config.dev))?;
+    let device = open_device(&config.dev)?;
 
+    // A specific local address binds the socket to it, and the scope of a link-local one selects
+    // the interface.
     let to_raw_socket_addr = |endpoint: &IpEndpoint| {
-        let (addr, port) = match endpoint {
+        let (addr, port, scope_id) = match endpoint {
             IpEndpoint::Scalar(addr) => match addr {
-                SocketAddr::V4(v4) => (v4.ip().to_ipv6_mapped().octets(), v4.port()),
-                SocketAddr::V6(v6) => (v6.ip().octets(), v6.port()),
+                SocketAddr::V4(v4) => (v4.ip().to_ipv6_mapped().octets(), v4.port(), 0),
+                SocketAddr::V6(v6) => (v6.ip().octets(), v6.port(), v6.scope_id()),
             },
             IpEndpoint::Mapping { addr, port } => match addr {
-                IpAddr::V4(v4) => (v4.to_ipv6_mapped().octets(), *port),
-                IpAddr::V6(v6) => (v6.octets(), *port),
+                IpAddr::V4(v4) => (v4.to_ipv6_mapped().octets(), *port, 0),
+                IpAddr::V6(v6) => (v6.octets(), *port, 0),
             },
         };
         SOCKADDR_IN6 {
-            sin6_family: AF_INET6 as _,
-            sin6_port: port.to_be(),
-            sin6_addr: addr,
+            family: AF_INET6 as _,
+            port: port.to_be(),
+            addr,
+            scope_id,
             ..default()
         }
     };
 
+    let to_raw_key = |key: &Option<Key>, name: &str| -> Result<[u8; KEY_SIZE], Box<dyn Error>> {
+        let key = key.as_ref().ok_or_else(|| format!("missing {}", name))?;
+        let key = <[u8; KEY_SIZE]>::try_from(key.as_ref())
+            .map_err(|_| format!("{} must be {} bytes", name, KEY_SIZE))?;
+        Ok(key)
+    };
+
+    let local_key = VEthLocalKey {
+        private_key: to_raw_key(&config.local.private_key, "local private-key")?,
+        cipher_suite: config.cipher.to_raw(),
+        hash: config.hash.to_raw(),
+    };
+    device.control_in_ref(IOCTL_VETH_SET_LOCAL_KEY, &local_key)?;
+
+    // The driver restarts the adapter if the MTU or the address changed, before the socket carries
+    // any traffic.
+    device.control_in_ref(IOCTL_VETH_SET_MTU, &config.mtu.0)?;
+    let mac = match &config.local.mac {
+        Some(mac) => mac.0,
+        None => mac_addr::from_public_key(&public_key(&local_key.private_key)?),
+    };
+    device.control_in_ref(IOCTL_VETH_SET_MAC_ADDR, &mac)?;
+
     let local_socket_addr = to_raw_socket_addr(&config.local.endpoint);
     device.control_in_ref(IOCTL_VETH_SET_LOCAL_ADDR, &local_socket_addr)?;
 
+    device.control_in_ref(IOCTL_VETH_SET_FLOOD_POLICY, &config.flood.to_raw())?;
+    device.control_in_ref(IOCTL_VETH_SET_MODE, &config.mode.to_raw())?;
+    device.control_in(IOCTL_VETH_SET_RELAY, config.relay)?;
+
+    let mut remote_peers = Vec::new();
     for remote in &config.remote {
-        let remote_socket_addr = to_raw_socket_addr(&remote.endpoint);
-        device.control_in_ref(IOCTL_VETH_ADD_REMOTE_PEER, &remote_socket_addr)?;
+        let (allowed_ips_count, allowed_ips) = remote.allowed_ips.to_raw();
+        let remote_public_key = to_raw_key(&remote.public_key, "remote public-key")?;
+        remote_peers.push(VEthRemotePeer {
+            socket_addr: to_raw_socket_addr(&remote.endpoint),
+            public_key: remote_public_key,
+            preshared_key: match &remote.preshared_key {
+                None => [0; KEY_SIZE],
+                key => to_raw_key(key, "remote preshared-key")?,
+            },
+            persistent_keepalive: remote.persistent_keepalive.unwrap_or(0),
+            mac_addr: match &remote.mac {
+                Some(mac) => mac.0,
+                None => mac_addr::from_public_key(&remote_public_key),
+            },
+            allowed_ips_count,
+            allowed_ips,
+        });
+    }
+    let current: Vec<_> = get_list::<VEthPeerStatus>(&device, IOCTL_VETH_GET_PEERS)?
+        .iter()
+        .map(|peer| peer.public_key)
+        .collect();
+    for change in peer_changes(&current, &remote_peers) {
+        match change {
+            PeerChange::Add(remote_peer) => {
+                device.control_in_ref(IOCTL_VETH_ADD_REMOTE_PEER, remote_peer)?
+            }
+            PeerChange::Update(remote_peer) => {
+                device.control_in_ref(IOCTL_VETH_UPDATE_REMOTE_PEER, remote_peer)?
+            }
+            PeerChange::Remove(public_key) => {
+                device.control_in_ref(IOCTL_VETH_REMOVE_REMOTE_PEER, &public_key)?
+            }
+        }
     }
 
     device.control_in(IOCTL_VETH_SET_CONNECT_STATE, true)?;
 
-    thread::sleep(Duration::MAX);
-    Ok(())
+    log_events(&device)
 }
 
 macro_rules! assert_matches {
@@ -246,18 +866,28 @@ curve: curve25519
 cipher: aes-256-gcm
 hash: sha-512
 dev: NVEth1
+mtu: 8868
+flood:
+  unknown-unicast: false
+  multicast: true
+mode: layer3
+relay: true
 
 local:
   endpoint: '[::]:5001'
   private-key: gFDRW4oyzSBH9ig8JHs4f9MA5xc6zZDOj2Z/hDB3gEM=
   public-key: 1SWFFZlt8UBfD2BCxd7YgM/oqc31I2evWsAOAygtbBM=
+  mac: 02:15:5d:01:02:03
 
 remote:
   - endpoint:
       addr: 169.254.123.180
       port: 5001
-    addr: 0.0.0.0
+    allowed-ips: [10.0.0.2, 192.168.10.0/24, 'fd00::/64']
     public-key: x9FYGi0CJlt810zXnokkoZ2alQd6gksA2fbJil2leE8=
+    preshared-key: FpCyhws9cxwWoV4xELtfJvjJN+zQVRPISllRWgeopVE=
+    persistent-keepalive: 25
+    mac: 02:15:5d:04:05:06
 ";
 
     let config: Config = serde_yaml::from_str(s)?;
@@ -266,6 +896,16 @@ remote:
     assert_matches!(config.cipher, Cipher::Aes256Gcm);
     assert_matches!(config.hash, Hash::Sha512);
     assert_eq!(config.dev.as_str(), "NVEth1");
+    assert_eq!(config.mtu, Mtu(8868));
+    assert_eq!(
+        config.flood,
+        Flood {
+            unknown_unicast: false,
+            multicast: true,
+        }
+    );
+    assert_eq!(config.mode, Mode::Layer3);
+    assert!(config.relay);
 
     let addr = assert_matches!(&config.local.endpoint, IpEndpoint::Scalar(addr));
     let v6 = assert_matches!(addr, SocketAddr::V6(v6));
@@ -282,17 +922,48 @@ remote:
         base64::encode(key),
         String::from("1SWFFZlt8UBfD2BCxd7YgM/oqc31I2evWsAOAygtbBM="),
     );
+    assert_eq!(
+        config.local.mac,
+        Some(MacAddr([0x02, 0x15, 0x5d, 0x01, 0x02, 0x03]))
+    );
 
     let peer = assert_matches!(&config.remote.as_slice(), [peer]);
     let (addr, port) = assert_matches!(&peer.endpoint, IpEndpoint::Mapping { addr, port });
     assert_eq!(*addr, Ipv4Addr::new(169, 254, 123, 180));
     assert_eq!(*port, 5001);
+    assert_eq!(
+        peer.allowed_ips.0,
+        [
+            IpPrefix {
+                addr: Ipv4Addr::new(10, 0, 0, 2).into(),
+                len: 32,
+            },
+            IpPrefix {
+                addr: Ipv4Addr::new(192, 168, 10, 0).into(),
+                len: 24,
+            },
+            IpPrefix {
+                addr: "fd00::".parse().unwrap(),
+                len: 64,
+            },
+        ]
+    );
 
     let key = peer.public_key.as_ref().unwrap();
     assert_eq!(
         base64::encode(key),
         String::from("x9FYGi0CJlt810zXnokkoZ2alQd6gksA2fbJil2leE8="),
     );
+    let key = peer.preshared_key.as_ref().unwrap();
+    assert_eq!(
+        base64::encode(key),
+        String::from("FpCyhws9cxwWoV4xELtfJvjJN+zQVRPISllRWgeopVE="),
+    );
+    assert_eq!(peer.persistent_keepalive, Some(25));
+    assert_eq!(
+        peer.mac,
+        Some(MacAddr([0x02, 0x15, 0x5d, 0x04, 0x05, 0x06]))
+    );
 
     Ok(())
 }
@@ -324,16 +995,334 @@ remote:
     assert_variant_eq!(config.cipher, Config::default_cipher());
     assert_variant_eq!(config.hash, Config::default_hash());
     assert_eq!(config.dev, Config::default_dev());
+    assert_eq!(config.mtu, Config::default_mtu());
+    assert_eq!(config.flood, Flood::default());
+    assert_eq!(config.mode, Config::default_mode());
+    assert!(!config.relay);
 
     assert_matches!(config.local.private_key, None);
     assert_matches!(config.local.public_key, None);
+    assert_matches!(config.local.mac, None);
 
     let peer = assert_matches!(&config.remote.as_slice(), [peer]);
     let (addr, port) = assert_matches!(&peer.endpoint, IpEndpoint::Mapping { addr, port });
     assert_eq!(*addr, Ipv4Addr::new(169, 254, 123, 180));
     assert_eq!(*port, 5001);
+    // The former single address
+    assert_eq!(
+        peer.allowed_ips.0,
+        [IpPrefix {
+            addr: Ipv4Addr::UNSPECIFIED.into(),
+            len: 32,
+        }]
+    );
 
     assert_matches!(peer.public_key, None);
+    assert_matches!(peer.preshared_key, None);
+    assert_matches!(peer.persistent_keepalive, None);
+    assert_matches!(peer.mac, None);
+
+    Ok(())
+}
+
+#[test]
+fn config_cipher() -> Result<(), serde_yaml::Error> {
+    let ciphers = [
+        ("aes-128-gcm", CipherSuite::Aes128Gcm),
+        ("aes-192-gcm", CipherSuite::Aes192Gcm),
+        ("aes-256-gcm", CipherSuite::Aes256Gcm),
+        ("chacha20-poly1305", CipherSuite::ChaCha20Poly1305),
+    ];
+    for (name, suite) in ciphers.iter() {
+        let s = format!(
+            r"
+cipher: {}
+
+local:
+  endpoint: '[::]:5001'
+
+remote: []
+",
+            name,
+        );
+
+        let config: Config = serde_yaml::from_str(&s)?;
+
+        assert_eq!(config.cipher.to_raw(), suite.as_raw());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn config_hash() -> Result<(), serde_yaml::Error> {
+    let hashes = [
+        ("sha-256", HashAlgorithm::Sha256),
+        ("sha-512", HashAlgorithm::Sha512),
+    ];
+    for (name, hash) in hashes.iter() {
+        let s = format!(
+            r"
+hash: {}
+
+local:
+  endpoint: '[::]:5001'
+
+remote: []
+",
+            name,
+        );
+
+        let config: Config = serde_yaml::from_str(&s)?;
+
+        assert_eq!(config.hash.to_raw(), hash.as_raw());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn config_mac() {
+    let mac = |s: &str| serde_yaml::from_str::<MacAddr>(s).map(|mac| mac.0);
+    assert_eq!(
+        mac("02:15:5d:0A:0b:0c").unwrap(),
+        [2, 0x15, 0x5d, 10, 11, 12]
+    );
+    assert_eq!(mac("02-00-00-00-00-01").unwrap(), [2, 0, 0, 0, 0, 1]);
+    assert!(mac("02:00:00:00:00").is_err());
+    assert!(mac("02:00:00:00:00:01:02").is_err());
+    assert!(mac("02:00:00:00:00:1").is_err());
+    assert!(mac("02:00:00:00:00:+1").is_err());
+    // Multicast and unspecified addresses cannot be assigned.
+    assert!(mac("01:00:5e:00:00:01").is_err());
+    assert!(mac("00:00:00:00:00:00").is_err());
+}
+
+#[test]
+fn config_mtu() {
+    let mtu = |s: &str| serde_yaml::from_str::<Mtu>(s).map(|mtu| mtu.0);
+    assert_eq!(mtu("1280").unwrap(), 1280);
+    assert_eq!(mtu("8868").unwrap(), 8868);
+    assert!(mtu("1279").is_err());
+    // Too large for a 9000-byte underlay once encapsulated
+    assert!(mtu("9000").is_err());
+}
+
+#[test]
+fn config_flood() {
+    let flood = |s: &str| serde_yaml::from_str::<Flood>(s).map(|flood| flood.to_raw());
+    assert_eq!(flood("{}").unwrap(), FloodPolicy::default().as_raw());
+    assert_eq!(
+        flood("multicast: false").unwrap(),
+        FloodPolicy {
+            unknown_unicast: true,
+            multicast: false,
+        }
+        .as_raw()
+    );
+    assert_eq!(
+        flood("{unknown-unicast: false, multicast: false}").unwrap(),
+        0
+    );
+}
+
+#[test]
+fn config_allowed_ips() {
+    let prefix = |s: &str| serde_yaml::from_str::<IpPrefix>(s).map(|prefix| prefix.to_raw());
+    let raw = prefix("10.1.2.0/24").unwrap();
+    assert_eq!(
+        raw.addr,
+        Ipv4Addr::new(10, 1, 2, 0).to_ipv6_mapped().octets()
+    );
+    assert_eq!(raw.len, 120);
+    assert_eq!(prefix("0.0.0.0/0").unwrap().len, 96);
+    assert_eq!(prefix("'::/0'").unwrap().len, 0);
+    assert_eq!(prefix("'fd00::1'").unwrap().len, 128);
+    assert!(prefix("10.1.2.0/33").is_err());
+    assert!(prefix("'fd00::/129'").is_err());
+    assert!(prefix("10.1.2.0/").is_err());
+    assert!(prefix("10.1.2/24").is_err());
+
+    let allowed_ips = |s: &str| serde_yaml::from_str::<AllowedIps>(s).map(|ips| ips.to_raw());
+    let (count, raw) = allowed_ips("10.0.0.1").unwrap();
+    assert_eq!(count, 1);
+    assert_eq!(raw[0].len, 128);
+    assert_eq!(allowed_ips("[]").unwrap().0, 0);
+    let list = format!("[{}]", vec!["10.0.0.1"; MAX_ALLOWED_IPS].join(", "));
+    assert_eq!(allowed_ips(&list).unwrap().0, MAX_ALLOWED_IPS as u32);
+    let list = format!("[{}]", vec!["10.0.0.1"; MAX_ALLOWED_IPS + 1].join(", "));
+    assert!(allowed_ips(&list).is_err());
+}
+
+#[test]
+fn config_mode() {
+    let mode = |s: &str| serde_yaml::from_str::<Mode>(s).map(|mode| mode.to_raw());
+    assert_eq!(mode("layer2").unwrap(), routing::Mode::Layer2.as_raw());
+    assert_eq!(mode("layer3").unwrap(), routing::Mode::Layer3.as_raw());
+    assert!(mode("tun").is_err());
+}
+
+#[test]
+fn peer_status_socket_addr() {
+    let v4 = SOCKADDR_IN6 {
+        family: AF_INET6 as _,
+        port: 5001u16.to_be(),
+        addr: Ipv4Addr::new(169, 254, 123, 180).to_ipv6_mapped().octets(),
+        ..default()
+    };
+    assert_eq!(
+        from_raw_socket_addr(&v4),
+        "169.254.123.180:5001".parse::<SocketAddr>().unwrap(),
+    );
+
+    let v6 = SOCKADDR_IN6 {
+        addr: Ipv6Addr::LOCALHOST.octets(),
+        ..v4
+    };
+    assert_eq!(
+        from_raw_socket_addr(&v6),
+        "[::1]:5001".parse::<SocketAddr>().unwrap(),
+    );
+}
+
+#[test]
+fn peer_status_time() {
+    assert_eq!(from_filetime(0), None);
+    assert_eq!(from_filetime(UNIX_EPOCH_FILETIME), Some(UNIX_EPOCH));
+    assert_eq!(
+        from_filetime(UNIX_EPOCH_FILETIME + 10_000_000),
+        Some(UNIX_EPOCH + Duration::from_secs(1)),
+    );
 
+    let now = UNIX_EPOCH + Duration::from_secs(100);
+    assert_eq!(format_elapsed(0, now), "never");
+    assert_eq!(
+        format_elapsed(UNIX_EPOCH_FILETIME + 30 * 10_000_000, now),
+        "70s ago"
+    );
+}
+
+#[test]
+fn event_format() {
+    let event = VEthEvent {
+        kind: EventKind::MacLearned.as_raw(),
+        public_key: [0; KEY_SIZE],
+        socket_addr: SOCKADDR_IN6 {
+            family: AF_INET6 as _,
+            port: 5001u16.to_be(),
+            addr: Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped().octets(),
+            ..default()
+        },
+        mac_addr: [0x02, 0x00, 0x5e, 0x10, 0xab, 0xcd],
+        time: UNIX_EPOCH_FILETIME + 15_000_000,
+    };
+    assert_eq!(
+        format_event(&event),
+        "1.500 mac-learned peer AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA= endpoint \
+         192.0.2.1:5001 mac 02:00:5e:10:ab:cd",
+    );
+
+    let event = VEthEvent {
+        kind: EventKind::PeerDown.as_raw(),
+        ..event
+    };
+    assert!(format_event(&event).starts_with("1.500 peer-down peer "));
+    assert!(!format_event(&event).contains(" mac "));
+}
+
+#[test]
+fn fdb_format() {
+    let entry = |last: u8, secs_ago: u64| VEthFdbEntry {
+        public_key: [last; KEY_SIZE],
+        mac_addr: [0x02, 0, 0, 0, 0, last],
+        last_seen: UNIX_EPOCH_FILETIME + (100 - secs_ago) * 10_000_000,
+    };
+    let mut fdb = [entry(1, 30), entry(2, 5)];
+    let now = UNIX_EPOCH + Duration::from_secs(100);
+    assert_eq!(
+        format_fdb(&mut fdb, now),
+        [
+            format!(
+                "02:00:00:00:00:02 peer {} seen 5s ago",
+                base64::encode([2; KEY_SIZE])
+            ),
+            format!(
+                "02:00:00:00:00:01 peer {} seen 30s ago",
+                base64::encode([1; KEY_SIZE])
+            ),
+        ]
+    );
+}
+
+#[test]
+fn peer_reconcile() {
+    let remote_peer = |key: u8| VEthRemotePeer {
+        socket_addr: default(),
+        public_key: [key; KEY_SIZE],
+        preshared_key: [0; KEY_SIZE],
+        persistent_keepalive: 0,
+        mac_addr: [0x02, 0, 0, 0, 0, key],
+        allowed_ips_count: 0,
+        allowed_ips: default(),
+    };
+    let remote_peers = [remote_peer(1), remote_peer(2), remote_peer(3)];
+
+    let changes = peer_changes(&[], &remote_peers);
+    assert_eq!(
+        changes,
+        remote_peers.iter().map(PeerChange::Add).collect::<Vec<_>>(),
+    );
+
+    let changes = peer_changes(&[[4; KEY_SIZE], [2; KEY_SIZE]], &remote_peers);
+    assert_eq!(
+        changes,
+        [
+            PeerChange::Remove([4; KEY_SIZE]),
+            PeerChange::Add(&remote_peers[0]),
+            PeerChange::Update(&remote_peers[1]),
+            PeerChange::Add(&remote_peers[2]),
+        ],
+    );
+
+    let changes = peer_changes(&[[1; KEY_SIZE]], &[]);
+    assert_eq!(changes, [PeerChange::Remove([1; KEY_SIZE])]);
+}
+
+#[test]
+fn stats_json() -> Result<(), serde_json::Error> {
+    let traffic = VEthTraffic {
+        tx_packets: 1,
+        tx_bytes: 100,
+        tx_failures: 2,
+        rx_packets: 3,
+        rx_bytes: 300,
+        mac_learned: 4,
+    };
+    let mut stats = VEthStats {
+        traffic,
+        ..default()
+    };
+    stats.rx_drops[2] = 5;
+    let peer = VEthPeerStats {
+        public_key: [0; KEY_SIZE],
+        traffic,
+        auth_failures: 6,
+        replays: 7,
+        psk_failures: 8,
+    };
+
+    let json = serde_json::to_value(Stats::new(&stats, &[peer]))?;
+    assert_eq!(json["tx_packets"], 1);
+    assert_eq!(json["mac_learned"], 4);
+    assert_eq!(json["rx_drops"]["unknown-source"], 5);
+    assert_eq!(json["rx_drops"]["malformed"], 0);
+    assert_eq!(json["rx_drops"].as_object().unwrap().len(), RxDrop::COUNT);
+    let peer = &json["peers"][0];
+    assert_eq!(
+        peer["public_key"],
+        "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+    );
+    assert_eq!(peer["rx_bytes"], 300);
+    assert_eq!(peer["replays"], 7);
     Ok(())
 }
@@ -1,3 +0,0 @@
-#![allow(nonstandard_style)]
-
-pub mod km;
@@ -0,0 +1,356 @@
+// Protection of the handshake against floods, after WireGuard's MAC1/MAC2 and cookie reply.
+//
+// Every handshake message ends with two MACs over everything before them. MAC1 is keyed with the
+// responder's public key, so that it can drop messages from senders that do not know it before any
+// DH computation. MAC2 is keyed with a cookie, a MAC of the initiator's source address under a
+// secret the responder rotates every `COOKIE_SECRET_LIFETIME`, and is zero without one.
+//
+// Under load, the responder answers initiations without a valid MAC2 with a cookie reply that
+// carries the cookie, encrypted for the initiator with MAC1 as associated data. Initiations with a
+// valid MAC2 prove that their sender receives datagrams at its source address, and go through a
+// per-address rate limiter instead.
+//
+// Times are offsets from an arbitrary origin supplied by the caller.
+
+use core::{mem::MaybeUninit, ptr, time::Duration};
+
+use crate::{
+    fnv,
+    kdf::{self, Hash, MAX_HASH_SIZE},
+    wire::{COOKIE_SIZE, MAC_SIZE},
+};
+
+pub const COOKIE_SECRET_LIFETIME: Duration = Duration::from_secs(120);
+// An initiator stops using a cookie a bit before the responder may have rotated its secret.
+pub const COOKIE_LIFETIME: Duration = Duration::from_secs(120 - 5);
+
+// The handshake rate above which the responder considers itself under load, and for how long.
+pub const UNDER_LOAD_HANDSHAKES_PER_SECOND: u32 = 256;
+pub const UNDER_LOAD_TIME: Duration = Duration::from_secs(1);
+
+pub const PACKETS_PER_SECOND: u64 = 20;
+pub const PACKETS_BURSTABLE: u64 = 5;
+
+const MAC1_LABEL: &[u8] = b"mac1----";
+const COOKIE_LABEL: &[u8] = b"cookie--";
+
+pub type Mac = [u8; MAC_SIZE];
+pub type Cookie = [u8; COOKIE_SIZE];
+
+fn derive_key<H: Hash>(
+    hash: &H,
+    label: &[u8],
+    public_key: &[u8],
+) -> Result<[u8; MAX_HASH_SIZE], H::Error> {
+    let mut key = [0; MAX_HASH_SIZE];
+    kdf::hash(hash, &[label, public_key], &mut key)?;
+    Ok(key)
+}
+
+// Keys MAC1 of the messages sent to the owner of `public_key`.
+pub fn mac1_key<H: Hash>(hash: &H, public_key: &[u8]) -> Result<[u8; MAX_HASH_SIZE], H::Error> {
+    derive_key(hash, MAC1_LABEL, public_key)
+}
+
+// Encrypts the cookie replies sent by the owner of `public_key`.
+pub fn cookie_key<H: Hash>(hash: &H, public_key: &[u8]) -> Result<[u8; MAX_HASH_SIZE], H::Error> {
+    derive_key(hash, COOKIE_LABEL, public_key)
+}
+
+fn mac<H: Hash>(hash: &H, key: &[u8], parts: &[&[u8]]) -> Result<Mac, H::Error> {
+    let mut out = [0; MAX_HASH_SIZE];
+    kdf::hmac(hash, key, parts, &mut out)?;
+    let mut mac = [0; MAC_SIZE];
+    mac.copy_from_slice(&out[..MAC_SIZE]);
+    Ok(mac)
+}
+
+fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+// Splits a whole handshake message into the MAC1 input, MAC1 and MAC2.
+fn split_macs(msg: &[u8]) -> (&[u8], &[u8], &[u8]) {
+    let (body, macs) = msg.split_at(msg.len() - 2 * MAC_SIZE);
+    let (mac1, mac2) = macs.split_at(MAC_SIZE);
+    (body, mac1, mac2)
+}
+
+// Fills in the MACs at the end of a whole handshake message, and returns MAC1.
+pub fn write_macs<H: Hash>(
+    hash: &H,
+    mac1_key: &[u8],
+    cookie: Option<&Cookie>,
+    msg: &mut [u8],
+) -> Result<Mac, H::Error> {
+    let mac2_offset = msg.len() - MAC_SIZE;
+    let mac1_offset = mac2_offset - MAC_SIZE;
+    let mac1 = mac(hash, mac1_key, &[&msg[..mac1_offset]])?;
+    msg[mac1_offset..mac2_offset].copy_from_slice(&mac1);
+    let mac2 = match cookie {
+        None => [0; MAC_SIZE],
+        Some(cookie) => mac(hash, cookie, &[&msg[..mac2_offset]])?,
+    };
+    msg[mac2_offset..].copy_from_slice(&mac2);
+    Ok(mac1)
+}
+
+pub fn check_mac1<H: Hash>(hash: &H, mac1_key: &[u8], msg: &[u8]) -> Result<bool, H::Error> {
+    let (body, mac1, _) = split_macs(msg);
+    Ok(ct_eq(&mac(hash, mac1_key, &[body])?, mac1))
+}
+
+pub fn check_mac2<H: Hash>(hash: &H, cookie: &Cookie, msg: &[u8]) -> Result<bool, H::Error> {
+    let mac2_offset = msg.len() - MAC_SIZE;
+    Ok(ct_eq(
+        &mac(hash, cookie, &[&msg[..mac2_offset]])?,
+        &msg[mac2_offset..],
+    ))
+}
+
+// The cookie of a source address, e.g. its IP address and port.
+pub fn make_cookie<H: Hash>(hash: &H, secret: &[u8], source: &[u8]) -> Result<Cookie, H::Error> {
+    mac(hash, secret, &[source])
+}
+
+// Counts the handshakes being processed to tell whether the responder is under load.
+#[derive(Default)]
+pub struct LoadMonitor {
+    window_start: Duration,
+    handshakes: u32,
+    under_load_until: Option<Duration>,
+}
+
+impl LoadMonitor {
+    pub const fn new() -> Self {
+        Self {
+            window_start: Duration::from_secs(0),
+            handshakes: 0,
+            under_load_until: None,
+        }
+    }
+
+    // Records a handshake, and returns whether it arrived under load.
+    pub fn record(&mut self, now: Duration) -> bool {
+        if now.saturating_sub(self.window_start) >= Duration::from_secs(1) {
+            self.window_start = now;
+            self.handshakes = 0;
+        }
+        self.handshakes = self.handshakes.saturating_add(1);
+        if self.handshakes > UNDER_LOAD_HANDSHAKES_PER_SECOND {
+            self.under_load_until = Some(now + UNDER_LOAD_TIME);
+        }
+        matches!(self.under_load_until, Some(until) if now < until)
+    }
+}
+
+// Token buckets keyed by source address, in a fixed table: a source that does not fit evicts the
+// stalest bucket of its probe sequence.
+pub type RateLimiterKey = [u8; 16];
+
+const PACKET_COST: u64 = 1_000_000_000 / PACKETS_PER_SECOND;
+const MAX_TOKENS: u64 = PACKET_COST * PACKETS_BURSTABLE;
+const PROBES: usize = 4;
+
+// All zeros is an empty entry, so that a table can be initialized in place.
+#[derive(Clone, Copy)]
+struct Bucket {
+    used: bool,
+    key: RateLimiterKey,
+    // In nanoseconds
+    last: u64,
+    tokens: u64,
+}
+
+impl Bucket {
+    const EMPTY: Self = Self {
+        used: false,
+        key: [0; 16],
+        last: 0,
+        tokens: 0,
+    };
+}
+
+pub struct RateLimiter<const N: usize> {
+    seed: u64,
+    buckets: [Bucket; N],
+}
+
+impl<const N: usize> RateLimiter<N> {
+    pub const fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            buckets: [Bucket::EMPTY; N],
+        }
+    }
+
+    // Initializes a table too large to be built on the stack.
+    pub fn init(uninit: &mut MaybeUninit<Self>, seed: u64) -> &mut Self {
+        unsafe {
+            // All zeros is a valid, empty table.
+            ptr::write_bytes(uninit.as_mut_ptr(), 0, 1);
+            let init = &mut *uninit.as_mut_ptr();
+            init.seed = seed;
+            init
+        }
+    }
+
+    pub fn allow(&mut self, key: &RateLimiterKey, now: Duration) -> bool {
+        let now = now.as_nanos() as u64;
+        let start = fnv::hash(self.seed, key);
+        let probes = (0..PROBES.min(N)).map(|i| (start.wrapping_add(i)) % N);
+
+        let mut victim = start % N;
+        for index in probes {
+            let bucket = &mut self.buckets[index];
+            if bucket.used && bucket.key == *key {
+                let refill = now.saturating_sub(bucket.last);
+                bucket.tokens = bucket.tokens.saturating_add(refill).min(MAX_TOKENS);
+                bucket.last = now;
+                if bucket.tokens < PACKET_COST {
+                    return false;
+                }
+                bucket.tokens -= PACKET_COST;
+                return true;
+            }
+            let (bucket, current) = (&self.buckets[index], &self.buckets[victim]);
+            if current.used && (!bucket.used || bucket.last < current.last) {
+                victim = index;
+            }
+        }
+
+        self.buckets[victim] = Bucket {
+            used: true,
+            key: *key,
+            last: now,
+            tokens: MAX_TOKENS - PACKET_COST,
+        };
+        true
+    }
+}
+
+#[cfg(test)]
+use crate::kdf::{SHA256, SHA512};
+
+#[cfg(test)]
+fn message(len: usize) -> Vec<u8> {
+    (0..len).map(|i| i as u8).collect()
+}
+
+#[test]
+fn cookie_macs() {
+    let responder_key = [7; 32];
+    let key = mac1_key(&SHA256, &responder_key).unwrap();
+    let mut msg = message(100);
+
+    let mac1 = write_macs(&SHA256, &key, None, &mut msg).unwrap();
+    assert_eq!(msg[68..84], mac1);
+    assert_eq!(msg[84..], [0; MAC_SIZE]);
+    assert!(check_mac1(&SHA256, &key, &msg).unwrap());
+
+    let other_key = mac1_key(&SHA256, &[8; 32]).unwrap();
+    assert!(!check_mac1(&SHA256, &other_key, &msg).unwrap());
+    let other_hash = mac1_key(&SHA512, &responder_key).unwrap();
+    assert!(!check_mac1(&SHA512, &other_hash, &msg).unwrap());
+
+    let mut tampered = msg.clone();
+    tampered[0] ^= 1;
+    assert!(!check_mac1(&SHA256, &key, &tampered).unwrap());
+
+    let cookie = make_cookie(&SHA256, &[1; 32], b"\x7f\0\0\x01\x13\x89").unwrap();
+    assert!(!check_mac2(&SHA256, &cookie, &msg).unwrap());
+    write_macs(&SHA256, &key, Some(&cookie), &mut msg).unwrap();
+    assert!(check_mac1(&SHA256, &key, &msg).unwrap());
+    assert!(check_mac2(&SHA256, &cookie, &msg).unwrap());
+
+    // The cookie is bound to the source address and to the secret.
+    let other_source = make_cookie(&SHA256, &[1; 32], b"\x7f\0\0\x01\x13\x8a").unwrap();
+    let other_secret = make_cookie(&SHA256, &[2; 32], b"\x7f\0\0\x01\x13\x89").unwrap();
+    assert!(!check_mac2(&SHA256, &other_source, &msg).unwrap());
+    assert!(!check_mac2(&SHA256, &other_secret, &msg).unwrap());
+
+    assert_ne!(cookie_key(&SHA256, &responder_key).unwrap(), key);
+}
+
+#[test]
+fn cookie_load_monitor() {
+    let mut load = LoadMonitor::new();
+    let mut now = Duration::from_secs(10);
+
+    // A steady handshake rate below the threshold never counts as load.
+    let interval = Duration::from_secs(1) / (UNDER_LOAD_HANDSHAKES_PER_SECOND / 2);
+    for _ in 0..UNDER_LOAD_HANDSHAKES_PER_SECOND * 4 {
+        assert!(!load.record(now));
+        now += interval;
+    }
+
+    // A flood does, until it has stopped for `UNDER_LOAD_TIME`.
+    now += Duration::from_secs(1);
+    let flood = Duration::from_micros(100);
+    let under_load = (0..UNDER_LOAD_HANDSHAKES_PER_SECOND * 2)
+        .filter(|_| {
+            now += flood;
+            load.record(now)
+        })
+        .count();
+    assert_eq!(under_load, UNDER_LOAD_HANDSHAKES_PER_SECOND as usize);
+
+    now += UNDER_LOAD_TIME / 2;
+    assert!(load.record(now));
+    now += UNDER_LOAD_TIME;
+    assert!(!load.record(now));
+}
+
+#[test]
+fn cookie_rate_limiter_under_load() {
+    let mut limiter = Box::new(RateLimiter::<256>::new(0x5eed));
+    let honest = [1; 16];
+    let attackers = (0..64u8).map(|i| {
+        let mut key = [0xaa; 16];
+        key[15] = i;
+        key
+    });
+    let attackers: Vec<_> = attackers.collect();
+
+    // Ten seconds of every attacker sending 1000 packets per second, while the honest source
+    // sends 10.
+    let step = Duration::from_millis(1);
+    let (mut honest_allowed, mut attacker_allowed) = (0, vec![0; attackers.len()]);
+    for tick in 0..10_000u32 {
+        let now = Duration::from_secs(1) + step * tick;
+        for (attacker, allowed) in attackers.iter().zip(attacker_allowed.iter_mut()) {
+            if limiter.allow(attacker, now) {
+                *allowed += 1;
+            }
+        }
+        if tick % 100 == 0 && limiter.allow(&honest, now) {
+            honest_allowed += 1;
+        }
+    }
+
+    assert_eq!(honest_allowed, 100);
+    let expected = PACKETS_BURSTABLE + 10 * PACKETS_PER_SECOND;
+    for allowed in attacker_allowed {
+        assert!(allowed <= expected, "{} > {}", allowed, expected);
+        assert!(allowed >= expected - PACKETS_BURSTABLE);
+    }
+}
+
+#[test]
+fn cookie_rate_limiter_burst() {
+    let mut limiter = RateLimiter::<16>::new(0);
+    let key = [3; 16];
+    let now = Duration::from_secs(1);
+    for _ in 0..PACKETS_BURSTABLE {
+        assert!(limiter.allow(&key, now));
+    }
+    assert!(!limiter.allow(&key, now));
+    let refill = Duration::from_secs(1) / PACKETS_PER_SECOND as u32;
+    assert!(limiter.allow(&key, now + refill));
+    assert!(!limiter.allow(&key, now + refill));
+
+    let mut uninit = MaybeUninit::<RateLimiter<16>>::uninit();
+    let in_place = RateLimiter::init(&mut uninit, 0);
+    assert!(in_place.allow(&key, now));
+    assert_eq!(in_place.buckets.iter().filter(|b| b.used).count(), 1);
+}
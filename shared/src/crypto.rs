@@ -1,7 +1,7 @@
 use winapi::shared::{
     bcrypt::{
-        BCryptCloseAlgorithmProvider, BCryptDestroyKey, BCryptDestroySecret, BCRYPT_ALG_HANDLE,
-        BCRYPT_KEY_HANDLE, BCRYPT_SECRET_HANDLE,
+        BCryptCloseAlgorithmProvider, BCryptDestroyHash, BCryptDestroyKey, BCryptDestroySecret,
+        BCRYPT_ALG_HANDLE, BCRYPT_HASH_HANDLE, BCRYPT_KEY_HANDLE, BCRYPT_SECRET_HANDLE,
     },
     ntstatus::STATUS_SUCCESS,
 };
@@ -26,6 +26,9 @@ win_wrapper!(
     pub struct BCryptAlgHandle(BCRYPT_ALG_HANDLE);
 );
 
+unsafe impl Send for BCryptAlgHandle {}
+unsafe impl Sync for BCryptAlgHandle {}
+
 impl Drop for BCryptAlgHandle {
     fn drop(&mut self) {
         let status = unsafe { BCryptCloseAlgorithmProvider(self.as_raw(), 0) };
@@ -37,6 +40,10 @@ win_wrapper!(
     pub struct BCryptKeyHandle(BCRYPT_KEY_HANDLE);
 );
 
+// Keys carry no per-call state, so CNG allows using them from several threads at once.
+unsafe impl Send for BCryptKeyHandle {}
+unsafe impl Sync for BCryptKeyHandle {}
+
 impl Drop for BCryptKeyHandle {
     fn drop(&mut self) {
         let status = unsafe { BCryptDestroyKey(self.as_raw()) };
@@ -54,3 +61,14 @@ impl Drop for BCryptSecretHandle {
         debug_assert_eq!(status, STATUS_SUCCESS);
     }
 }
+
+win_wrapper!(
+    pub struct BCryptHashHandle(BCRYPT_HASH_HANDLE);
+);
+
+impl Drop for BCryptHashHandle {
+    fn drop(&mut self) {
+        let status = unsafe { BCryptDestroyHash(self.as_raw()) };
+        debug_assert_eq!(status, STATUS_SUCCESS);
+    }
+}
@@ -0,0 +1,257 @@
+// The forwarding database: which peer each Ethernet address is behind, learned from the source of
+// every authenticated frame. A peer may have many, e.g. when it bridges virtual machines. Entries
+// not refreshed for `AGING_TIME` are forgotten, and the table is fixed: an address that does not
+// fit evicts the stalest entry of its probe sequence.
+//
+// Times are offsets from an arbitrary origin supplied by the caller.
+
+use core::{mem::MaybeUninit, ptr, time::Duration};
+
+use crate::{
+    fnv,
+    ioctl::KEY_SIZE,
+    mac_addr::{self, MAC_ADDR_SIZE},
+};
+
+// As for 802.1D bridges
+pub const AGING_TIME: Duration = Duration::from_secs(300);
+
+const PROBES: usize = 8;
+
+// All zeros is an empty entry, so that a table can be initialized in place.
+#[derive(Clone, Copy)]
+struct Entry {
+    used: bool,
+    mac_addr: [u8; MAC_ADDR_SIZE],
+    public_key: [u8; KEY_SIZE],
+    // In nanoseconds
+    last_seen: u64,
+}
+
+impl Entry {
+    const EMPTY: Self = Self {
+        used: false,
+        mac_addr: [0; MAC_ADDR_SIZE],
+        public_key: [0; KEY_SIZE],
+        last_seen: 0,
+    };
+
+    fn is_live(&self, now: u64) -> bool {
+        self.used && now.saturating_sub(self.last_seen) < AGING_TIME.as_nanos() as u64
+    }
+
+    fn public(&self) -> FdbEntry {
+        FdbEntry {
+            mac_addr: self.mac_addr,
+            public_key: self.public_key,
+            last_seen: Duration::from_nanos(self.last_seen),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FdbEntry {
+    pub mac_addr: [u8; MAC_ADDR_SIZE],
+    pub public_key: [u8; KEY_SIZE],
+    pub last_seen: Duration,
+}
+
+pub struct Fdb<const N: usize> {
+    seed: u64,
+    entries: [Entry; N],
+}
+
+impl<const N: usize> Fdb<N> {
+    pub const fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            entries: [Entry::EMPTY; N],
+        }
+    }
+
+    // Initializes a table too large to be built on the stack.
+    pub fn init(uninit: &mut MaybeUninit<Self>, seed: u64) -> &mut Self {
+        unsafe {
+            // All zeros is a valid, empty table.
+            ptr::write_bytes(uninit.as_mut_ptr(), 0, 1);
+            let init = &mut *uninit.as_mut_ptr();
+            init.seed = seed;
+            init
+        }
+    }
+
+    fn probes(&self, mac_addr: &[u8; MAC_ADDR_SIZE]) -> impl Iterator<Item = usize> {
+        let start = fnv::hash(self.seed, mac_addr);
+        (0..PROBES.min(N)).map(move |i| start.wrapping_add(i) % N)
+    }
+
+    // Records that `mac_addr` is behind the peer, and returns whether that is news: the address was
+    // unknown, aged out or behind another peer. Group addresses are never sources.
+    pub fn learn(
+        &mut self,
+        mac_addr: &[u8; MAC_ADDR_SIZE],
+        public_key: &[u8; KEY_SIZE],
+        now: Duration,
+    ) -> bool {
+        if mac_addr::is_multicast(mac_addr) {
+            return false;
+        }
+        let now = now.as_nanos() as u64;
+
+        let mut victim = None::<usize>;
+        for index in self.probes(mac_addr) {
+            let entry = &mut self.entries[index];
+            if entry.used && entry.mac_addr == *mac_addr {
+                let news = !entry.is_live(now) || entry.public_key != *public_key;
+                entry.public_key = *public_key;
+                entry.last_seen = now;
+                return news;
+            }
+            victim = match victim {
+                Some(current) if !stalest(&self.entries[index], &self.entries[current], now) => {
+                    Some(current)
+                }
+                _ => Some(index),
+            };
+        }
+
+        if let Some(victim) = victim {
+            self.entries[victim] = Entry {
+                used: true,
+                mac_addr: *mac_addr,
+                public_key: *public_key,
+                last_seen: now,
+            };
+        }
+        true
+    }
+
+    // The peer that `mac_addr` is behind, and since when
+    pub fn lookup(&self, mac_addr: &[u8; MAC_ADDR_SIZE], now: Duration) -> Option<FdbEntry> {
+        let now = now.as_nanos() as u64;
+        self.probes(mac_addr)
+            .map(|index| &self.entries[index])
+            .find(|entry| entry.is_live(now) && entry.mac_addr == *mac_addr)
+            .map(Entry::public)
+    }
+
+    // Forgets the addresses behind a removed peer.
+    pub fn remove_peer(&mut self, public_key: &[u8; KEY_SIZE]) {
+        for entry in self.entries.iter_mut() {
+            if entry.used && entry.public_key == *public_key {
+                *entry = Entry::EMPTY;
+            }
+        }
+    }
+
+    // The entries that have not aged out, in no particular order
+    pub fn entries(&self, now: Duration) -> impl Iterator<Item = FdbEntry> + '_ {
+        let now = now.as_nanos() as u64;
+        self.entries
+            .iter()
+            .filter(move |entry| entry.is_live(now))
+            .map(Entry::public)
+    }
+}
+
+// Whether `entry` makes a better victim than `current`: empty or aged out first, then the oldest.
+fn stalest(entry: &Entry, current: &Entry, now: u64) -> bool {
+    match (entry.is_live(now), current.is_live(now)) {
+        (false, _) => current.is_live(now),
+        (true, false) => false,
+        (true, true) => entry.last_seen < current.last_seen,
+    }
+}
+
+#[cfg(test)]
+fn mac(last: u8) -> [u8; MAC_ADDR_SIZE] {
+    [0x02, 0x15, 0x5d, 0x00, 0x00, last]
+}
+
+#[cfg(test)]
+fn key(entry: Option<FdbEntry>) -> Option<[u8; KEY_SIZE]> {
+    entry.map(|entry| entry.public_key)
+}
+
+#[cfg(test)]
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+#[test]
+fn fdb_learn_many_per_peer() {
+    let mut fdb = Box::new(Fdb::<256>::new(0x5eed));
+    let (peer_a, peer_b) = ([1; KEY_SIZE], [2; KEY_SIZE]);
+    for i in 0..16 {
+        assert!(fdb.learn(&mac(i), &peer_a, secs(1)));
+    }
+    assert!(fdb.learn(&mac(100), &peer_b, secs(1)));
+    for i in 0..16 {
+        assert_eq!(key(fdb.lookup(&mac(i), secs(2))), Some(peer_a));
+    }
+    assert_eq!(key(fdb.lookup(&mac(100), secs(2))), Some(peer_b));
+    assert_eq!(key(fdb.lookup(&mac(200), secs(2))), None);
+    assert_eq!(fdb.entries(secs(2)).count(), 17);
+
+    // Refreshed, then moved
+    assert!(!fdb.learn(&mac(0), &peer_a, secs(3)));
+    assert!(fdb.learn(&mac(0), &peer_b, secs(4)));
+    assert_eq!(key(fdb.lookup(&mac(0), secs(4))), Some(peer_b));
+
+    // Never learned from a group source
+    assert!(!fdb.learn(&[0x33, 0x33, 0, 0, 0, 1], &peer_a, secs(4)));
+    assert!(!fdb.learn(&mac_addr::BROADCAST, &peer_a, secs(4)));
+}
+
+#[test]
+fn fdb_aging() {
+    let mut fdb = Fdb::<64>::new(0);
+    let peer = [1; KEY_SIZE];
+    assert!(fdb.learn(&mac(1), &peer, secs(10)));
+    assert!(fdb.learn(&mac(2), &peer, secs(100)));
+
+    let aged = secs(10) + AGING_TIME;
+    assert_eq!(key(fdb.lookup(&mac(1), aged)), None);
+    assert_eq!(key(fdb.lookup(&mac(2), aged)), Some(peer));
+    let entry = fdb.entries(aged).next().unwrap();
+    assert_eq!(entry.mac_addr, mac(2));
+    assert_eq!(entry.last_seen, secs(100));
+
+    // Relearning an aged-out address is news.
+    assert!(fdb.learn(&mac(1), &peer, aged));
+    assert_eq!(key(fdb.lookup(&mac(1), aged)), Some(peer));
+}
+
+#[test]
+fn fdb_remove_peer() {
+    let mut fdb = Fdb::<64>::new(0);
+    let (peer_a, peer_b) = ([1; KEY_SIZE], [2; KEY_SIZE]);
+    fdb.learn(&mac(1), &peer_a, secs(1));
+    fdb.learn(&mac(2), &peer_a, secs(1));
+    fdb.learn(&mac(3), &peer_b, secs(1));
+    fdb.remove_peer(&peer_a);
+    assert_eq!(key(fdb.lookup(&mac(1), secs(1))), None);
+    assert_eq!(key(fdb.lookup(&mac(2), secs(1))), None);
+    assert_eq!(key(fdb.lookup(&mac(3), secs(1))), Some(peer_b));
+}
+
+#[test]
+fn fdb_full_evicts_stalest() {
+    // Every address shares the whole table as its probe sequence.
+    let mut fdb = Fdb::<{ PROBES }>::new(0);
+    let peer = [1; KEY_SIZE];
+    for i in 0..PROBES as u8 {
+        fdb.learn(&mac(i), &peer, secs(u64::from(i) + 1));
+    }
+    assert_eq!(fdb.entries(secs(10)).count(), PROBES);
+
+    assert!(fdb.learn(&mac(100), &peer, secs(20)));
+    assert_eq!(key(fdb.lookup(&mac(0), secs(20))), None);
+    assert_eq!(key(fdb.lookup(&mac(1), secs(20))), Some(peer));
+    assert_eq!(key(fdb.lookup(&mac(100), secs(20))), Some(peer));
+
+    let mut uninit = MaybeUninit::<Fdb<16>>::uninit();
+    let in_place = Fdb::init(&mut uninit, 0);
+    assert!(in_place.learn(&mac(1), &peer, secs(1)));
+    assert_eq!(in_place.entries(secs(1)).count(), 1);
+}
@@ -0,0 +1,11 @@
+// FNV-1a, which places keys in the fixed tables. It is seeded so that the keys that collide differ
+// between hosts.
+
+pub(crate) fn hash(seed: u64, bytes: &[u8]) -> usize {
+    let hash = bytes
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325 ^ seed, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    hash as usize
+}
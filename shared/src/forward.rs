@@ -0,0 +1,171 @@
+// Which peers a frame from the stack goes to. Unicast goes to the peer its destination was learned
+// from, and broadcast to every peer. Multicast and unicast to a destination not learned yet are
+// flooded to every peer as well unless the policy says otherwise: IPv6 neighbor discovery, mDNS,
+// LLMNR and the first frames to any host depend on them.
+
+use crate::{
+    mac_addr::{self, MAC_ADDR_SIZE},
+    mtu::ETH_HEADER_SIZE,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FloodPolicy {
+    pub unknown_unicast: bool,
+    pub multicast: bool,
+}
+
+impl FloodPolicy {
+    const UNKNOWN_UNICAST: u32 = 0x1;
+    const MULTICAST: u32 = 0x2;
+
+    // Unknown flags are rejected rather than ignored.
+    pub fn from_raw(value: u32) -> Option<Self> {
+        if value & !(Self::UNKNOWN_UNICAST | Self::MULTICAST) != 0 {
+            return None;
+        }
+        Some(Self {
+            unknown_unicast: value & Self::UNKNOWN_UNICAST != 0,
+            multicast: value & Self::MULTICAST != 0,
+        })
+    }
+
+    pub fn as_raw(self) -> u32 {
+        let mut value = 0;
+        if self.unknown_unicast {
+            value |= Self::UNKNOWN_UNICAST;
+        }
+        if self.multicast {
+            value |= Self::MULTICAST;
+        }
+        value
+    }
+}
+
+impl Default for FloodPolicy {
+    fn default() -> Self {
+        Self {
+            unknown_unicast: true,
+            multicast: true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Forward<P> {
+    To(P),
+    Flood,
+    Drop,
+}
+
+// `find` looks up the peer a unicast destination was learned from.
+pub fn forward<P>(
+    frame: &[u8],
+    policy: FloodPolicy,
+    find: impl FnOnce(&[u8; MAC_ADDR_SIZE]) -> Option<P>,
+) -> Forward<P> {
+    if frame.len() < ETH_HEADER_SIZE as usize {
+        return Forward::Drop;
+    }
+    let mut dst = [0; MAC_ADDR_SIZE];
+    dst.copy_from_slice(&frame[..MAC_ADDR_SIZE]);
+    if dst == mac_addr::BROADCAST {
+        return Forward::Flood;
+    }
+    if mac_addr::is_multicast(&dst) {
+        return if policy.multicast {
+            Forward::Flood
+        } else {
+            Forward::Drop
+        };
+    }
+    match find(&dst) {
+        Some(peer) => Forward::To(peer),
+        None if policy.unknown_unicast => Forward::Flood,
+        None => Forward::Drop,
+    }
+}
+
+#[cfg(test)]
+const LEARNED: [u8; MAC_ADDR_SIZE] = [0x02, 0x15, 0x5d, 0x01, 0x02, 0x03];
+
+#[cfg(test)]
+fn frame(dst: [u8; MAC_ADDR_SIZE], ether_type: u16) -> [u8; 64] {
+    let mut frame = [0; 64];
+    frame[..MAC_ADDR_SIZE].copy_from_slice(&dst);
+    frame[MAC_ADDR_SIZE..2 * MAC_ADDR_SIZE].copy_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+    frame[2 * MAC_ADDR_SIZE..ETH_HEADER_SIZE as usize].copy_from_slice(&ether_type.to_be_bytes());
+    frame
+}
+
+#[cfg(test)]
+fn forward_with(frame: &[u8], policy: FloodPolicy) -> Forward<u32> {
+    forward(
+        frame,
+        policy,
+        |dst| if *dst == LEARNED { Some(1) } else { None },
+    )
+}
+
+#[test]
+fn flood_policy_raw() {
+    for &value in &[0, 1, 2, 3] {
+        assert_eq!(FloodPolicy::from_raw(value).unwrap().as_raw(), value);
+    }
+    assert_eq!(FloodPolicy::from_raw(4), None);
+    assert_eq!(FloodPolicy::default().as_raw(), 3);
+}
+
+#[test]
+fn forward_unicast() {
+    let none = FloodPolicy::from_raw(0).unwrap();
+    for &policy in &[FloodPolicy::default(), none] {
+        assert_eq!(
+            forward_with(&frame(LEARNED, 0x0800), policy),
+            Forward::To(1)
+        );
+        assert_eq!(
+            forward_with(&frame(mac_addr::BROADCAST, 0x0806), policy),
+            Forward::Flood
+        );
+    }
+
+    // Unlearned
+    let unknown = frame([0x02, 0x15, 0x5d, 0x0a, 0x0b, 0x0c], 0x0800);
+    assert_eq!(
+        forward_with(&unknown, FloodPolicy::default()),
+        Forward::Flood
+    );
+    assert_eq!(forward_with(&unknown, none), Forward::Drop);
+}
+
+#[test]
+fn forward_multicast() {
+    let unicast_only = FloodPolicy {
+        unknown_unicast: true,
+        multicast: false,
+    };
+    // IPv6 solicited-node, all-nodes, mDNS over IPv4 and LLMNR over IPv6
+    let groups = [
+        ([0x33, 0x33, 0xff, 0x01, 0x02, 0x03], 0x86dd),
+        ([0x33, 0x33, 0x00, 0x00, 0x00, 0x01], 0x86dd),
+        ([0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb], 0x0800),
+        ([0x33, 0x33, 0x00, 0x01, 0x00, 0x03], 0x86dd),
+    ];
+    for &(dst, ether_type) in &groups {
+        let frame = frame(dst, ether_type);
+        assert_eq!(forward_with(&frame, FloodPolicy::default()), Forward::Flood);
+        assert_eq!(forward_with(&frame, unicast_only), Forward::Drop);
+    }
+}
+
+#[test]
+fn forward_runt() {
+    let frame = frame(LEARNED, 0x0800);
+    assert_eq!(
+        forward_with(
+            &frame[..ETH_HEADER_SIZE as usize - 1],
+            FloodPolicy::default()
+        ),
+        Forward::Drop
+    );
+}
@@ -0,0 +1,370 @@
+// The control interface between nvnet and the driver: the IOCTL codes and the `#[repr(C)]`
+// structures they exchange. Both sides are built against this module, and `ABI_VERSION` tells
+// apart the builds of either side that were not.
+//
+// Enumerations travel as their raw values: `cipher_suite` and `hash` hold those of
+// `provider::{CipherSuite, HashAlgorithm}`, `state` that of `liveness::PeerState`, and `kind` that
+// of `EventKind`. Flags travel as those of `forward::FloodPolicy`, and the adapter mode as the raw
+// value of `routing::Mode`.
+
+use crate::mac_addr::MAC_ADDR_SIZE;
+
+// Bumped whenever a code or a structure below changes meaning or layout.
+pub const ABI_VERSION: u32 = 9;
+
+// Public, private and pre-shared keys
+pub const KEY_SIZE: usize = 32;
+
+// Per peer
+pub const MAX_ALLOWED_IPS: usize = 32;
+
+const FILE_DEVICE_NETWORK: u32 = 0x00000012;
+const METHOD_BUFFERED: u32 = 0;
+const FILE_ANY_ACCESS: u32 = 0;
+
+const fn ctl_code(device_type: u32, function: u32, method: u32, access: u32) -> u32 {
+    (device_type << 16) | (access << 14) | (function << 2) | method
+}
+
+const fn veth_ctl_code(function_index: u32) -> u32 {
+    ctl_code(
+        FILE_DEVICE_NETWORK,
+        function_index + 0x800,
+        METHOD_BUFFERED,
+        FILE_ANY_ACCESS,
+    )
+}
+
+pub const IOCTL_VETH_SET_CONNECT_STATE: u32 = veth_ctl_code(0);
+pub const IOCTL_VETH_SET_LOCAL_ADDR: u32 = veth_ctl_code(1);
+pub const IOCTL_VETH_ADD_REMOTE_PEER: u32 = veth_ctl_code(2);
+pub const IOCTL_VETH_SET_DISCONNECT_ON_CLOSE: u32 = veth_ctl_code(3);
+pub const IOCTL_VETH_SET_LOCAL_KEY: u32 = veth_ctl_code(4);
+pub const IOCTL_VETH_GET_PEERS: u32 = veth_ctl_code(5);
+// Takes the peer's public key.
+pub const IOCTL_VETH_REMOVE_REMOTE_PEER: u32 = veth_ctl_code(6);
+// Takes a `VEthRemotePeer`, identified by its public key.
+pub const IOCTL_VETH_UPDATE_REMOTE_PEER: u32 = veth_ctl_code(7);
+pub const IOCTL_VETH_GET_STATS: u32 = veth_ctl_code(8);
+pub const IOCTL_VETH_GET_PEER_STATS: u32 = veth_ctl_code(9);
+// Returns the driver's `ABI_VERSION` as a `u32`. Neither the code nor the output may ever change,
+// so that any nvnet can tell whether it speaks the driver's ABI before issuing other requests.
+pub const IOCTL_VETH_GET_ABI_VERSION: u32 = veth_ctl_code(10);
+// Takes the adapter's `[u8; mac_addr::MAC_ADDR_SIZE]`, which the driver persists and applies by
+// restarting the adapter if it changed.
+pub const IOCTL_VETH_SET_MAC_ADDR: u32 = veth_ctl_code(11);
+// Takes the adapter's MTU as a `u32`, within `mtu::MIN_MTU..=mtu::MAX_MTU`. Persisted and applied
+// like the MAC address.
+pub const IOCTL_VETH_SET_MTU: u32 = veth_ctl_code(12);
+// Fills the output buffer with `VEthEvent`s, oldest first. The driver holds the request until there
+// are events to report, so nvnet keeps one pending to be told of them as they happen. It may still
+// complete with none, and is canceled when the handle is closed.
+pub const IOCTL_VETH_GET_EVENTS: u32 = veth_ctl_code(13);
+// Takes the flags of a `forward::FloodPolicy` as a `u32`. Until set, both kinds of frames are
+// flooded.
+pub const IOCTL_VETH_SET_FLOOD_POLICY: u32 = veth_ctl_code(14);
+// Fills the output buffer with `VEthFdbEntry`s. A full buffer means that there may be more.
+pub const IOCTL_VETH_GET_FDB: u32 = veth_ctl_code(15);
+// Takes a `routing::Mode` as a `u32`. Until set, the adapter is in layer-2 mode.
+pub const IOCTL_VETH_SET_MODE: u32 = veth_ctl_code(16);
+// Takes a `bool`: whether data from a peer is relayed to the others it is addressed to, as a hub
+// does for its spokes. Until set, it is only delivered to the local stack.
+pub const IOCTL_VETH_SET_RELAY: u32 = veth_ctl_code(17);
+
+// The Windows layout. Integers are in host byte order except for `port` and `flowinfo`.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SOCKADDR_IN6 {
+    pub family: u16,
+    pub port: u16,
+    pub flowinfo: u32,
+    pub addr: [u8; 16],
+    pub scope_id: u32,
+}
+
+#[repr(C)]
+pub struct VEthLocalKey {
+    pub private_key: [u8; KEY_SIZE],
+    pub cipher_suite: u32,
+    pub hash: u32,
+}
+
+// An IPv6 prefix, or an IPv4-mapped one whose `len` counts the 96 bits of the mapping.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VEthPrefix {
+    pub addr: [u8; 16],
+    pub len: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct VEthRemotePeer {
+    pub socket_addr: SOCKADDR_IN6,
+    pub public_key: [u8; KEY_SIZE],
+    // All zeros when the peer has no pre-shared key.
+    pub preshared_key: [u8; KEY_SIZE],
+    // In seconds, zero to disable.
+    pub persistent_keepalive: u32,
+    // The peer's adapter address, which replies to ARP and neighbor solicitations on its behalf
+    pub mac_addr: [u8; MAC_ADDR_SIZE],
+    // The first `allowed_ips_count` are the prefixes the peer owns in layer-3 mode.
+    pub allowed_ips_count: u32,
+    pub allowed_ips: [VEthPrefix; MAX_ALLOWED_IPS],
+}
+
+// Times are system times, zero for never.
+#[repr(C)]
+#[derive(Default)]
+pub struct VEthPeerStatus {
+    pub socket_addr: SOCKADDR_IN6,
+    pub public_key: [u8; KEY_SIZE],
+    pub state: u32,
+    pub persistent_keepalive: u32,
+    pub last_handshake: u64,
+    pub last_received: u64,
+    // How many times authenticated traffic moved the peer to another endpoint
+    pub roams: u64,
+    pub last_roamed: u64,
+}
+
+// Packets and bytes are whole datagrams exchanged with peers.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VEthTraffic {
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_failures: u64,
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub mac_learned: u64,
+}
+
+// Why a received datagram was not indicated to the stack. The discriminants index
+// `VEthStats::rx_drops`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RxDrop {
+    ReceiveFailed,
+    Malformed,
+    // No peer has a session with the receiver index, nor is configured at the source address.
+    UnknownSource,
+    // No peer has a session with the receiver index.
+    UnknownSession,
+    InvalidNonce,
+    AuthFailed,
+    Replayed,
+    // Too short for an Ethernet frame
+    Runt,
+    // The handshake message failed the MAC1 check or the rate limiter.
+    HandshakeRejected,
+    HandshakeFailed,
+    // In layer-3 mode, the packet's source is not among the allowed IPs of the peer that sent it.
+    DisallowedSource,
+}
+
+impl RxDrop {
+    pub const COUNT: usize = Self::DisallowedSource as usize + 1;
+
+    pub const ALL: [Self; Self::COUNT] = [
+        Self::ReceiveFailed,
+        Self::Malformed,
+        Self::UnknownSource,
+        Self::UnknownSession,
+        Self::InvalidNonce,
+        Self::AuthFailed,
+        Self::Replayed,
+        Self::Runt,
+        Self::HandshakeRejected,
+        Self::HandshakeFailed,
+        Self::DisallowedSource,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ReceiveFailed => "receive-failed",
+            Self::Malformed => "malformed",
+            Self::UnknownSource => "unknown-source",
+            Self::UnknownSession => "unknown-session",
+            Self::InvalidNonce => "invalid-nonce",
+            Self::AuthFailed => "auth-failed",
+            Self::Replayed => "replayed",
+            Self::Runt => "runt",
+            Self::HandshakeRejected => "handshake-rejected",
+            Self::HandshakeFailed => "handshake-failed",
+            Self::DisallowedSource => "disallowed-source",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum EventKind {
+    PeerUp = 1,
+    PeerDown = 2,
+    HandshakeCompleted = 3,
+    // An initiation went unanswered.
+    HandshakeFailed = 4,
+    EndpointRoamed = 5,
+    MacLearned = 6,
+}
+
+impl EventKind {
+    pub const ALL: [Self; 6] = [
+        Self::PeerUp,
+        Self::PeerDown,
+        Self::HandshakeCompleted,
+        Self::HandshakeFailed,
+        Self::EndpointRoamed,
+        Self::MacLearned,
+    ];
+
+    pub fn from_raw(value: u32) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.as_raw() == value)
+    }
+
+    pub fn as_raw(self) -> u32 {
+        self as u32
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::PeerUp => "peer-up",
+            Self::PeerDown => "peer-down",
+            Self::HandshakeCompleted => "handshake-completed",
+            Self::HandshakeFailed => "handshake-failed",
+            Self::EndpointRoamed => "endpoint-roamed",
+            Self::MacLearned => "mac-learned",
+        }
+    }
+}
+
+// Every event concerns a peer. `socket_addr` is its endpoint at the time, and `mac_addr` is only set
+// for `EventKind::MacLearned`. `time` is a system time.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VEthEvent {
+    pub kind: u32,
+    pub public_key: [u8; KEY_SIZE],
+    pub socket_addr: SOCKADDR_IN6,
+    pub mac_addr: [u8; MAC_ADDR_SIZE],
+    pub time: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct VEthStats {
+    pub traffic: VEthTraffic,
+    pub rx_drops: [u64; RxDrop::COUNT],
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct VEthPeerStats {
+    pub public_key: [u8; KEY_SIZE],
+    pub traffic: VEthTraffic,
+    pub auth_failures: u64,
+    pub replays: u64,
+    pub psk_failures: u64,
+}
+
+// An address of the forwarding database and the peer it is behind. `last_seen` is a system time.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VEthFdbEntry {
+    pub public_key: [u8; KEY_SIZE],
+    pub mac_addr: [u8; MAC_ADDR_SIZE],
+    pub last_seen: u64,
+}
+
+#[cfg(test)]
+macro_rules! offset_of {
+    ($type:ty, $field:ident) => {{
+        let value = core::mem::MaybeUninit::<$type>::uninit();
+        let base = value.as_ptr();
+        let field = unsafe { core::ptr::addr_of!((*base).$field) };
+        field as usize - base as usize
+    }};
+}
+
+#[test]
+fn ioctl_codes() {
+    assert_eq!(IOCTL_VETH_SET_CONNECT_STATE, 0x0012_2000);
+    assert_eq!(IOCTL_VETH_SET_DISCONNECT_ON_CLOSE, 0x0012_200c);
+    assert_eq!(IOCTL_VETH_GET_PEER_STATS, 0x0012_2024);
+    assert_eq!(IOCTL_VETH_GET_ABI_VERSION, 0x0012_2028);
+    assert_eq!(IOCTL_VETH_SET_MAC_ADDR, 0x0012_202c);
+    assert_eq!(IOCTL_VETH_SET_MTU, 0x0012_2030);
+    assert_eq!(IOCTL_VETH_GET_EVENTS, 0x0012_2034);
+    assert_eq!(IOCTL_VETH_SET_FLOOD_POLICY, 0x0012_2038);
+    assert_eq!(IOCTL_VETH_GET_FDB, 0x0012_203c);
+    assert_eq!(IOCTL_VETH_SET_MODE, 0x0012_2040);
+    assert_eq!(IOCTL_VETH_SET_RELAY, 0x0012_2044);
+}
+
+#[test]
+fn ioctl_layout() {
+    use core::mem::{align_of, size_of};
+
+    assert_eq!(size_of::<SOCKADDR_IN6>(), 28);
+    assert_eq!(align_of::<SOCKADDR_IN6>(), 4);
+    assert_eq!(offset_of!(SOCKADDR_IN6, port), 2);
+    assert_eq!(offset_of!(SOCKADDR_IN6, addr), 8);
+    assert_eq!(offset_of!(SOCKADDR_IN6, scope_id), 24);
+
+    assert_eq!(size_of::<VEthLocalKey>(), 40);
+    assert_eq!(offset_of!(VEthLocalKey, hash), 36);
+
+    assert_eq!(size_of::<VEthPrefix>(), 20);
+    assert_eq!(size_of::<VEthRemotePeer>(), 108 + 20 * MAX_ALLOWED_IPS);
+    assert_eq!(offset_of!(VEthRemotePeer, public_key), 28);
+    assert_eq!(offset_of!(VEthRemotePeer, persistent_keepalive), 92);
+    assert_eq!(offset_of!(VEthRemotePeer, mac_addr), 96);
+    assert_eq!(offset_of!(VEthRemotePeer, allowed_ips_count), 104);
+    assert_eq!(offset_of!(VEthRemotePeer, allowed_ips), 108);
+
+    assert_eq!(size_of::<VEthPeerStatus>(), 104);
+    assert_eq!(offset_of!(VEthPeerStatus, state), 60);
+    // Padded to the alignment of the times.
+    assert_eq!(offset_of!(VEthPeerStatus, last_handshake), 72);
+    assert_eq!(offset_of!(VEthPeerStatus, last_roamed), 96);
+
+    assert_eq!(size_of::<VEthTraffic>(), 48);
+    assert_eq!(size_of::<VEthStats>(), 136);
+    assert_eq!(offset_of!(VEthStats, rx_drops), 48);
+
+    assert_eq!(size_of::<VEthPeerStats>(), 104);
+    assert_eq!(offset_of!(VEthPeerStats, traffic), 32);
+    assert_eq!(offset_of!(VEthPeerStats, psk_failures), 96);
+
+    assert_eq!(size_of::<VEthEvent>(), 80);
+    assert_eq!(offset_of!(VEthEvent, socket_addr), 36);
+    assert_eq!(offset_of!(VEthEvent, mac_addr), 64);
+    assert_eq!(offset_of!(VEthEvent, time), 72);
+
+    assert_eq!(size_of::<VEthFdbEntry>(), 48);
+    assert_eq!(offset_of!(VEthFdbEntry, mac_addr), 32);
+    assert_eq!(offset_of!(VEthFdbEntry, last_seen), 40);
+}
+
+#[test]
+fn rx_drop_names() {
+    for (index, reason) in RxDrop::ALL.iter().enumerate() {
+        assert_eq!(*reason as usize, index);
+    }
+    assert_eq!(RxDrop::Malformed.name(), "malformed");
+    assert_eq!(RxDrop::HandshakeFailed.name(), "handshake-failed");
+    assert_eq!(RxDrop::DisallowedSource.name(), "disallowed-source");
+}
+
+#[test]
+fn event_kinds() {
+    for kind in EventKind::ALL.iter().copied() {
+        assert_eq!(EventKind::from_raw(kind.as_raw()), Some(kind));
+    }
+    assert_eq!(EventKind::from_raw(0), None);
+    assert_eq!(EventKind::MacLearned.name(), "mac-learned");
+}
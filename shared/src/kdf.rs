@@ -0,0 +1,411 @@
+// HMAC and HKDF (RFC 2104, RFC 5869) over any hash, and the Noise-style key schedule built on
+// them: a chaining key that absorbs every DH result, and a transcript hash of everything sent.
+
+pub const MAX_HASH_SIZE: usize = 512 / 8;
+pub const MAX_BLOCK_SIZE: usize = 1024 / 8;
+
+pub trait Hash {
+    type Error;
+    type Context;
+
+    fn output_size(&self) -> usize;
+    fn block_size(&self) -> usize;
+
+    fn init(&self) -> Result<Self::Context, Self::Error>;
+    fn update(&self, context: &mut Self::Context, data: &[u8]) -> Result<(), Self::Error>;
+    // Writes `output_size` bytes.
+    fn finish(&self, context: Self::Context, out: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+pub fn hash<H: Hash>(hash: &H, parts: &[&[u8]], out: &mut [u8]) -> Result<(), H::Error> {
+    let mut context = hash.init()?;
+    for part in parts {
+        hash.update(&mut context, part)?;
+    }
+    hash.finish(context, out)
+}
+
+pub fn hmac<H: Hash>(
+    hash: &H,
+    key: &[u8],
+    parts: &[&[u8]],
+    out: &mut [u8],
+) -> Result<(), H::Error> {
+    let block_size = hash.block_size();
+    let hash_size = hash.output_size();
+    debug_assert!(block_size <= MAX_BLOCK_SIZE && hash_size <= MAX_HASH_SIZE);
+
+    let mut block_key = [0; MAX_BLOCK_SIZE];
+    if key.len() > block_size {
+        self::hash(hash, &[key], &mut block_key)?;
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut pad = [0; MAX_BLOCK_SIZE];
+    for (pad, key) in pad.iter_mut().zip(&block_key[..block_size]) {
+        *pad = key ^ 0x36;
+    }
+    let mut context = hash.init()?;
+    hash.update(&mut context, &pad[..block_size])?;
+    for part in parts {
+        hash.update(&mut context, part)?;
+    }
+    let mut inner = [0; MAX_HASH_SIZE];
+    hash.finish(context, &mut inner)?;
+
+    for (pad, key) in pad.iter_mut().zip(&block_key[..block_size]) {
+        *pad = key ^ 0x5c;
+    }
+    self::hash(hash, &[&pad[..block_size], &inner[..hash_size]], out)
+}
+
+pub fn hkdf_extract<H: Hash>(
+    hash: &H,
+    salt: &[u8],
+    ikm: &[u8],
+    prk: &mut [u8],
+) -> Result<(), H::Error> {
+    hmac(hash, salt, &[ikm], prk)
+}
+
+pub fn hkdf_expand<H: Hash>(
+    hash: &H,
+    prk: &[u8],
+    info: &[u8],
+    okm: &mut [u8],
+) -> Result<(), H::Error> {
+    let hash_size = hash.output_size();
+    assert!(okm.len() <= 255 * hash_size);
+
+    let mut block = [0; MAX_HASH_SIZE];
+    let mut block_size = 0;
+    for (i, chunk) in okm.chunks_mut(hash_size).enumerate() {
+        let counter = [i as u8 + 1];
+        let previous = block;
+        hmac(
+            hash,
+            prk,
+            &[&previous[..block_size], info, &counter],
+            &mut block,
+        )?;
+        block_size = hash_size;
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+    Ok(())
+}
+
+pub fn hkdf<H: Hash>(
+    hash: &H,
+    salt: &[u8],
+    ikm: &[u8],
+    info: &[u8],
+    okm: &mut [u8],
+) -> Result<(), H::Error> {
+    let mut prk = [0; MAX_HASH_SIZE];
+    hkdf_extract(hash, salt, ikm, &mut prk)?;
+    hkdf_expand(hash, &prk[..hash.output_size()], info, okm)
+}
+
+pub type Key = [u8; MAX_HASH_SIZE];
+
+pub struct SessionKeys {
+    pub send: Key,
+    pub recv: Key,
+}
+
+#[derive(Clone)]
+pub struct KeySchedule {
+    hash_size: usize,
+    chaining_key: Key,
+    transcript: Key,
+}
+
+impl KeySchedule {
+    pub fn new<H: Hash>(hash: &H, protocol_name: &[u8]) -> Result<Self, H::Error> {
+        let hash_size = hash.output_size();
+        let mut transcript = [0; MAX_HASH_SIZE];
+        if protocol_name.len() <= hash_size {
+            transcript[..protocol_name.len()].copy_from_slice(protocol_name);
+        } else {
+            self::hash(hash, &[protocol_name], &mut transcript)?;
+        }
+        Ok(Self {
+            hash_size,
+            chaining_key: transcript,
+            transcript,
+        })
+    }
+
+    pub fn transcript(&self) -> &[u8] {
+        &self.transcript[..self.hash_size]
+    }
+
+    pub fn mix_hash<H: Hash>(&mut self, hash: &H, data: &[u8]) -> Result<(), H::Error> {
+        let previous = self.transcript;
+        self::hash(
+            hash,
+            &[&previous[..self.hash_size], data],
+            &mut self.transcript,
+        )
+    }
+
+    // Absorbs the input into the chaining key and returns a key for the next handshake message.
+    pub fn mix_key<H: Hash>(&mut self, hash: &H, input: &[u8]) -> Result<Key, H::Error> {
+        let [chaining_key, key] = self.derive(hash, input)?;
+        self.chaining_key = chaining_key;
+        Ok(key)
+    }
+
+    // Like `mix_key`, but the input also reaches the transcript. Used for pre-shared keys.
+    pub fn mix_key_and_hash<H: Hash>(&mut self, hash: &H, input: &[u8]) -> Result<Key, H::Error> {
+        let [chaining_key, transcript_input, key] = self.derive(hash, input)?;
+        self.chaining_key = chaining_key;
+        self.mix_hash(hash, &transcript_input[..self.hash_size])?;
+        Ok(key)
+    }
+
+    pub fn split<H: Hash>(&self, hash: &H, initiator: bool) -> Result<SessionKeys, H::Error> {
+        let [initiator_key, responder_key] = self.derive(hash, &[])?;
+        Ok(if initiator {
+            SessionKeys {
+                send: initiator_key,
+                recv: responder_key,
+            }
+        } else {
+            SessionKeys {
+                send: responder_key,
+                recv: initiator_key,
+            }
+        })
+    }
+
+    fn derive<H: Hash, const N: usize>(
+        &self,
+        hash: &H,
+        input: &[u8],
+    ) -> Result<[Key; N], H::Error> {
+        let mut okm = [0; MAX_HASH_SIZE * 3];
+        let okm = &mut okm[..self.hash_size * N];
+        hkdf(hash, &self.chaining_key[..self.hash_size], input, &[], okm)?;
+        let mut keys = [[0; MAX_HASH_SIZE]; N];
+        for (key, okm) in keys.iter_mut().zip(okm.chunks(self.hash_size)) {
+            key[..self.hash_size].copy_from_slice(okm);
+        }
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+pub(crate) struct Sha<D>(core::marker::PhantomData<D>);
+
+#[cfg(test)]
+impl<D: sha2::Digest + sha2::digest::core_api::BlockSizeUser> Hash for Sha<D> {
+    type Error = ();
+    type Context = D;
+
+    fn output_size(&self) -> usize {
+        <D as sha2::Digest>::output_size()
+    }
+
+    fn block_size(&self) -> usize {
+        D::block_size()
+    }
+
+    fn init(&self) -> Result<D, ()> {
+        Ok(D::new())
+    }
+
+    fn update(&self, context: &mut D, data: &[u8]) -> Result<(), ()> {
+        context.update(data);
+        Ok(())
+    }
+
+    fn finish(&self, context: D, out: &mut [u8]) -> Result<(), ()> {
+        out[..self.output_size()].copy_from_slice(&context.finalize());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) const SHA256: Sha<sha2::Sha256> = Sha(core::marker::PhantomData);
+#[cfg(test)]
+pub(crate) const SHA512: Sha<sha2::Sha512> = Sha(core::marker::PhantomData);
+
+#[cfg(test)]
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+fn check_hkdf(ikm: &str, salt: &str, info: &str, prk: &str, okm: &str) {
+    let (ikm, salt, info, prk, okm) = (hex(ikm), hex(salt), hex(info), hex(prk), hex(okm));
+
+    let mut actual_prk = [0; MAX_HASH_SIZE];
+    hkdf_extract(&SHA256, &salt, &ikm, &mut actual_prk).unwrap();
+    assert_eq!(&actual_prk[..32], prk.as_slice());
+
+    let mut actual_okm = vec![0; okm.len()];
+    hkdf_expand(&SHA256, &prk, &info, &mut actual_okm).unwrap();
+    assert_eq!(actual_okm, okm);
+
+    let mut actual_okm = vec![0; okm.len()];
+    hkdf(&SHA256, &salt, &ikm, &info, &mut actual_okm).unwrap();
+    assert_eq!(actual_okm, okm);
+}
+
+// RFC 5869 A.1
+#[test]
+fn hkdf_sha256_basic() {
+    check_hkdf(
+        "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+        "000102030405060708090a0b0c",
+        "f0f1f2f3f4f5f6f7f8f9",
+        "077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5",
+        "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865",
+    );
+}
+
+// RFC 5869 A.2
+#[test]
+fn hkdf_sha256_long() {
+    check_hkdf(
+        "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f\
+         202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f\
+         404142434445464748494a4b4c4d4e4f",
+        "606162636465666768696a6b6c6d6e6f707172737475767778797a7b7c7d7e7f\
+         808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f\
+         a0a1a2a3a4a5a6a7a8a9aaabacadaeaf",
+        "b0b1b2b3b4b5b6b7b8b9babbbcbdbebfc0c1c2c3c4c5c6c7c8c9cacbcccdcecf\
+         d0d1d2d3d4d5d6d7d8d9dadbdcdddedfe0e1e2e3e4e5e6e7e8e9eaebecedeeef\
+         f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff",
+        "06a6b88c5853361a06104c9ceb35b45cef760014904671014a193f40c15fc244",
+        "b11e398dc80327a1c8e7f78c596a49344f012eda2d4efad8a050cc4c19afa97c\
+         59045a99cac7827271cb41c65e590e09da3275600c2f09b8367793a9aca3db71\
+         cc30c58179ec3e87c14c01d5c1f3434f1d87",
+    );
+}
+
+// RFC 5869 A.3
+#[test]
+fn hkdf_sha256_empty_salt_and_info() {
+    check_hkdf(
+        "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+        "",
+        "",
+        "19ef24a32c717b167f33a91d6f648bdf96596776afdb6377ac434c1c293ccb04",
+        "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d9d201395faa4b61a96c8",
+    );
+}
+
+// RFC 4231 4.3, which exercises the block size of SHA-512.
+#[test]
+fn hmac_sha512() {
+    let mut out = [0; MAX_HASH_SIZE];
+    hmac(
+        &SHA512,
+        b"Jefe",
+        &[b"what do ya want ", b"for nothing?"],
+        &mut out,
+    )
+    .unwrap();
+    assert_eq!(
+        out.to_vec(),
+        hex(
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+             9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        ),
+    );
+}
+
+#[cfg(test)]
+fn check_key_schedule<H: Hash<Error = ()>>(hash: &H) {
+    let hash_size = hash.output_size();
+    let mut initiator = KeySchedule::new(hash, b"Noise_IK_25519_test").unwrap();
+    let mut responder = initiator.clone();
+
+    for schedule in [&mut initiator, &mut responder].iter_mut() {
+        schedule.mix_hash(hash, b"prologue").unwrap();
+        let key = schedule.mix_key(hash, &[1; 32]).unwrap();
+        assert_ne!(key[..hash_size], [0; MAX_HASH_SIZE][..hash_size]);
+        schedule.mix_hash(hash, b"message").unwrap();
+    }
+    assert_eq!(initiator.transcript(), responder.transcript());
+    assert_eq!(initiator.transcript().len(), hash_size);
+
+    let initiator_keys = initiator.split(hash, true).unwrap();
+    let responder_keys = responder.split(hash, false).unwrap();
+    assert_eq!(initiator_keys.send, responder_keys.recv);
+    assert_eq!(initiator_keys.recv, responder_keys.send);
+    assert_ne!(initiator_keys.send, initiator_keys.recv);
+
+    // A different DH result changes the keys but not the transcript.
+    let mut other = KeySchedule::new(hash, b"Noise_IK_25519_test").unwrap();
+    other.mix_hash(hash, b"prologue").unwrap();
+    other.mix_key(hash, &[2; 32]).unwrap();
+    other.mix_hash(hash, b"message").unwrap();
+    assert_eq!(other.transcript(), initiator.transcript());
+    assert_ne!(other.split(hash, true).unwrap().send, initiator_keys.send);
+
+    other.mix_hash(hash, b"more").unwrap();
+    assert_ne!(other.transcript(), initiator.transcript());
+}
+
+#[test]
+fn key_schedule_sha256() {
+    check_key_schedule(&SHA256);
+}
+
+#[test]
+fn key_schedule_sha512() {
+    check_key_schedule(&SHA512);
+}
+
+#[test]
+fn key_schedule_matches_hkdf() {
+    let mut schedule = KeySchedule::new(&SHA256, b"short").unwrap();
+    let mut expected = [0; 32];
+    expected[..5].copy_from_slice(b"short");
+    assert_eq!(schedule.transcript(), expected);
+
+    let mut okm = [0; 64];
+    hkdf(&SHA256, &expected, b"ikm", &[], &mut okm).unwrap();
+    let key = schedule.mix_key(&SHA256, b"ikm").unwrap();
+    assert_eq!(key[..32], okm[32..]);
+    assert_eq!(schedule.chaining_key[..32], okm[..32]);
+}
+
+#[test]
+fn key_schedule_preshared_key() {
+    let mut base = KeySchedule::new(&SHA256, b"Noise_IKpsk2_25519_test").unwrap();
+    base.mix_key(&SHA256, &[1; 32]).unwrap();
+
+    let mix = |psk: &[u8]| {
+        let mut schedule = base.clone();
+        let key = schedule.mix_key_and_hash(&SHA256, psk).unwrap();
+        (schedule, key)
+    };
+    let (zero, zero_key) = mix(&[0; 32]);
+    let (first, first_key) = mix(&[7; 32]);
+    let (second, second_key) = mix(&[7; 32]);
+
+    // The pre-shared key changes the transcript, the next key and the session keys.
+    assert_ne!(zero.transcript(), base.transcript());
+    assert_ne!(zero.transcript(), first.transcript());
+    assert_ne!(zero_key, first_key);
+    assert_ne!(
+        zero.split(&SHA256, true).unwrap().send,
+        first.split(&SHA256, true).unwrap().send,
+    );
+
+    assert_eq!(first.transcript(), second.transcript());
+    assert_eq!(first_key, second_key);
+
+    let mut okm = [0; 96];
+    hkdf(&SHA256, &base.chaining_key[..32], &[7; 32], &[], &mut okm).unwrap();
+    assert_eq!(first.chaining_key[..32], okm[..32]);
+    assert_eq!(first_key[..32], okm[64..]);
+}
@@ -1,3 +1,19 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+pub mod cookie;
+#[cfg(windows)]
 pub mod crypto;
+pub mod fdb;
+mod fnv;
+pub mod forward;
+pub mod ioctl;
+pub mod kdf;
+pub mod liveness;
+pub mod mac_addr;
+pub mod mtu;
+pub mod nonce;
+pub mod provider;
+pub mod relay;
+pub mod routing;
+pub mod session;
+pub mod wire;
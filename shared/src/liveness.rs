@@ -0,0 +1,237 @@
+// When a peer was last heard from, and whether it is considered reachable.
+//
+// A peer that receives data answers with a keepalive if it has nothing to send back within
+// `KEEPALIVE_TIMEOUT`. Data that stays unanswered for `REPLY_TIMEOUT` therefore means that the peer
+// is gone. Keepalives themselves are never answered, and persistent keepalives, sent after
+// `persistent_keepalive` without any outgoing message, keep middleboxes from forgetting the path.
+//
+// Times are offsets from an arbitrary origin supplied by the caller.
+
+use core::time::Duration;
+
+use crate::session::REJECT_AFTER_TIME;
+
+pub const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(15);
+
+// The discriminants are the values used in the IOCTLs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PeerState {
+    Down = 0,
+    Up = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message {
+    Data,
+    Keepalive,
+}
+
+#[derive(Default)]
+pub struct Liveness {
+    persistent_keepalive: Option<Duration>,
+    last_handshake: Option<Duration>,
+    last_received: Option<Duration>,
+    last_sent: Option<Duration>,
+    // The first data received since the last message was sent.
+    unanswered_received: Option<Duration>,
+    // The first data sent since the last message was received.
+    unanswered_sent: Option<Duration>,
+    // The last state returned by `state_change`
+    reported_up: bool,
+}
+
+impl Liveness {
+    pub const fn new(persistent_keepalive: Option<Duration>) -> Self {
+        Self {
+            persistent_keepalive,
+            last_handshake: None,
+            last_received: None,
+            last_sent: None,
+            unanswered_received: None,
+            unanswered_sent: None,
+            reported_up: false,
+        }
+    }
+
+    pub fn persistent_keepalive(&self) -> Option<Duration> {
+        self.persistent_keepalive
+    }
+
+    pub fn set_persistent_keepalive(&mut self, persistent_keepalive: Option<Duration>) {
+        self.persistent_keepalive = persistent_keepalive;
+    }
+
+    pub fn last_handshake(&self) -> Option<Duration> {
+        self.last_handshake
+    }
+
+    pub fn last_received(&self) -> Option<Duration> {
+        self.last_received
+    }
+
+    pub fn handshake_completed(&mut self, now: Duration) {
+        self.last_handshake = Some(now);
+    }
+
+    pub fn received(&mut self, message: Message, now: Duration) {
+        self.last_received = Some(now);
+        self.unanswered_sent = None;
+        if message == Message::Data && self.unanswered_received.is_none() {
+            self.unanswered_received = Some(now);
+        }
+    }
+
+    pub fn sent(&mut self, message: Message, now: Duration) {
+        self.last_sent = Some(now);
+        self.unanswered_received = None;
+        if message == Message::Data && self.unanswered_sent.is_none() {
+            self.unanswered_sent = Some(now);
+        }
+    }
+
+    pub fn keepalive_due(&self, now: Duration) -> bool {
+        let elapsed = |since: Duration| now.saturating_sub(since);
+        if let Some(received) = self.unanswered_received {
+            if elapsed(received) >= KEEPALIVE_TIMEOUT {
+                return true;
+            }
+        }
+        match (self.persistent_keepalive, self.last_sent) {
+            (Some(interval), Some(sent)) => elapsed(sent) >= interval,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    pub fn state(&self, now: Duration) -> PeerState {
+        let elapsed = |since: Duration| now.saturating_sub(since);
+        if self.last_handshake.is_none() {
+            return PeerState::Down;
+        }
+        if let Some(sent) = self.unanswered_sent {
+            if elapsed(sent) >= REPLY_TIMEOUT {
+                return PeerState::Down;
+            }
+        }
+        match self.last_received {
+            Some(received) if elapsed(received) < REJECT_AFTER_TIME => PeerState::Up,
+            _ => PeerState::Down,
+        }
+    }
+
+    // The state, if it changed since the last call. Peers start down, so the first report is of
+    // them coming up.
+    pub fn state_change(&mut self, now: Duration) -> Option<PeerState> {
+        let state = self.state(now);
+        let up = state == PeerState::Up;
+        if up == self.reported_up {
+            return None;
+        }
+        self.reported_up = up;
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+const fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+#[test]
+fn liveness_up_after_handshake() {
+    let mut liveness = Liveness::new(None);
+    assert_eq!(liveness.state(secs(0)), PeerState::Down);
+
+    liveness.received(Message::Keepalive, secs(1));
+    assert_eq!(liveness.state(secs(1)), PeerState::Down);
+
+    liveness.handshake_completed(secs(1));
+    assert_eq!(liveness.last_handshake(), Some(secs(1)));
+    assert_eq!(liveness.state(secs(1)), PeerState::Up);
+    // An idle tunnel stays up for as long as its keys are valid.
+    assert_eq!(
+        liveness.state(secs(1) + REJECT_AFTER_TIME / 2),
+        PeerState::Up
+    );
+    assert_eq!(liveness.state(secs(1) + REJECT_AFTER_TIME), PeerState::Down);
+}
+
+#[test]
+fn liveness_down_without_reply() {
+    let mut liveness = Liveness::new(None);
+    liveness.handshake_completed(secs(0));
+    liveness.received(Message::Keepalive, secs(0));
+
+    liveness.sent(Message::Data, secs(10));
+    liveness.sent(Message::Data, secs(20));
+    assert_eq!(liveness.state(secs(24)), PeerState::Up);
+    assert_eq!(liveness.state(secs(25)), PeerState::Down);
+
+    liveness.received(Message::Keepalive, secs(26));
+    assert_eq!(liveness.state(secs(26)), PeerState::Up);
+
+    // Keepalives are not answered, so they do not count.
+    liveness.sent(Message::Keepalive, secs(30));
+    assert_eq!(liveness.state(secs(60)), PeerState::Up);
+}
+
+#[test]
+fn liveness_passive_keepalive() {
+    let mut liveness = Liveness::new(None);
+    liveness.handshake_completed(secs(0));
+    assert!(!liveness.keepalive_due(secs(100)));
+
+    liveness.received(Message::Keepalive, secs(1));
+    assert!(!liveness.keepalive_due(secs(100)));
+
+    liveness.received(Message::Data, secs(2));
+    liveness.received(Message::Data, secs(5));
+    assert!(!liveness.keepalive_due(secs(11)));
+    assert!(liveness.keepalive_due(secs(12)));
+
+    liveness.sent(Message::Keepalive, secs(12));
+    assert!(!liveness.keepalive_due(secs(100)));
+
+    // Data sent back in time makes the keepalive unnecessary.
+    liveness.received(Message::Data, secs(200));
+    liveness.sent(Message::Data, secs(201));
+    assert!(!liveness.keepalive_due(secs(300)));
+}
+
+#[test]
+fn liveness_persistent_keepalive() {
+    let mut liveness = Liveness::new(Some(secs(25)));
+    assert_eq!(liveness.persistent_keepalive(), Some(secs(25)));
+    assert!(liveness.keepalive_due(secs(0)));
+
+    liveness.sent(Message::Keepalive, secs(0));
+    assert!(!liveness.keepalive_due(secs(24)));
+    assert!(liveness.keepalive_due(secs(25)));
+
+    liveness.sent(Message::Data, secs(20));
+    assert!(!liveness.keepalive_due(secs(44)));
+    assert!(liveness.keepalive_due(secs(45)));
+
+    liveness.set_persistent_keepalive(None);
+    assert!(!liveness.keepalive_due(secs(100)));
+}
+
+#[test]
+fn liveness_state_changes() {
+    let mut liveness = Liveness::new(None);
+    assert_eq!(liveness.state_change(secs(0)), None);
+
+    liveness.handshake_completed(secs(1));
+    liveness.received(Message::Keepalive, secs(1));
+    assert_eq!(liveness.state_change(secs(1)), Some(PeerState::Up));
+    assert_eq!(liveness.state_change(secs(2)), None);
+
+    liveness.sent(Message::Data, secs(2));
+    assert_eq!(
+        liveness.state_change(secs(2) + REPLY_TIMEOUT),
+        Some(PeerState::Down)
+    );
+    assert_eq!(liveness.state_change(secs(3) + REPLY_TIMEOUT), None);
+}
@@ -0,0 +1,58 @@
+// The Ethernet addresses of the adapters. Unless one is configured, an adapter takes the address
+// derived from its node's public key, which stays the same for as long as the key does and is
+// unlikely to be taken by another node of the overlay.
+
+use crate::ioctl::KEY_SIZE;
+
+pub const MAC_ADDR_SIZE: usize = 6;
+
+const MULTICAST: u8 = 0x01;
+const LOCALLY_ADMINISTERED: u8 = 0x02;
+
+pub const BROADCAST: [u8; MAC_ADDR_SIZE] = [0xff; MAC_ADDR_SIZE];
+
+// Group addresses, broadcast included
+pub fn is_multicast(addr: &[u8; MAC_ADDR_SIZE]) -> bool {
+    addr[0] & MULTICAST != 0
+}
+
+// Whether an adapter may use `addr` as its own: neither group nor all zeros.
+pub fn is_assignable(addr: &[u8; MAC_ADDR_SIZE]) -> bool {
+    !is_multicast(addr) && *addr != [0; MAC_ADDR_SIZE]
+}
+
+// Random bytes made into a locally administered unicast address
+pub fn local_unicast(mut addr: [u8; MAC_ADDR_SIZE]) -> [u8; MAC_ADDR_SIZE] {
+    addr[0] = addr[0] & !MULTICAST | LOCALLY_ADMINISTERED;
+    addr
+}
+
+// The leading bytes of the key are as good as random.
+pub fn from_public_key(public_key: &[u8; KEY_SIZE]) -> [u8; MAC_ADDR_SIZE] {
+    let mut addr = [0; MAC_ADDR_SIZE];
+    addr.copy_from_slice(&public_key[..MAC_ADDR_SIZE]);
+    local_unicast(addr)
+}
+
+#[test]
+fn derived_addrs() {
+    let addr = from_public_key(&[0xff; KEY_SIZE]);
+    assert_eq!(addr, [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    assert!(is_assignable(&addr));
+
+    let addr = from_public_key(&[0; KEY_SIZE]);
+    assert_eq!(addr, [0x02, 0, 0, 0, 0, 0]);
+    assert!(is_assignable(&addr));
+
+    let mut public_key = [0; KEY_SIZE];
+    public_key[MAC_ADDR_SIZE] = 1;
+    assert_eq!(from_public_key(&public_key), addr);
+}
+
+#[test]
+fn assignable_addrs() {
+    assert!(is_assignable(&[0x00, 0x15, 0x5d, 0x01, 0x02, 0x03]));
+    assert!(!is_assignable(&[0; MAC_ADDR_SIZE]));
+    assert!(!is_assignable(&[0xff; MAC_ADDR_SIZE]));
+    assert!(!is_assignable(&[0x01, 0x00, 0x5e, 0x00, 0x00, 0x01]));
+}
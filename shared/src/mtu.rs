@@ -0,0 +1,69 @@
+// The MTU of an adapter: the largest payload of the Ethernet frames it carries. Each frame travels
+// whole in a data message, so the underlay must fit the frame, the message header and tag, and its
+// own IP, UDP and Ethernet headers.
+
+use crate::wire::{DATA_OVERHEAD, HEADER_SIZE};
+
+pub const ETH_HEADER_SIZE: u32 = 14;
+
+const MAX_IP_HEADER_SIZE: u32 = 60;
+const UDP_HEADER_SIZE: u32 = 8;
+
+// What a frame costs on the underlay on top of the MTU
+pub const ENCAPSULATION_OVERHEAD: u32 = ETH_HEADER_SIZE
+    + MAX_IP_HEADER_SIZE
+    + UDP_HEADER_SIZE
+    + (HEADER_SIZE + DATA_OVERHEAD) as u32
+    + ETH_HEADER_SIZE;
+
+// The largest MTU an underlay with `underlay_mtu` can carry
+pub const fn for_underlay(underlay_mtu: u32) -> u32 {
+    underlay_mtu - ENCAPSULATION_OVERHEAD
+}
+
+// 1368, over a standard Ethernet underlay
+pub const DEFAULT_MTU: u32 = for_underlay(1500);
+
+// IPv6 requires it of every link.
+pub const MIN_MTU: u32 = 1280;
+
+// 8868, over an underlay with 9000-byte jumbo frames
+pub const MAX_MTU: u32 = for_underlay(9000);
+
+pub fn is_valid(mtu: u32) -> bool {
+    (MIN_MTU..=MAX_MTU).contains(&mtu)
+}
+
+// The largest Ethernet frame
+pub const fn frame_size(mtu: u32) -> usize {
+    (ETH_HEADER_SIZE + mtu) as usize
+}
+
+// The largest UDP payload: a data message carrying the largest frame
+pub const fn datagram_size(mtu: u32) -> usize {
+    HEADER_SIZE + DATA_OVERHEAD + frame_size(mtu)
+}
+
+#[test]
+fn mtu_bounds() {
+    assert_eq!(DEFAULT_MTU, 1368);
+    assert_eq!(MAX_MTU, 8868);
+    assert!(is_valid(DEFAULT_MTU));
+    assert!(is_valid(MIN_MTU));
+    assert!(is_valid(MAX_MTU));
+    assert!(!is_valid(MIN_MTU - 1));
+    assert!(!is_valid(MAX_MTU + 1));
+}
+
+#[test]
+fn mtu_fits_underlay() {
+    for &underlay_mtu in &[1500, 9000] {
+        let mtu = for_underlay(underlay_mtu);
+        let datagram_size = datagram_size(mtu) as u32;
+        assert_eq!(
+            ETH_HEADER_SIZE + MAX_IP_HEADER_SIZE + UDP_HEADER_SIZE + datagram_size,
+            underlay_mtu
+        );
+    }
+    assert_eq!(datagram_size(DEFAULT_MTU), 1418);
+}
@@ -0,0 +1,184 @@
+// Per-session message counters and the receive-side anti-replay window.
+//
+// The 12-byte AEAD nonce is four zero bytes followed by the little-endian counter. The window is
+// the bitmap ring of RFC 6479: one block more than the window size is kept so that sliding it only
+// clears whole blocks.
+
+use core::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+pub const NONCE_SIZE: usize = 12;
+
+const BLOCK_BITS: u64 = 64;
+const BLOCKS: usize = 32;
+
+pub const WINDOW_SIZE: u64 = (BLOCKS as u64 - 1) * BLOCK_BITS;
+
+// Leaves room for the window so that the counter never wraps.
+pub const REJECT_AFTER_MESSAGES: u64 = u64::MAX - WINDOW_SIZE - 1;
+
+pub fn encode(counter: u64) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0; NONCE_SIZE];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+pub fn decode(nonce: &[u8; NONCE_SIZE]) -> Option<u64> {
+    if nonce[..4] != [0; 4] {
+        return None;
+    }
+    let mut counter = [0; 8];
+    counter.copy_from_slice(&nonce[4..]);
+    Some(u64::from_le_bytes(counter))
+}
+
+#[derive(Default)]
+pub struct SendCounter(AtomicU64);
+
+impl SendCounter {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn next(&self) -> Option<u64> {
+        let counter = self.0.fetch_add(1, Relaxed);
+        if counter >= REJECT_AFTER_MESSAGES {
+            // Keep the counter saturated instead of letting it wrap around.
+            self.0.store(REJECT_AFTER_MESSAGES, Relaxed);
+            return None;
+        }
+        Some(counter)
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Relaxed)
+    }
+}
+
+pub struct ReplayWindow {
+    last: u64,
+    bitmap: [u64; BLOCKS],
+}
+
+impl Default for ReplayWindow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReplayWindow {
+    pub const fn new() -> Self {
+        Self {
+            last: 0,
+            bitmap: [0; BLOCKS],
+        }
+    }
+
+    // Marks the counter as seen. Only counters of authenticated messages may be passed in.
+    pub fn check(&mut self, counter: u64) -> bool {
+        if counter >= REJECT_AFTER_MESSAGES {
+            return false;
+        }
+        if counter + WINDOW_SIZE < self.last {
+            return false;
+        }
+        let index = counter / BLOCK_BITS;
+        if counter > self.last {
+            let current = self.last / BLOCK_BITS;
+            let diff = (index - current).min(BLOCKS as _);
+            for i in 1..=diff {
+                self.bitmap[((current + i) % BLOCKS as u64) as usize] = 0;
+            }
+            self.last = counter;
+        }
+        let block = &mut self.bitmap[(index % BLOCKS as u64) as usize];
+        let bit = 1 << (counter % BLOCK_BITS);
+        if *block & bit != 0 {
+            return false;
+        }
+        *block |= bit;
+        true
+    }
+}
+
+#[test]
+fn nonce_round_trip() {
+    for &counter in &[0, 1, 0x0102_0304_0506_0708, u64::MAX] {
+        let nonce = encode(counter);
+        assert_eq!(nonce[..4], [0; 4]);
+        assert_eq!(decode(&nonce), Some(counter));
+    }
+    let mut nonce = encode(1);
+    nonce[0] = 1;
+    assert_eq!(decode(&nonce), None);
+}
+
+#[test]
+fn send_counter_saturates() {
+    let counter = SendCounter::new();
+    assert_eq!(counter.next(), Some(0));
+    assert_eq!(counter.next(), Some(1));
+    counter.0.store(REJECT_AFTER_MESSAGES - 1, Relaxed);
+    assert_eq!(counter.next(), Some(REJECT_AFTER_MESSAGES - 1));
+    assert_eq!(counter.next(), None);
+    assert_eq!(counter.next(), None);
+    assert_eq!(counter.get(), REJECT_AFTER_MESSAGES);
+}
+
+#[test]
+fn replay_window_in_order() {
+    let mut window = ReplayWindow::new();
+    for counter in 0..10_000 {
+        assert!(window.check(counter));
+        assert!(!window.check(counter));
+    }
+}
+
+#[test]
+fn replay_window_reordered() {
+    let mut window = ReplayWindow::new();
+    assert!(window.check(10));
+    assert!(window.check(8));
+    assert!(window.check(9));
+    assert!(window.check(0));
+    assert!(!window.check(9));
+    assert!(window.check(WINDOW_SIZE + 10));
+    assert!(window.check(11));
+    assert!(!window.check(11));
+    assert!(!window.check(10));
+}
+
+#[test]
+fn replay_window_too_old() {
+    let mut window = ReplayWindow::new();
+    assert!(window.check(WINDOW_SIZE + 1));
+    assert!(window.check(1));
+    assert!(!window.check(0));
+
+    assert!(window.check(10 * WINDOW_SIZE));
+    assert!(!window.check(9 * WINDOW_SIZE - 1));
+    assert!(window.check(9 * WINDOW_SIZE));
+    assert!(!window.check(9 * WINDOW_SIZE));
+}
+
+#[test]
+fn replay_window_large_jump() {
+    let mut window = ReplayWindow::new();
+    for counter in 0..WINDOW_SIZE {
+        assert!(window.check(counter));
+    }
+    // Slides past every block, which must all be cleared.
+    let base = 100 * WINDOW_SIZE;
+    assert!(window.check(base));
+    for counter in base - WINDOW_SIZE..base {
+        assert!(window.check(counter));
+    }
+    assert!(!window.check(base - WINDOW_SIZE - 1));
+}
+
+#[test]
+fn replay_window_limit() {
+    let mut window = ReplayWindow::new();
+    assert!(window.check(REJECT_AFTER_MESSAGES - 1));
+    assert!(!window.check(REJECT_AFTER_MESSAGES));
+    assert!(!window.check(u64::MAX));
+}
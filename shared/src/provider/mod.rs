@@ -0,0 +1,153 @@
+// The primitives the protocol needs, behind one trait so that the key handling does not depend on
+// where they come from: BCrypt in the driver, or the pure-Rust backend below.
+
+#[cfg(feature = "rust-crypto")]
+pub mod rust;
+mod vectors;
+
+pub use vectors::{self_test, SelfTestError};
+
+use crate::kdf::{self, Hash};
+
+pub const X25519_KEY_SIZE: usize = 256 / 8;
+pub const NONCE_SIZE: usize = 96 / 8;
+pub const TAG_SIZE: usize = 128 / 8;
+
+// The discriminants are the values used on the wire and in the IOCTLs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum CipherSuite {
+    Aes128Gcm = 1,
+    Aes192Gcm = 2,
+    Aes256Gcm = 3,
+    ChaCha20Poly1305 = 4,
+}
+
+impl CipherSuite {
+    pub const ALL: [Self; 4] = [
+        Self::Aes128Gcm,
+        Self::Aes192Gcm,
+        Self::Aes256Gcm,
+        Self::ChaCha20Poly1305,
+    ];
+
+    pub fn from_raw(value: u32) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|suite| suite.as_raw() == value)
+    }
+
+    pub fn as_raw(self) -> u32 {
+        self as u32
+    }
+
+    pub fn key_size(self) -> usize {
+        match self {
+            Self::Aes128Gcm => 128 / 8,
+            Self::Aes192Gcm => 192 / 8,
+            Self::Aes256Gcm | Self::ChaCha20Poly1305 => 256 / 8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum HashAlgorithm {
+    Sha256 = 1,
+    Sha512 = 2,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [Self; 2] = [Self::Sha256, Self::Sha512];
+
+    pub fn from_raw(value: u32) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|hash| hash.as_raw() == value)
+    }
+
+    pub fn as_raw(self) -> u32 {
+        self as u32
+    }
+
+    pub fn output_size(self) -> usize {
+        match self {
+            Self::Sha256 => 256 / 8,
+            Self::Sha512 => 512 / 8,
+        }
+    }
+
+    pub fn block_size(self) -> usize {
+        match self {
+            Self::Sha256 => 512 / 8,
+            Self::Sha512 => 1024 / 8,
+        }
+    }
+}
+
+pub trait Aead {
+    type Error;
+
+    fn encrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        text: &mut [u8],
+        tag: &mut [u8; TAG_SIZE],
+    ) -> Result<(), Self::Error>;
+
+    fn decrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        text: &mut [u8],
+        tag: &[u8; TAG_SIZE],
+    ) -> Result<(), Self::Error>;
+}
+
+pub trait CryptoProvider {
+    type Error;
+    type Hash: Hash<Error = Self::Error>;
+    type Aead: Aead<Error = Self::Error>;
+
+    fn hash(&self, algorithm: HashAlgorithm) -> Result<Self::Hash, Self::Error>;
+
+    // Uses the leading `key_size` bytes of the key.
+    fn aead(&self, suite: CipherSuite, key: &[u8]) -> Result<Self::Aead, Self::Error>;
+
+    fn x25519_public_key(
+        &self,
+        private_key: &[u8; X25519_KEY_SIZE],
+    ) -> Result<[u8; X25519_KEY_SIZE], Self::Error>;
+
+    fn x25519(
+        &self,
+        private_key: &[u8; X25519_KEY_SIZE],
+        public_key: &[u8; X25519_KEY_SIZE],
+    ) -> Result<[u8; X25519_KEY_SIZE], Self::Error>;
+
+    fn hkdf(
+        &self,
+        hash: &Self::Hash,
+        salt: &[u8],
+        ikm: &[u8],
+        info: &[u8],
+        okm: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        kdf::hkdf(hash, salt, ikm, info, okm)
+    }
+}
+
+#[test]
+fn raw_values_round_trip() {
+    for suite in CipherSuite::ALL.iter() {
+        assert_eq!(CipherSuite::from_raw(suite.as_raw()), Some(*suite));
+    }
+    for hash in HashAlgorithm::ALL.iter() {
+        assert_eq!(HashAlgorithm::from_raw(hash.as_raw()), Some(*hash));
+    }
+    assert_eq!(CipherSuite::from_raw(0), None);
+    assert_eq!(HashAlgorithm::from_raw(3), None);
+}
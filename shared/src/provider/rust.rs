@@ -0,0 +1,299 @@
+// A pure-Rust backend for platforms without BCrypt. It needs neither `std` nor an allocator.
+
+use aes_gcm::{
+    aead::{
+        consts::{U12, U16},
+        generic_array::GenericArray,
+        AeadInPlace, KeyInit,
+    },
+    aes::Aes192,
+    Aes128Gcm, Aes256Gcm, AesGcm,
+};
+use chacha20poly1305::ChaCha20Poly1305;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::{
+    kdf,
+    provider::{
+        self, CipherSuite, CryptoProvider, HashAlgorithm, NONCE_SIZE, TAG_SIZE, X25519_KEY_SIZE,
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    InvalidKey,
+    MessageTooLong,
+    AuthenticationFailed,
+}
+
+pub struct RustCrypto;
+
+impl CryptoProvider for RustCrypto {
+    type Error = Error;
+    type Hash = Hash;
+    type Aead = Aead;
+
+    fn hash(&self, algorithm: HashAlgorithm) -> Result<Hash, Error> {
+        Ok(Hash(algorithm))
+    }
+
+    fn aead(&self, suite: CipherSuite, key: &[u8]) -> Result<Aead, Error> {
+        let key = key.get(..suite.key_size()).ok_or(Error::InvalidKey)?;
+        Ok(match suite {
+            CipherSuite::Aes128Gcm => {
+                Aead::Aes128Gcm(Aes128Gcm::new(GenericArray::from_slice(key)))
+            }
+            CipherSuite::Aes192Gcm => Aead::Aes192Gcm(AesGcm::new(GenericArray::from_slice(key))),
+            CipherSuite::Aes256Gcm => {
+                Aead::Aes256Gcm(Aes256Gcm::new(GenericArray::from_slice(key)))
+            }
+            CipherSuite::ChaCha20Poly1305 => {
+                Aead::ChaCha20Poly1305(ChaCha20Poly1305::new(GenericArray::from_slice(key)))
+            }
+        })
+    }
+
+    fn x25519_public_key(
+        &self,
+        private_key: &[u8; X25519_KEY_SIZE],
+    ) -> Result<[u8; X25519_KEY_SIZE], Error> {
+        Ok(x25519_dalek::x25519(
+            *private_key,
+            x25519_dalek::X25519_BASEPOINT_BYTES,
+        ))
+    }
+
+    fn x25519(
+        &self,
+        private_key: &[u8; X25519_KEY_SIZE],
+        public_key: &[u8; X25519_KEY_SIZE],
+    ) -> Result<[u8; X25519_KEY_SIZE], Error> {
+        Ok(x25519_dalek::x25519(*private_key, *public_key))
+    }
+}
+
+pub struct Hash(HashAlgorithm);
+
+pub enum HashContext {
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl kdf::Hash for Hash {
+    type Error = Error;
+    type Context = HashContext;
+
+    fn output_size(&self) -> usize {
+        self.0.output_size()
+    }
+
+    fn block_size(&self) -> usize {
+        self.0.block_size()
+    }
+
+    fn init(&self) -> Result<HashContext, Error> {
+        Ok(match self.0 {
+            HashAlgorithm::Sha256 => HashContext::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => HashContext::Sha512(Sha512::new()),
+        })
+    }
+
+    fn update(&self, context: &mut HashContext, data: &[u8]) -> Result<(), Error> {
+        match context {
+            HashContext::Sha256(context) => context.update(data),
+            HashContext::Sha512(context) => context.update(data),
+        }
+        Ok(())
+    }
+
+    fn finish(&self, context: HashContext, out: &mut [u8]) -> Result<(), Error> {
+        match context {
+            HashContext::Sha256(context) => {
+                out[..self.output_size()].copy_from_slice(&context.finalize())
+            }
+            HashContext::Sha512(context) => {
+                out[..self.output_size()].copy_from_slice(&context.finalize())
+            }
+        }
+        Ok(())
+    }
+}
+
+pub enum Aead {
+    Aes128Gcm(Aes128Gcm),
+    Aes192Gcm(AesGcm<Aes192, U12>),
+    Aes256Gcm(Aes256Gcm),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+impl Aead {
+    fn cipher(&self) -> &dyn AeadInPlaceDetached {
+        match self {
+            Self::Aes128Gcm(cipher) => cipher,
+            Self::Aes192Gcm(cipher) => cipher,
+            Self::Aes256Gcm(cipher) => cipher,
+            Self::ChaCha20Poly1305(cipher) => cipher,
+        }
+    }
+}
+
+impl provider::Aead for Aead {
+    type Error = Error;
+
+    fn encrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        text: &mut [u8],
+        tag: &mut [u8; TAG_SIZE],
+    ) -> Result<(), Error> {
+        *tag = self.cipher().encrypt(nonce, aad, text)?;
+        Ok(())
+    }
+
+    fn decrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        text: &mut [u8],
+        tag: &[u8; TAG_SIZE],
+    ) -> Result<(), Error> {
+        self.cipher().decrypt(nonce, aad, text, tag)
+    }
+}
+
+// `AeadInPlace` is generic over the nonce and tag sizes, which all the suites share.
+trait AeadInPlaceDetached {
+    fn encrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        text: &mut [u8],
+    ) -> Result<[u8; TAG_SIZE], Error>;
+
+    fn decrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        text: &mut [u8],
+        tag: &[u8; TAG_SIZE],
+    ) -> Result<(), Error>;
+}
+
+impl<T> AeadInPlaceDetached for T
+where
+    T: AeadInPlace<NonceSize = U12, TagSize = U16>,
+{
+    fn encrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        text: &mut [u8],
+    ) -> Result<[u8; TAG_SIZE], Error> {
+        let tag = self
+            .encrypt_in_place_detached(GenericArray::from_slice(nonce), aad, text)
+            .map_err(|_| Error::MessageTooLong)?;
+        Ok(tag.into())
+    }
+
+    fn decrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        text: &mut [u8],
+        tag: &[u8; TAG_SIZE],
+    ) -> Result<(), Error> {
+        self.decrypt_in_place_detached(
+            GenericArray::from_slice(nonce),
+            aad,
+            text,
+            GenericArray::from_slice(tag),
+        )
+        .map_err(|_| Error::AuthenticationFailed)
+    }
+}
+
+#[test]
+fn rust_crypto_self_test() {
+    assert_eq!(provider::self_test(&RustCrypto), Ok(()));
+}
+
+#[test]
+fn rust_crypto_matches_reference_hkdf() {
+    use crate::kdf::Hash as _;
+
+    // The generic HKDF over this provider's hashes agrees with an independent implementation.
+    struct Reference;
+
+    impl kdf::Hash for Reference {
+        type Error = Error;
+        type Context = Sha512;
+
+        fn output_size(&self) -> usize {
+            64
+        }
+
+        fn block_size(&self) -> usize {
+            128
+        }
+
+        fn init(&self) -> Result<Sha512, Error> {
+            Ok(Sha512::new())
+        }
+
+        fn update(&self, context: &mut Sha512, data: &[u8]) -> Result<(), Error> {
+            Digest::update(context, data);
+            Ok(())
+        }
+
+        fn finish(&self, context: Sha512, out: &mut [u8]) -> Result<(), Error> {
+            out[..64].copy_from_slice(&context.finalize());
+            Ok(())
+        }
+    }
+
+    let hash = RustCrypto.hash(HashAlgorithm::Sha512).unwrap();
+    assert_eq!(hash.output_size(), Reference.output_size());
+    let (mut expected, mut actual) = ([0; 100], [0; 100]);
+    kdf::hkdf(&Reference, b"salt", b"ikm", b"info", &mut expected).unwrap();
+    RustCrypto
+        .hkdf(&hash, b"salt", b"ikm", b"info", &mut actual)
+        .unwrap();
+    assert_eq!(expected[..], actual[..]);
+}
+
+#[test]
+fn rust_crypto_agreement_and_aead() {
+    use provider::Aead as _;
+
+    let alice = [0x11; X25519_KEY_SIZE];
+    let bob = [0x22; X25519_KEY_SIZE];
+    let alice_public = RustCrypto.x25519_public_key(&alice).unwrap();
+    let bob_public = RustCrypto.x25519_public_key(&bob).unwrap();
+    let shared = RustCrypto.x25519(&alice, &bob_public).unwrap();
+    assert_eq!(shared, RustCrypto.x25519(&bob, &alice_public).unwrap());
+
+    for suite in CipherSuite::ALL.iter() {
+        assert_eq!(
+            RustCrypto
+                .aead(*suite, &shared[..suite.key_size() - 1])
+                .err(),
+            Some(Error::InvalidKey),
+        );
+        let aead = RustCrypto.aead(*suite, &shared).unwrap();
+        let mut text = *b"hello";
+        let mut tag = [0; TAG_SIZE];
+        aead.encrypt(&[1; NONCE_SIZE], b"aad", &mut text, &mut tag)
+            .unwrap();
+        assert_ne!(&text, b"hello");
+
+        let mut tampered = text;
+        assert_eq!(
+            aead.decrypt(&[1; NONCE_SIZE], b"aaa", &mut tampered, &tag),
+            Err(Error::AuthenticationFailed),
+        );
+        aead.decrypt(&[1; NONCE_SIZE], b"aad", &mut text, &tag)
+            .unwrap();
+        assert_eq!(&text, b"hello");
+    }
+}
@@ -0,0 +1,159 @@
+// Known-answer tests that every provider has to pass before it is used. Since the vectors come
+// from the specifications, two providers that pass them agree with each other.
+
+use crate::{
+    kdf::{self, MAX_HASH_SIZE},
+    provider::{Aead, CipherSuite, CryptoProvider, HashAlgorithm, NONCE_SIZE, TAG_SIZE},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelfTestError<E> {
+    Provider(E),
+    Mismatch(&'static str),
+}
+
+impl<E> From<E> for SelfTestError<E> {
+    fn from(error: E) -> Self {
+        Self::Provider(error)
+    }
+}
+
+fn unhex<const N: usize>(s: &str) -> [u8; N] {
+    let digit = |c: u8| match c {
+        b'0'..=b'9' => c - b'0',
+        b'a'..=b'f' => c - b'a' + 10,
+        _ => unreachable!(),
+    };
+    let s = s.as_bytes();
+    debug_assert_eq!(s.len(), N * 2);
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = digit(s[i * 2]) << 4 | digit(s[i * 2 + 1]);
+    }
+    bytes
+}
+
+fn check<E>(name: &'static str, equal: bool) -> Result<(), SelfTestError<E>> {
+    if equal {
+        Ok(())
+    } else {
+        Err(SelfTestError::Mismatch(name))
+    }
+}
+
+// FIPS 180-2 appendix B.1 and C.1
+const SHA_INPUT: &[u8] = b"abc";
+const SHA256_OUTPUT: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+const SHA512_OUTPUT: &str = "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f";
+
+// RFC 5869 A.1
+const HKDF_IKM: &str = "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b";
+const HKDF_SALT: &str = "000102030405060708090a0b0c";
+const HKDF_INFO: &str = "f0f1f2f3f4f5f6f7f8f9";
+const HKDF_OKM: &str = "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf\
+                        34007208d5b887185865";
+
+// RFC 7748 6.1
+const X25519_ALICE_PRIVATE: &str =
+    "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a";
+const X25519_ALICE_PUBLIC: &str =
+    "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a";
+const X25519_BOB_PRIVATE: &str = "5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb";
+const X25519_BOB_PUBLIC: &str = "de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f";
+const X25519_SHARED: &str = "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742";
+
+// The GCM specification, test cases 2, 8 and 14: an all-zero key, nonce and block.
+const AES_GCM_CIPHERTEXTS: [(CipherSuite, &str, &str); 3] = [
+    (
+        CipherSuite::Aes128Gcm,
+        "0388dace60b6a392f328c2b971b2fe78",
+        "ab6e47d42cec13bdf53a67b21257bddf",
+    ),
+    (
+        CipherSuite::Aes192Gcm,
+        "98e7247c07f0fe411c267e4384b0f600",
+        "2ff58d80033927ab8ef4d4587514f0fb",
+    ),
+    (
+        CipherSuite::Aes256Gcm,
+        "cea7403d4d606b6e074ec5d3baf39d18",
+        "d0d1c8a799996bf0265b98b5d48ab919",
+    ),
+];
+
+// RFC 8439 2.8.2
+const CHACHA_KEY: &str = "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f";
+const CHACHA_NONCE: &str = "070000004041424344454647";
+const CHACHA_AAD: &str = "50515253c0c1c2c3c4c5c6c7";
+const CHACHA_PLAINTEXT: &[u8; 114] = b"Ladies and Gentlemen of the class of '99: \
+    If I could offer you only one tip for the future, sunscreen would be it.";
+const CHACHA_CIPHERTEXT: &str = "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+                                 3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+                                 92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+                                 3ff4def08e4b7a9de576d26586cec64b6116";
+const CHACHA_TAG: &str = "1ae10b594f09e26a7e902ecbd0600691";
+
+pub fn self_test<P: CryptoProvider>(provider: &P) -> Result<(), SelfTestError<P::Error>> {
+    let mut out = [0; MAX_HASH_SIZE];
+    let sha256 = provider.hash(HashAlgorithm::Sha256)?;
+    kdf::hash(&sha256, &[SHA_INPUT], &mut out)?;
+    check("SHA-256", out[..32] == unhex::<32>(SHA256_OUTPUT))?;
+    let sha512 = provider.hash(HashAlgorithm::Sha512)?;
+    kdf::hash(&sha512, &[SHA_INPUT], &mut out)?;
+    check("SHA-512", out == unhex::<64>(SHA512_OUTPUT))?;
+
+    let mut okm = [0; 42];
+    provider.hkdf(
+        &sha256,
+        &unhex::<13>(HKDF_SALT),
+        &unhex::<22>(HKDF_IKM),
+        &unhex::<10>(HKDF_INFO),
+        &mut okm,
+    )?;
+    check("HKDF-SHA-256", okm == unhex::<42>(HKDF_OKM))?;
+
+    let alice_private = unhex(X25519_ALICE_PRIVATE);
+    let alice_public = unhex(X25519_ALICE_PUBLIC);
+    let bob_private = unhex(X25519_BOB_PRIVATE);
+    let bob_public = unhex(X25519_BOB_PUBLIC);
+    let shared = unhex(X25519_SHARED);
+    check(
+        "X25519 public key",
+        provider.x25519_public_key(&alice_private)? == alice_public
+            && provider.x25519_public_key(&bob_private)? == bob_public,
+    )?;
+    check(
+        "X25519",
+        provider.x25519(&alice_private, &bob_public)? == shared
+            && provider.x25519(&bob_private, &alice_public)? == shared,
+    )?;
+
+    for (suite, ciphertext, tag) in AES_GCM_CIPHERTEXTS.iter() {
+        let aead = provider.aead(*suite, &[0; 32])?;
+        let mut text = [0; 16];
+        let mut actual_tag = [0; TAG_SIZE];
+        aead.encrypt(&[0; NONCE_SIZE], &[], &mut text, &mut actual_tag)?;
+        check(
+            "AES-GCM",
+            text == unhex::<16>(ciphertext) && actual_tag == unhex::<TAG_SIZE>(tag),
+        )?;
+        aead.decrypt(&[0; NONCE_SIZE], &[], &mut text, &actual_tag)?;
+        check("AES-GCM", text == [0; 16])?;
+    }
+
+    let aead = provider.aead(CipherSuite::ChaCha20Poly1305, &unhex::<32>(CHACHA_KEY))?;
+    let nonce = unhex(CHACHA_NONCE);
+    let aad = unhex::<12>(CHACHA_AAD);
+    let mut text = *CHACHA_PLAINTEXT;
+    let mut tag = [0; TAG_SIZE];
+    aead.encrypt(&nonce, &aad, &mut text, &mut tag)?;
+    check(
+        "ChaCha20-Poly1305",
+        text == unhex::<114>(CHACHA_CIPHERTEXT) && tag == unhex::<TAG_SIZE>(CHACHA_TAG),
+    )?;
+    aead.decrypt(&nonce, &aad, &mut text, &tag)?;
+    check("ChaCha20-Poly1305", text == *CHACHA_PLAINTEXT)?;
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+// Hub-and-spoke relaying: where a frame or a packet that arrived from a peer goes when the adapter
+// relays between its peers. A destination behind another peer takes it there instead of to the
+// local stack, and what the stack would flood is both delivered and relayed to every other peer.
+// The hops in the wire header bound how far it travels.
+
+use crate::{
+    forward::{self, FloodPolicy, Forward},
+    mac_addr::MAC_ADDR_SIZE,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Relay<P> {
+    // To the local stack only
+    Deliver,
+    // To the peer only
+    To(P),
+    // To the local stack and every peer but the source
+    Flood,
+}
+
+// `find` looks up the peer a unicast destination was learned from, as for frames from the stack.
+pub fn relay_frame<P: PartialEq>(
+    frame: &[u8],
+    local_mac_addr: &[u8; MAC_ADDR_SIZE],
+    source: &P,
+    policy: FloodPolicy,
+    find: impl FnOnce(&[u8; MAC_ADDR_SIZE]) -> Option<P>,
+) -> Relay<P> {
+    if frame.get(..MAC_ADDR_SIZE) == Some(&local_mac_addr[..]) {
+        return Relay::Deliver;
+    }
+    match forward::forward(frame, policy, find) {
+        // Never back where it came from
+        Forward::To(peer) if peer == *source => Relay::Deliver,
+        Forward::To(peer) => Relay::To(peer),
+        Forward::Flood => Relay::Flood,
+        // The policy only keeps it from the other peers.
+        Forward::Drop => Relay::Deliver,
+    }
+}
+
+// `owner` is the peer whose allowed IPs the destination is in.
+pub fn relay_packet<P: PartialEq>(owner: Option<P>, source: &P) -> Relay<P> {
+    match owner {
+        Some(peer) if peer != *source => Relay::To(peer),
+        _ => Relay::Deliver,
+    }
+}
+
+#[cfg(test)]
+const LOCAL: [u8; MAC_ADDR_SIZE] = [0x02, 0x15, 0x5d, 0x00, 0x00, 0x01];
+
+#[cfg(test)]
+fn frame(dst: [u8; MAC_ADDR_SIZE]) -> [u8; 64] {
+    let mut frame = [0; 64];
+    frame[..MAC_ADDR_SIZE].copy_from_slice(&dst);
+    frame
+}
+
+#[cfg(test)]
+fn relay_with(dst: [u8; MAC_ADDR_SIZE], policy: FloodPolicy) -> Relay<u32> {
+    // Peer 1 is the source, and peer 2 is behind the last address byte 2.
+    relay_frame(&frame(dst), &LOCAL, &1, policy, |dst| match dst[5] {
+        1 | 2 => Some(u32::from(dst[5])),
+        _ => None,
+    })
+}
+
+#[test]
+fn relay_frame_unicast() {
+    let policy = FloodPolicy::default();
+    assert_eq!(relay_with(LOCAL, policy), Relay::Deliver);
+    assert_eq!(
+        relay_with([0x02, 0x15, 0x5d, 0x00, 0x01, 2], policy),
+        Relay::To(2),
+    );
+    assert_eq!(
+        relay_with([0x02, 0x15, 0x5d, 0x00, 0x01, 1], policy),
+        Relay::Deliver,
+    );
+    assert_eq!(
+        relay_with([0x02, 0x15, 0x5d, 0x00, 0x01, 3], policy),
+        Relay::Flood,
+    );
+
+    let none = FloodPolicy::from_raw(0).unwrap();
+    assert_eq!(
+        relay_with([0x02, 0x15, 0x5d, 0x00, 0x01, 3], none),
+        Relay::Deliver,
+    );
+    assert_eq!(
+        relay_with([0x02, 0x15, 0x5d, 0x00, 0x01, 2], none),
+        Relay::To(2)
+    );
+}
+
+#[test]
+fn relay_frame_group() {
+    let policy = FloodPolicy::default();
+    assert_eq!(relay_with([0xff; MAC_ADDR_SIZE], policy), Relay::Flood);
+    assert_eq!(relay_with([0x33, 0x33, 0, 0, 0, 1], policy), Relay::Flood);
+    let none = FloodPolicy::from_raw(0).unwrap();
+    assert_eq!(relay_with([0xff; MAC_ADDR_SIZE], none), Relay::Flood);
+    assert_eq!(relay_with([0x33, 0x33, 0, 0, 0, 1], none), Relay::Deliver);
+
+    // Too short to tell
+    assert_eq!(
+        relay_frame(&[0xff; 4], &LOCAL, &1, policy, |_| None::<u32>),
+        Relay::Deliver
+    );
+}
+
+#[test]
+fn relay_packet_owner() {
+    assert_eq!(relay_packet(Some(2), &1), Relay::To(2));
+    assert_eq!(relay_packet(Some(1), &1), Relay::Deliver);
+    assert_eq!(relay_packet(None, &1), Relay::Deliver);
+}
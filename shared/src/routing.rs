@@ -0,0 +1,490 @@
+// Cryptokey routing, for adapters in layer-3 mode: each peer owns a set of prefixes, its allowed
+// IPs. A packet from the stack goes to the peer owning the longest prefix that holds its
+// destination, and a packet from a peer is only accepted if its source routes back to that peer.
+// Only the IP packet crosses the overlay, without the Ethernet header.
+//
+// IPv4 addresses are kept IPv4-mapped so that both families share a table: the IPv4 prefix of
+// length `n` is the IPv6 one of length `96 + n`. The table is fixed and hashed by prefix, and a
+// lookup probes each prefix length in use, the longest first.
+
+use core::{convert::TryFrom, mem::MaybeUninit, ptr};
+
+use crate::{
+    fnv,
+    ioctl::{VEthPrefix, KEY_SIZE},
+    mtu::ETH_HEADER_SIZE,
+};
+
+pub const ADDR_SIZE: usize = 16;
+pub const MAX_PREFIX_LEN: u8 = 128;
+
+pub const ETHER_TYPE_IPV4: u16 = 0x0800;
+pub const ETHER_TYPE_IPV6: u16 = 0x86dd;
+
+const IPV4_MAPPED: [u8; 12] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff];
+const IPV4_HEADER_SIZE: usize = 20;
+const IPV6_HEADER_SIZE: usize = 40;
+
+const PROBES: usize = 8;
+
+// The discriminants are the values used in the IOCTLs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Mode {
+    // Ethernet frames go to the peer their destination was learned from.
+    Layer2 = 0,
+    // IP packets go to the peer that owns their destination.
+    Layer3 = 1,
+}
+
+impl Mode {
+    pub const ALL: [Self; 2] = [Self::Layer2, Self::Layer3];
+
+    pub fn from_raw(value: u32) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|mode| mode.as_raw() == value)
+    }
+
+    pub fn as_raw(self) -> u32 {
+        self as u32
+    }
+}
+
+pub fn ipv4_mapped(addr: [u8; 4]) -> [u8; ADDR_SIZE] {
+    let mut mapped = [0; ADDR_SIZE];
+    mapped[..IPV4_MAPPED.len()].copy_from_slice(&IPV4_MAPPED);
+    mapped[IPV4_MAPPED.len()..].copy_from_slice(&addr);
+    mapped
+}
+
+// Clears the bits past the first `len`.
+fn mask(addr: &[u8; ADDR_SIZE], len: u8) -> [u8; ADDR_SIZE] {
+    let mut masked = [0; ADDR_SIZE];
+    for (i, byte) in masked.iter_mut().enumerate() {
+        let bits = usize::from(len).saturating_sub(i * 8).min(8) as u32;
+        *byte = addr[i] & !0xffu8.checked_shr(bits).unwrap_or(0);
+    }
+    masked
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Prefix {
+    addr: [u8; ADDR_SIZE],
+    len: u8,
+}
+
+impl Prefix {
+    // The address bits past the prefix are ignored.
+    pub fn new(addr: &[u8; ADDR_SIZE], len: u8) -> Option<Self> {
+        if len > MAX_PREFIX_LEN {
+            return None;
+        }
+        Some(Self {
+            addr: mask(addr, len),
+            len,
+        })
+    }
+
+    pub fn from_raw(raw: &VEthPrefix) -> Option<Self> {
+        Self::new(&raw.addr, u8::try_from(raw.len).ok()?)
+    }
+
+    pub fn as_raw(&self) -> VEthPrefix {
+        VEthPrefix {
+            addr: self.addr,
+            len: self.len.into(),
+        }
+    }
+
+    pub fn contains(&self, addr: &[u8; ADDR_SIZE]) -> bool {
+        mask(addr, self.len) == self.addr
+    }
+}
+
+// The IP packet carried by an Ethernet frame from the stack
+pub fn ip_packet(frame: &[u8]) -> Option<&[u8]> {
+    let header_size = ETH_HEADER_SIZE as usize;
+    if frame.len() < header_size {
+        return None;
+    }
+    match u16::from_be_bytes([frame[12], frame[13]]) {
+        ETHER_TYPE_IPV4 | ETHER_TYPE_IPV6 => Some(&frame[header_size..]),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct IpHeader {
+    // Of the Ethernet frame that carries the packet
+    pub ether_type: u16,
+    pub src: [u8; ADDR_SIZE],
+    pub dst: [u8; ADDR_SIZE],
+}
+
+// The addresses of an IPv4 or IPv6 packet, told apart by its version
+pub fn parse_ip(packet: &[u8]) -> Option<IpHeader> {
+    let version = packet.first()? >> 4;
+    let mut src = [0; ADDR_SIZE];
+    let mut dst = [0; ADDR_SIZE];
+    match version {
+        4 if packet.len() >= IPV4_HEADER_SIZE => {
+            let mut addr = [0; 4];
+            addr.copy_from_slice(&packet[12..16]);
+            src = ipv4_mapped(addr);
+            addr.copy_from_slice(&packet[16..20]);
+            dst = ipv4_mapped(addr);
+            Some(IpHeader {
+                ether_type: ETHER_TYPE_IPV4,
+                src,
+                dst,
+            })
+        }
+        6 if packet.len() >= IPV6_HEADER_SIZE => {
+            src.copy_from_slice(&packet[8..24]);
+            dst.copy_from_slice(&packet[24..40]);
+            Some(IpHeader {
+                ether_type: ETHER_TYPE_IPV6,
+                src,
+                dst,
+            })
+        }
+        _ => None,
+    }
+}
+
+// The table has no room left for a prefix.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Full;
+
+// All zeros is an empty entry, so that a table can be initialized in place.
+#[derive(Clone, Copy)]
+struct Entry {
+    used: bool,
+    len: u8,
+    addr: [u8; ADDR_SIZE],
+    public_key: [u8; KEY_SIZE],
+}
+
+impl Entry {
+    const EMPTY: Self = Self {
+        used: false,
+        len: 0,
+        addr: [0; ADDR_SIZE],
+        public_key: [0; KEY_SIZE],
+    };
+
+    fn is(&self, addr: &[u8; ADDR_SIZE], len: u8) -> bool {
+        self.used && self.len == len && self.addr == *addr
+    }
+}
+
+pub struct Routes<const N: usize> {
+    seed: u64,
+    // How many prefixes of each length there are
+    lens: [u16; MAX_PREFIX_LEN as usize + 1],
+    entries: [Entry; N],
+}
+
+impl<const N: usize> Routes<N> {
+    pub const fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            lens: [0; MAX_PREFIX_LEN as usize + 1],
+            entries: [Entry::EMPTY; N],
+        }
+    }
+
+    // Initializes a table too large to be built on the stack.
+    pub fn init(uninit: &mut MaybeUninit<Self>, seed: u64) -> &mut Self {
+        unsafe {
+            // All zeros is a valid, empty table.
+            ptr::write_bytes(uninit.as_mut_ptr(), 0, 1);
+            let init = &mut *uninit.as_mut_ptr();
+            init.seed = seed;
+            init
+        }
+    }
+
+    fn probes(&self, addr: &[u8; ADDR_SIZE], len: u8) -> impl Iterator<Item = usize> {
+        let mut key = [0; ADDR_SIZE + 1];
+        key[..ADDR_SIZE].copy_from_slice(addr);
+        key[ADDR_SIZE] = len;
+        let start = fnv::hash(self.seed, &key);
+        (0..PROBES.min(N)).map(move |i| start.wrapping_add(i) % N)
+    }
+
+    // Gives the peer the prefix, taking it from any other peer that had it.
+    pub fn insert(&mut self, prefix: &Prefix, public_key: &[u8; KEY_SIZE]) -> Result<(), Full> {
+        let mut free = None;
+        for index in self.probes(&prefix.addr, prefix.len) {
+            let entry = &mut self.entries[index];
+            if entry.is(&prefix.addr, prefix.len) {
+                entry.public_key = *public_key;
+                return Ok(());
+            }
+            if !entry.used && free.is_none() {
+                free = Some(index);
+            }
+        }
+        let index = free.ok_or(Full)?;
+        self.entries[index] = Entry {
+            used: true,
+            len: prefix.len,
+            addr: prefix.addr,
+            public_key: *public_key,
+        };
+        self.lens[usize::from(prefix.len)] += 1;
+        Ok(())
+    }
+
+    // Replaces the prefixes of the peer. On failure, the peer is left without any.
+    pub fn set_peer(
+        &mut self,
+        public_key: &[u8; KEY_SIZE],
+        prefixes: impl IntoIterator<Item = Prefix>,
+    ) -> Result<(), Full> {
+        self.remove_peer(public_key);
+        for prefix in prefixes {
+            if let Err(full) = self.insert(&prefix, public_key) {
+                self.remove_peer(public_key);
+                return Err(full);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove_peer(&mut self, public_key: &[u8; KEY_SIZE]) {
+        for entry in self.entries.iter_mut() {
+            if entry.used && entry.public_key == *public_key {
+                self.lens[usize::from(entry.len)] -= 1;
+                *entry = Entry::EMPTY;
+            }
+        }
+    }
+
+    // The peer owning the longest prefix that holds `addr`
+    pub fn lookup(&self, addr: &[u8; ADDR_SIZE]) -> Option<&[u8; KEY_SIZE]> {
+        for len in (0..=MAX_PREFIX_LEN).rev() {
+            if self.lens[usize::from(len)] == 0 {
+                continue;
+            }
+            let masked = mask(addr, len);
+            let found = self
+                .probes(&masked, len)
+                .map(|index| &self.entries[index])
+                .find(|entry| entry.is(&masked, len));
+            if let Some(entry) = found {
+                return Some(&entry.public_key);
+            }
+        }
+        None
+    }
+
+    // The peer that owns `addr` as a host prefix, i.e. as an address of its own
+    pub fn lookup_host(&self, addr: &[u8; ADDR_SIZE]) -> Option<&[u8; KEY_SIZE]> {
+        let entry = self
+            .probes(addr, MAX_PREFIX_LEN)
+            .map(|index| &self.entries[index])
+            .find(|entry| entry.is(addr, MAX_PREFIX_LEN))?;
+        Some(&entry.public_key)
+    }
+}
+
+#[cfg(test)]
+fn v4(a: u8, b: u8, c: u8, d: u8) -> [u8; ADDR_SIZE] {
+    ipv4_mapped([a, b, c, d])
+}
+
+#[cfg(test)]
+fn v4_prefix(addr: [u8; ADDR_SIZE], len: u8) -> Prefix {
+    Prefix::new(&addr, 96 + len).unwrap()
+}
+
+#[cfg(test)]
+fn v6(segments: [u16; 8]) -> [u8; ADDR_SIZE] {
+    let mut addr = [0; ADDR_SIZE];
+    for (bytes, segment) in addr.chunks_mut(2).zip(segments.iter()) {
+        bytes.copy_from_slice(&segment.to_be_bytes());
+    }
+    addr
+}
+
+#[test]
+fn routing_mode_raw() {
+    for mode in Mode::ALL.iter().copied() {
+        assert_eq!(Mode::from_raw(mode.as_raw()), Some(mode));
+    }
+    assert_eq!(Mode::from_raw(2), None);
+}
+
+#[test]
+fn routing_prefix() {
+    // Host bits are cleared.
+    let prefix = v4_prefix(v4(10, 1, 2, 3), 16);
+    assert_eq!(prefix.as_raw().addr, v4(10, 1, 0, 0));
+    assert_eq!(prefix.as_raw().len, 112);
+    assert!(prefix.contains(&v4(10, 1, 255, 255)));
+    assert!(!prefix.contains(&v4(10, 2, 0, 0)));
+
+    let prefix = v4_prefix(v4(192, 168, 1, 129), 25);
+    assert_eq!(prefix.as_raw().addr, v4(192, 168, 1, 128));
+
+    let prefix = Prefix::new(&v6([0xfd00, 0, 0, 0, 0, 0, 0, 1]), 0).unwrap();
+    assert_eq!(prefix.as_raw().addr, [0; ADDR_SIZE]);
+    assert!(prefix.contains(&v4(8, 8, 8, 8)));
+
+    assert_eq!(Prefix::new(&[0; ADDR_SIZE], 129), None);
+    let raw = VEthPrefix {
+        addr: [0; ADDR_SIZE],
+        len: 0x100,
+    };
+    assert_eq!(Prefix::from_raw(&raw), None);
+}
+
+#[test]
+fn routing_longest_prefix() {
+    let mut routes = Box::new(Routes::<256>::new(0x5eed));
+    let (peer_a, peer_b, peer_c) = ([1; KEY_SIZE], [2; KEY_SIZE], [3; KEY_SIZE]);
+    routes
+        .set_peer(
+            &peer_a,
+            vec![
+                v4_prefix(v4(10, 0, 0, 0), 8),
+                Prefix::new(&v6([0xfd00, 0, 0, 0, 0, 0, 0, 0]), 16).unwrap(),
+            ],
+        )
+        .unwrap();
+    routes
+        .set_peer(
+            &peer_b,
+            vec![
+                v4_prefix(v4(10, 1, 0, 0), 16),
+                Prefix::new(&v6([0xfd00, 0, 0, 1, 0, 0, 0, 0]), 64).unwrap(),
+            ],
+        )
+        .unwrap();
+    routes
+        .set_peer(&peer_c, vec![v4_prefix(v4(10, 1, 2, 3), 32)])
+        .unwrap();
+
+    assert_eq!(routes.lookup(&v4(10, 9, 9, 9)), Some(&peer_a));
+    assert_eq!(routes.lookup(&v4(10, 1, 9, 9)), Some(&peer_b));
+    assert_eq!(routes.lookup(&v4(10, 1, 2, 3)), Some(&peer_c));
+    assert_eq!(routes.lookup(&v4(11, 0, 0, 1)), None);
+    // Only a host prefix makes an address a peer's own.
+    assert_eq!(routes.lookup_host(&v4(10, 1, 2, 3)), Some(&peer_c));
+    assert_eq!(routes.lookup_host(&v4(10, 1, 9, 9)), None);
+    assert_eq!(
+        routes.lookup(&v6([0xfd00, 0, 0, 1, 0, 0, 0, 5])),
+        Some(&peer_b)
+    );
+    assert_eq!(
+        routes.lookup(&v6([0xfd00, 0, 0, 2, 0, 0, 0, 5])),
+        Some(&peer_a)
+    );
+    assert_eq!(routes.lookup(&v6([0xfe80, 0, 0, 0, 0, 0, 0, 1])), None);
+
+    // A default route catches the rest, without hiding the longer prefixes.
+    routes
+        .set_peer(&peer_c, vec![Prefix::new(&[0; ADDR_SIZE], 0).unwrap()])
+        .unwrap();
+    assert_eq!(routes.lookup(&v4(11, 0, 0, 1)), Some(&peer_c));
+    assert_eq!(routes.lookup(&v4(10, 1, 2, 3)), Some(&peer_b));
+}
+
+#[test]
+fn routing_set_peer() {
+    let mut routes = Box::new(Routes::<64>::new(0));
+    let (peer_a, peer_b) = ([1; KEY_SIZE], [2; KEY_SIZE]);
+    let subnet = v4_prefix(v4(10, 0, 0, 0), 24);
+    routes.set_peer(&peer_a, vec![subnet]).unwrap();
+    assert_eq!(routes.lookup(&v4(10, 0, 0, 1)), Some(&peer_a));
+
+    // Taken over by another peer
+    routes.set_peer(&peer_b, vec![subnet]).unwrap();
+    assert_eq!(routes.lookup(&v4(10, 0, 0, 1)), Some(&peer_b));
+
+    // Replaced, then removed
+    routes
+        .set_peer(&peer_b, vec![v4_prefix(v4(10, 0, 1, 0), 24)])
+        .unwrap();
+    assert_eq!(routes.lookup(&v4(10, 0, 0, 1)), None);
+    assert_eq!(routes.lookup(&v4(10, 0, 1, 1)), Some(&peer_b));
+    routes.remove_peer(&peer_b);
+    assert_eq!(routes.lookup(&v4(10, 0, 1, 1)), None);
+    assert!(routes.lens.iter().all(|&count| count == 0));
+}
+
+#[test]
+fn routing_full() {
+    // Every prefix shares the whole table as its probe sequence.
+    let mut routes = Routes::<{ PROBES }>::new(0);
+    let (peer_a, peer_b) = ([1; KEY_SIZE], [2; KEY_SIZE]);
+    let hosts: Vec<_> = (0..PROBES as u8)
+        .map(|i| v4_prefix(v4(10, 0, 0, i), 32))
+        .collect();
+    routes.set_peer(&peer_a, hosts).unwrap();
+
+    // The peer is left without any rather than some.
+    let more = vec![
+        v4_prefix(v4(10, 0, 1, 0), 24),
+        v4_prefix(v4(10, 0, 2, 0), 24),
+    ];
+    assert_eq!(routes.set_peer(&peer_b, more), Err(Full));
+    assert!(routes
+        .entries
+        .iter()
+        .all(|entry| entry.public_key != peer_b));
+    assert_eq!(routes.lookup(&v4(10, 0, 0, 7)), Some(&peer_a));
+
+    let mut uninit = MaybeUninit::<Routes<16>>::uninit();
+    let in_place = Routes::init(&mut uninit, 0);
+    in_place
+        .insert(&v4_prefix(v4(10, 0, 0, 0), 8), &peer_a)
+        .unwrap();
+    assert_eq!(in_place.lookup(&v4(10, 1, 2, 3)), Some(&peer_a));
+}
+
+#[test]
+fn routing_parse() {
+    let mut frame = [0; 14 + IPV6_HEADER_SIZE];
+    frame[12..14].copy_from_slice(&ETHER_TYPE_IPV4.to_be_bytes());
+    frame[14] = 0x45;
+    frame[14 + 12..14 + 16].copy_from_slice(&[192, 0, 2, 1]);
+    frame[14 + 16..14 + 20].copy_from_slice(&[10, 0, 0, 2]);
+    let packet = ip_packet(&frame).unwrap();
+    assert_eq!(
+        parse_ip(packet),
+        Some(IpHeader {
+            ether_type: ETHER_TYPE_IPV4,
+            src: v4(192, 0, 2, 1),
+            dst: v4(10, 0, 0, 2),
+        })
+    );
+    assert_eq!(parse_ip(&packet[..IPV4_HEADER_SIZE - 1]), None);
+
+    let src = v6([0xfd00, 0, 0, 0, 0, 0, 0, 1]);
+    let dst = v6([0xfd00, 0, 0, 0, 0, 0, 0, 2]);
+    let mut packet = [0; IPV6_HEADER_SIZE];
+    packet[0] = 0x60;
+    packet[8..24].copy_from_slice(&src);
+    packet[24..40].copy_from_slice(&dst);
+    assert_eq!(
+        parse_ip(&packet),
+        Some(IpHeader {
+            ether_type: ETHER_TYPE_IPV6,
+            src,
+            dst,
+        })
+    );
+    assert_eq!(parse_ip(&packet[..IPV6_HEADER_SIZE - 1]), None);
+
+    // Neither IPv4 nor IPv6
+    frame[12..14].copy_from_slice(&0x0806u16.to_be_bytes());
+    assert_eq!(ip_packet(&frame), None);
+    assert_eq!(ip_packet(&frame[..13]), None);
+    assert_eq!(parse_ip(&[0x50; IPV6_HEADER_SIZE]), None);
+    assert_eq!(parse_ip(&[]), None);
+}
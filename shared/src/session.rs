@@ -0,0 +1,349 @@
+// The keypairs of one peer and when they have to be replaced.
+//
+// The initiator of a handshake starts sending with the new keypair as soon as the response
+// arrives. The responder keeps it as `next` until the first message under it is received, so that
+// it never sends with keys the initiator does not have yet. The replaced keypair stays around as
+// `previous` for a short while to decrypt messages that were already in flight.
+//
+// Times are offsets from an arbitrary origin supplied by the caller.
+
+use core::time::Duration;
+
+use crate::provider::CipherSuite;
+
+pub const REKEY_AFTER_TIME: Duration = Duration::from_secs(120);
+pub const REJECT_AFTER_TIME: Duration = Duration::from_secs(180);
+pub const KEEP_PREVIOUS_TIME: Duration = Duration::from_secs(10);
+
+// How many messages one keypair may send. AES-GCM is good for far fewer than ChaCha20-Poly1305:
+// TLS 1.3 caps it at 2^24.5 full-size records per key (RFC 8446, section 5.5).
+pub fn rekey_after_messages(suite: CipherSuite) -> u64 {
+    match suite {
+        CipherSuite::Aes128Gcm | CipherSuite::Aes192Gcm | CipherSuite::Aes256Gcm => 1 << 24,
+        CipherSuite::ChaCha20Poly1305 => 1 << 60,
+    }
+}
+
+pub trait SessionKeypair {
+    fn local_index(&self) -> u32;
+    fn cipher_suite(&self) -> CipherSuite;
+    fn sent_messages(&self) -> u64;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeySlot {
+    Current,
+    Previous,
+    Next,
+}
+
+// Keypairs that left the session. They are returned so that they can be destroyed later, e.g.
+// after a lock has been released.
+pub type Evicted<K> = [Option<K>; 2];
+
+struct Slot<K> {
+    keypair: K,
+    created_at: Duration,
+    initiator: bool,
+}
+
+impl<K> Slot<K> {
+    fn is_expired(&self, now: Duration) -> bool {
+        now.saturating_sub(self.created_at) >= REJECT_AFTER_TIME
+    }
+}
+
+pub struct Session<K> {
+    current: Option<Slot<K>>,
+    previous: Option<Slot<K>>,
+    next: Option<Slot<K>>,
+    previous_until: Duration,
+}
+
+impl<K> Default for Session<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> Session<K> {
+    pub const fn new() -> Self {
+        Self {
+            current: None,
+            previous: None,
+            next: None,
+            previous_until: Duration::from_secs(0),
+        }
+    }
+
+    pub fn insert(&mut self, keypair: K, initiator: bool, now: Duration) -> Evicted<K> {
+        let slot = Slot {
+            keypair,
+            created_at: now,
+            initiator,
+        };
+        if initiator {
+            let next = self.next.take().map(|slot| slot.keypair);
+            let previous = self.retire(now);
+            self.current = Some(slot);
+            [next, previous]
+        } else {
+            [self.next.replace(slot).map(|slot| slot.keypair), None]
+        }
+    }
+
+    pub fn current(&self, now: Duration) -> Option<&K> {
+        match &self.current {
+            Some(slot) if !slot.is_expired(now) => Some(&slot.keypair),
+            _ => None,
+        }
+    }
+
+    pub fn clear(&mut self) -> [Option<K>; 3] {
+        [
+            self.current.take().map(|slot| slot.keypair),
+            self.previous.take().map(|slot| slot.keypair),
+            self.next.take().map(|slot| slot.keypair),
+        ]
+    }
+
+    fn retire(&mut self, now: Duration) -> Option<K> {
+        self.previous_until = now + KEEP_PREVIOUS_TIME;
+        let previous = self.previous.take();
+        self.previous = self.current.take();
+        previous.map(|slot| slot.keypair)
+    }
+}
+
+impl<K: SessionKeypair> Session<K> {
+    pub fn find(&self, local_index: u32, now: Duration) -> Option<(&K, KeySlot)> {
+        let slots = [
+            (&self.current, KeySlot::Current),
+            (&self.next, KeySlot::Next),
+            (&self.previous, KeySlot::Previous),
+        ];
+        slots.iter().find_map(|(slot, kind)| match slot {
+            Some(slot) if slot.keypair.local_index() == local_index => {
+                if slot.is_expired(now)
+                    || (*kind == KeySlot::Previous && now >= self.previous_until)
+                {
+                    None
+                } else {
+                    Some((&slot.keypair, *kind))
+                }
+            }
+            _ => None,
+        })
+    }
+
+    // Promotes `next` once the initiator has proven that it uses it.
+    pub fn confirm(&mut self, local_index: u32, now: Duration) -> Evicted<K> {
+        match &self.next {
+            Some(slot) if slot.keypair.local_index() == local_index => {
+                let next = self.next.take();
+                let previous = self.retire(now);
+                self.current = next;
+                [previous, None]
+            }
+            _ => [None, None],
+        }
+    }
+
+    pub fn needs_rekey(&self, now: Duration) -> bool {
+        match &self.current {
+            None => true,
+            Some(slot) => {
+                slot.is_expired(now)
+                    || slot.keypair.sent_messages()
+                        >= rekey_after_messages(slot.keypair.cipher_suite())
+                    || (slot.initiator && now.saturating_sub(slot.created_at) >= REKEY_AFTER_TIME)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+use core::cell::Cell;
+
+#[cfg(test)]
+struct FakeKeypair {
+    index: u32,
+    suite: CipherSuite,
+    sent: Cell<u64>,
+}
+
+#[cfg(test)]
+impl FakeKeypair {
+    fn new(index: u32) -> Self {
+        Self {
+            index,
+            suite: CipherSuite::ChaCha20Poly1305,
+            sent: Cell::new(0),
+        }
+    }
+}
+
+#[cfg(test)]
+impl SessionKeypair for FakeKeypair {
+    fn local_index(&self) -> u32 {
+        self.index
+    }
+
+    fn cipher_suite(&self) -> CipherSuite {
+        self.suite
+    }
+
+    fn sent_messages(&self) -> u64 {
+        self.sent.get()
+    }
+}
+
+#[cfg(test)]
+struct FakeClock(Duration);
+
+#[cfg(test)]
+impl FakeClock {
+    fn now(&self) -> Duration {
+        self.0
+    }
+
+    fn advance(&mut self, duration: Duration) {
+        self.0 += duration;
+    }
+}
+
+#[cfg(test)]
+fn evicted_indices<K: SessionKeypair>(evicted: &[Option<K>]) -> Vec<u32> {
+    evicted.iter().flatten().map(|k| k.local_index()).collect()
+}
+
+#[test]
+fn session_initiator_rotates() {
+    let mut clock = FakeClock(Duration::from_secs(1000));
+    let mut session = Session::new();
+    assert!(session.needs_rekey(clock.now()));
+    assert!(session.current(clock.now()).is_none());
+
+    let evicted = session.insert(FakeKeypair::new(1), true, clock.now());
+    assert!(evicted_indices(&evicted).is_empty());
+    assert_eq!(session.current(clock.now()).unwrap().index, 1);
+    assert!(!session.needs_rekey(clock.now()));
+
+    clock.advance(REKEY_AFTER_TIME - Duration::from_secs(1));
+    assert!(!session.needs_rekey(clock.now()));
+    clock.advance(Duration::from_secs(1));
+    assert!(session.needs_rekey(clock.now()));
+    assert_eq!(session.current(clock.now()).unwrap().index, 1);
+
+    let evicted = session.insert(FakeKeypair::new(2), true, clock.now());
+    assert!(evicted_indices(&evicted).is_empty());
+    assert_eq!(session.current(clock.now()).unwrap().index, 2);
+    assert!(!session.needs_rekey(clock.now()));
+    assert_eq!(
+        session.find(1, clock.now()).map(|(k, s)| (k.index, s)),
+        Some((1, KeySlot::Previous)),
+    );
+
+    let evicted = session.insert(FakeKeypair::new(3), true, clock.now());
+    assert_eq!(evicted_indices(&evicted), [1]);
+    assert!(session.find(1, clock.now()).is_none());
+    assert!(session.find(2, clock.now()).is_some());
+}
+
+#[test]
+fn session_previous_is_kept_briefly() {
+    let mut clock = FakeClock(Duration::from_secs(0));
+    let mut session = Session::new();
+    session.insert(FakeKeypair::new(1), true, clock.now());
+    clock.advance(Duration::from_secs(30));
+    session.insert(FakeKeypair::new(2), true, clock.now());
+
+    clock.advance(KEEP_PREVIOUS_TIME - Duration::from_millis(1));
+    assert_eq!(
+        session.find(1, clock.now()).map(|(_, s)| s),
+        Some(KeySlot::Previous),
+    );
+    clock.advance(Duration::from_millis(1));
+    assert!(session.find(1, clock.now()).is_none());
+    assert_eq!(
+        session.find(2, clock.now()).map(|(_, s)| s),
+        Some(KeySlot::Current),
+    );
+}
+
+#[test]
+fn session_responder_waits_for_confirmation() {
+    let mut clock = FakeClock(Duration::from_secs(0));
+    let mut session = Session::new();
+    session.insert(FakeKeypair::new(1), false, clock.now());
+    assert!(session.current(clock.now()).is_none());
+    assert_eq!(
+        session.find(1, clock.now()).map(|(_, s)| s),
+        Some(KeySlot::Next),
+    );
+
+    // Confirming an unknown index changes nothing.
+    assert!(evicted_indices(&session.confirm(7, clock.now())).is_empty());
+    assert!(session.current(clock.now()).is_none());
+
+    clock.advance(Duration::from_secs(1));
+    assert!(evicted_indices(&session.confirm(1, clock.now())).is_empty());
+    assert_eq!(session.current(clock.now()).unwrap().index, 1);
+    assert_eq!(
+        session.find(1, clock.now()).map(|(_, s)| s),
+        Some(KeySlot::Current),
+    );
+
+    // The responder does not rekey on time alone; the initiator does.
+    clock.advance(REKEY_AFTER_TIME);
+    assert!(!session.needs_rekey(clock.now()));
+
+    // A second responder handshake leaves the current keypair in use.
+    session.insert(FakeKeypair::new(2), false, clock.now());
+    assert_eq!(session.current(clock.now()).unwrap().index, 1);
+    let evicted = session.insert(FakeKeypair::new(3), false, clock.now());
+    assert_eq!(evicted_indices(&evicted), [2]);
+    session.confirm(3, clock.now());
+    assert_eq!(session.current(clock.now()).unwrap().index, 3);
+    assert_eq!(
+        session.find(1, clock.now()).map(|(_, s)| s),
+        Some(KeySlot::Previous),
+    );
+}
+
+#[test]
+fn session_rekeys_after_messages() {
+    let clock = FakeClock(Duration::from_secs(0));
+    for suite in CipherSuite::ALL {
+        let mut session = Session::new();
+        let mut keypair = FakeKeypair::new(1);
+        keypair.suite = suite;
+        session.insert(keypair, false, clock.now());
+        session.confirm(1, clock.now());
+        let keypair = session.current(clock.now()).unwrap();
+        let limit = rekey_after_messages(suite);
+        keypair.sent.set(limit - 1);
+        assert!(!session.needs_rekey(clock.now()));
+        keypair.sent.set(limit);
+        assert!(session.needs_rekey(clock.now()));
+    }
+
+    assert!(rekey_after_messages(CipherSuite::Aes128Gcm) <= 1 << 32);
+    assert!(rekey_after_messages(CipherSuite::Aes256Gcm) <= 1 << 32);
+}
+
+#[test]
+fn session_rejects_after_time() {
+    let mut clock = FakeClock(Duration::from_secs(0));
+    let mut session = Session::new();
+    session.insert(FakeKeypair::new(1), true, clock.now());
+    session.insert(FakeKeypair::new(2), false, clock.now());
+    clock.advance(REJECT_AFTER_TIME);
+    assert!(session.current(clock.now()).is_none());
+    assert!(session.find(1, clock.now()).is_none());
+    assert!(session.find(2, clock.now()).is_none());
+    assert!(session.needs_rekey(clock.now()));
+
+    let evicted = session.clear();
+    assert_eq!(evicted_indices(&evicted), [1, 2]);
+}
@@ -0,0 +1,288 @@
+// The outer header of every datagram exchanged between peers, and the sizes of the message bodies
+// that follow it. All integers are little-endian.
+//
+//   0       1       2       3       4                               8
+//   +-------+-------+-------+-------+-------------------------------+
+//   |version| type  | hops  | rsvd  |        receiver index         |
+//   +-------+-------+-------+-------+-------------------------------+
+//
+// The receiver index is the session index the recipient chose during the handshake. It is zero in
+// handshake initiations, which do not belong to a session yet.
+//
+// Hops counts the peers that relayed a data message so far, and is zero in every other message. A
+// message that took `HOP_LIMIT` hops is not relayed any further, so that relays looping frames
+// between them eventually drop them.
+
+use crate::provider::{NONCE_SIZE, TAG_SIZE, X25519_KEY_SIZE};
+
+pub const VERSION: u8 = 1;
+pub const HEADER_SIZE: usize = 8;
+
+// Enough for a spoke to reach another through a hub on either side
+pub const HOP_LIMIT: u8 = 2;
+
+pub const TIMESTAMP_SIZE: usize = 12;
+pub const COOKIE_SIZE: usize = 16;
+pub const MAC_SIZE: usize = 16;
+
+// nonce || ciphertext || tag; keepalives have no ciphertext.
+pub const DATA_OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+// sender index, cipher suite, hash, ephemeral key, encrypted static key, encrypted timestamp,
+// MAC1, MAC2
+pub const HANDSHAKE_INIT_SIZE: usize = 4
+    + 4
+    + 4
+    + X25519_KEY_SIZE
+    + X25519_KEY_SIZE
+    + TAG_SIZE
+    + TIMESTAMP_SIZE
+    + TAG_SIZE
+    + MAC_SIZE
+    + MAC_SIZE;
+
+// sender index, ephemeral key, two empty payloads, MAC1, MAC2
+pub const HANDSHAKE_RESPONSE_SIZE: usize =
+    4 + X25519_KEY_SIZE + TAG_SIZE + TAG_SIZE + MAC_SIZE + MAC_SIZE;
+
+// nonce, encrypted cookie
+pub const COOKIE_REPLY_SIZE: usize = NONCE_SIZE + COOKIE_SIZE + TAG_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageType {
+    Data = 1,
+    HandshakeInit = 2,
+    HandshakeResponse = 3,
+    Cookie = 4,
+    Keepalive = 5,
+}
+
+impl MessageType {
+    pub const ALL: [Self; 5] = [
+        Self::Data,
+        Self::HandshakeInit,
+        Self::HandshakeResponse,
+        Self::Cookie,
+        Self::Keepalive,
+    ];
+
+    pub fn from_raw(value: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|t| *t as u8 == value)
+    }
+
+    // The accepted body sizes, header excluded.
+    fn body_size(self) -> (usize, usize) {
+        match self {
+            Self::Data => (DATA_OVERHEAD, usize::MAX),
+            Self::HandshakeInit => (HANDSHAKE_INIT_SIZE, HANDSHAKE_INIT_SIZE),
+            Self::HandshakeResponse => (HANDSHAKE_RESPONSE_SIZE, HANDSHAKE_RESPONSE_SIZE),
+            Self::Cookie => (COOKIE_REPLY_SIZE, COOKIE_REPLY_SIZE),
+            Self::Keepalive => (DATA_OVERHEAD, DATA_OVERHEAD),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    Truncated,
+    UnsupportedVersion(u8),
+    UnknownType(u8),
+    Reserved,
+    InvalidLength(MessageType),
+    InvalidReceiver,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub message_type: MessageType,
+    pub hops: u8,
+    pub receiver_index: u32,
+}
+
+impl Header {
+    pub fn new(message_type: MessageType, receiver_index: u32) -> Self {
+        Self {
+            message_type,
+            hops: 0,
+            receiver_index,
+        }
+    }
+
+    // The header of a data message relayed on to the peer with `receiver_index`, one hop further
+    pub fn relayed(hops: u8, receiver_index: u32) -> Self {
+        Self {
+            message_type: MessageType::Data,
+            hops: hops + 1,
+            receiver_index,
+        }
+    }
+
+    // Whether a data message with this header may be relayed once more
+    pub fn may_relay(&self) -> bool {
+        self.message_type == MessageType::Data && self.hops < HOP_LIMIT
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0; HEADER_SIZE];
+        buf[0] = VERSION;
+        buf[1] = self.message_type as u8;
+        buf[2] = self.hops;
+        buf[4..].copy_from_slice(&self.receiver_index.to_le_bytes());
+        buf
+    }
+
+    // Validates the header and the body size of a whole datagram, and returns the body.
+    pub fn decode(datagram: &[u8]) -> Result<(Self, &[u8]), Error> {
+        if datagram.len() < HEADER_SIZE {
+            return Err(Error::Truncated);
+        }
+        let (header, body) = datagram.split_at(HEADER_SIZE);
+        if header[0] != VERSION {
+            return Err(Error::UnsupportedVersion(header[0]));
+        }
+        let message_type = MessageType::from_raw(header[1]).ok_or(Error::UnknownType(header[1]))?;
+        let hops = header[2];
+        if header[3] != 0 || (hops != 0 && message_type != MessageType::Data) {
+            return Err(Error::Reserved);
+        }
+        let mut receiver_index = [0; 4];
+        receiver_index.copy_from_slice(&header[4..]);
+        let receiver_index = u32::from_le_bytes(receiver_index);
+
+        let (min, max) = message_type.body_size();
+        if body.len() < min || body.len() > max {
+            return Err(Error::InvalidLength(message_type));
+        }
+        if message_type == MessageType::HandshakeInit && receiver_index != 0 {
+            return Err(Error::InvalidReceiver);
+        }
+        Ok((
+            Self {
+                message_type,
+                hops,
+                receiver_index,
+            },
+            body,
+        ))
+    }
+}
+
+#[cfg(test)]
+fn datagram(header: &Header, body_size: usize) -> Vec<u8> {
+    let mut datagram = header.encode().to_vec();
+    datagram.resize(HEADER_SIZE + body_size, 0xaa);
+    datagram
+}
+
+#[test]
+fn header_round_trip() {
+    let cases = [
+        (MessageType::Data, 0x12345678, DATA_OVERHEAD + 1400),
+        (MessageType::Data, 1, DATA_OVERHEAD),
+        (MessageType::HandshakeInit, 0, HANDSHAKE_INIT_SIZE),
+        (
+            MessageType::HandshakeResponse,
+            u32::MAX,
+            HANDSHAKE_RESPONSE_SIZE,
+        ),
+        (MessageType::Cookie, 7, COOKIE_REPLY_SIZE),
+        (MessageType::Keepalive, 8, DATA_OVERHEAD),
+    ];
+    for (message_type, receiver_index, body_size) in cases.iter() {
+        let header = Header::new(*message_type, *receiver_index);
+        let datagram = datagram(&header, *body_size);
+        let (decoded, body) = Header::decode(&datagram).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(body.len(), *body_size);
+        assert_eq!(body.as_ptr(), datagram[HEADER_SIZE..].as_ptr());
+    }
+}
+
+#[test]
+fn header_layout() {
+    let header = Header::new(MessageType::HandshakeResponse, 0x0403_0201);
+    assert_eq!(header.encode(), [VERSION, 3, 0, 0, 1, 2, 3, 4]);
+    let relayed = Header::relayed(0, 0x0403_0201);
+    assert_eq!(relayed.encode(), [VERSION, 1, 1, 0, 1, 2, 3, 4]);
+    assert_eq!(HANDSHAKE_INIT_SIZE, 152);
+    assert_eq!(HANDSHAKE_RESPONSE_SIZE, 100);
+}
+
+#[test]
+fn header_rejects_malformed() {
+    let data = Header::new(MessageType::Data, 1);
+
+    for len in 0..HEADER_SIZE {
+        let datagram = datagram(&data, DATA_OVERHEAD);
+        assert_eq!(Header::decode(&datagram[..len]), Err(Error::Truncated));
+    }
+
+    let mut datagram = datagram(&data, DATA_OVERHEAD);
+    for version in [0, 2, 0xff].iter() {
+        datagram[0] = *version;
+        assert_eq!(
+            Header::decode(&datagram),
+            Err(Error::UnsupportedVersion(*version)),
+        );
+    }
+    datagram[0] = VERSION;
+
+    for r#type in [0, 6, 0xff].iter() {
+        datagram[1] = *r#type;
+        assert_eq!(Header::decode(&datagram), Err(Error::UnknownType(*r#type)));
+    }
+    datagram[1] = MessageType::Data as u8;
+
+    datagram[3] = 1;
+    assert_eq!(Header::decode(&datagram), Err(Error::Reserved));
+    datagram[3] = 0;
+    assert!(Header::decode(&datagram).is_ok());
+
+    // Only data messages are relayed.
+    let mut keepalive = self::datagram(&Header::new(MessageType::Keepalive, 1), DATA_OVERHEAD);
+    keepalive[2] = 1;
+    assert_eq!(Header::decode(&keepalive), Err(Error::Reserved));
+
+    let init = Header::new(MessageType::HandshakeInit, 1);
+    assert_eq!(
+        Header::decode(&self::datagram(&init, HANDSHAKE_INIT_SIZE)),
+        Err(Error::InvalidReceiver),
+    );
+}
+
+#[test]
+fn header_hops() {
+    let mut header = Header::new(MessageType::Data, 9);
+    assert!(header.may_relay());
+    for hops in 1..=HOP_LIMIT {
+        header = Header::relayed(header.hops, header.receiver_index);
+        let datagram = datagram(&header, DATA_OVERHEAD);
+        let (decoded, _) = Header::decode(&datagram).unwrap();
+        assert_eq!(decoded.hops, hops);
+        assert_eq!(decoded.may_relay(), hops < HOP_LIMIT);
+    }
+    assert!(!Header::new(MessageType::Keepalive, 9).may_relay());
+}
+
+#[test]
+fn header_rejects_invalid_lengths() {
+    let cases = [
+        (MessageType::Data, DATA_OVERHEAD - 1),
+        (MessageType::Keepalive, DATA_OVERHEAD - 1),
+        (MessageType::Keepalive, DATA_OVERHEAD + 1),
+        (MessageType::HandshakeInit, HANDSHAKE_INIT_SIZE - 1),
+        (MessageType::HandshakeInit, HANDSHAKE_INIT_SIZE + 1),
+        (MessageType::HandshakeResponse, HANDSHAKE_RESPONSE_SIZE - 1),
+        (MessageType::HandshakeResponse, HANDSHAKE_RESPONSE_SIZE + 1),
+        (MessageType::Cookie, COOKIE_REPLY_SIZE - 1),
+        (MessageType::Cookie, 0),
+    ];
+    for (message_type, body_size) in cases.iter() {
+        let header = Header::new(*message_type, 0);
+        assert_eq!(
+            Header::decode(&datagram(&header, *body_size)),
+            Err(Error::InvalidLength(*message_type)),
+        );
+    }
+}